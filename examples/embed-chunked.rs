@@ -1,8 +1,10 @@
-use std::time::Duration;
-
 use colored::Colorize;
 use ollama_rust::{
-    generation::{embed::request::EmbedRequest, parameters::KeepAlive},
+    generation::{
+        embed::request::EmbedRequest,
+        metrics::{Metrics, MetricsSource},
+        parameters::KeepAlive,
+    },
     ollama::Ollama,
 };
 use tokio_stream::StreamExt;
@@ -35,11 +37,12 @@ async fn main() -> anyhow::Result<()> {
     )?;
 
     let mut embeddings = vec![];
-    let mut gen_sum = Duration::ZERO;
+    let mut metrics_sum = Metrics::default();
 
     while let Some(item) = stream.next().await {
         let item = item?;
         println!("Received chunk with {} embeddings", item.embeddings.len());
+        let metrics = item.metrics();
         embeddings.extend(item.embeddings);
 
         println!(
@@ -48,15 +51,11 @@ async fn main() -> anyhow::Result<()> {
                 .blue()
         );
         println!("Embeddings: {}", embeddings.len());
-        let total = Duration::from_nanos(item.total_duration.unwrap_or_default());
-        let load = Duration::from_nanos(item.load_duration.unwrap_or_default());
-        let generate = total.saturating_sub(load);
-        gen_sum += generate;
-        println!("Generation Duration: {generate:?}");
-        println!(
-            "Input tokens processed: {:?}",
-            item.prompt_eval_count.unwrap_or_default()
-        );
+        if let Some(metrics) = metrics {
+            metrics_sum += metrics;
+            println!("Generation Duration: {:?}", metrics.generation_duration());
+            println!("Input tokens processed: {}", metrics.prompt_eval_count);
+        }
     }
 
     println!(
@@ -64,7 +63,16 @@ async fn main() -> anyhow::Result<()> {
         "================================================================================".blue()
     );
     println!("Total embeddings completed: {}", embeddings.len());
-    println!("Total generation duration: {gen_sum:?}");
+    println!(
+        "Total generation duration: {:?}",
+        metrics_sum.generation_duration()
+    );
+    println!(
+        "Throughput: {:.1} tokens/s",
+        metrics_sum
+            .generation_tokens_per_second()
+            .unwrap_or_default()
+    );
 
     Ok(())
 }
@@ -1,7 +1,5 @@
-use std::time::Duration;
-
 use ollama_rust::{
-    generation::{embed::request::EmbedRequest, parameters::KeepAlive},
+    generation::{embed::request::EmbedRequest, metrics::MetricsSource, parameters::KeepAlive},
     ollama::Ollama,
 };
 
@@ -37,14 +35,14 @@ async fn main() -> anyhow::Result<()> {
         "Dimensions: {}",
         res.embeddings.first().cloned().unwrap_or_default().len()
     );
-    let total = Duration::from_nanos(res.total_duration.unwrap_or_default());
-    let load = Duration::from_nanos(res.load_duration.unwrap_or_default());
-    let generate = total.saturating_sub(load);
-    println!("Generation Duration: {generate:?}");
-    println!(
-        "Input tokens processed: {:?}",
-        res.prompt_eval_count.unwrap_or_default()
-    );
+    if let Some(metrics) = res.metrics() {
+        println!("Generation Duration: {:?}", metrics.generation_duration());
+        println!("Input tokens processed: {}", metrics.prompt_eval_count);
+        println!(
+            "Throughput: {:.1} tokens/s",
+            metrics.generation_tokens_per_second().unwrap_or_default()
+        );
+    }
 
     Ok(())
 }
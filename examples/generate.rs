@@ -24,10 +24,8 @@ async fn main() -> anyhow::Result<()> {
     while let Some(a) = stream.next().await {
         match a {
             Ok(res) => {
-                for res in res {
-                    stdout.write_all(res.response.as_bytes()).await?;
-                    stdout.flush().await?;
-                }
+                stdout.write_all(res.response.as_bytes()).await?;
+                stdout.flush().await?;
             }
             Err(e) => println!(">> Error: {e}"),
         }
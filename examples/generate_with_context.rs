@@ -35,22 +35,17 @@ async fn main() -> anyhow::Result<()> {
         while let Some(a) = stream.next().await {
             match a {
                 Ok(res) => {
-                    for res in res {
-                        stdout.write_all(res.response.as_bytes()).await?;
-                        stdout.flush().await?;
-
-                        if let Some(new_context) = res.context {
-                            context = new_context;
-                            stdout
-                                .write_all(
-                                    format!(
-                                        "\n\n>> Done. Updated context length: {}",
-                                        context.len()
-                                    )
+                    stdout.write_all(res.response.as_bytes()).await?;
+                    stdout.flush().await?;
+
+                    if let Some(new_context) = res.context {
+                        context = new_context;
+                        stdout
+                            .write_all(
+                                format!("\n\n>> Done. Updated context length: {}", context.len())
                                     .as_bytes(),
-                                )
-                                .await?;
-                        }
+                            )
+                            .await?;
                     }
                 }
                 Err(e) => println!(">> Error: {e}"),
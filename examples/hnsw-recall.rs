@@ -0,0 +1,95 @@
+//! Recall and speed of the HNSW index compared to exact search.
+//!
+//! Usage: `cargo run --release --example hnsw-recall -- [documents] [dimensions]`
+
+use std::{collections::HashSet, time::Instant};
+
+use ollama_rust::{
+    ollama::Ollama,
+    store::{HnswConfig, VectorStore},
+};
+
+const QUERIES: usize = 200;
+const K: usize = 10;
+
+/// Vectors around a few hundred random centers, similar to embeddings of related texts.
+fn random_vectors(rng: &mut fastrand::Rng, count: usize, dimensions: usize) -> Vec<Vec<f32>> {
+    let centers = (0..256)
+        .map(|_| (0..dimensions).map(|_| rng.f32() - 0.5).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+
+    (0..count)
+        .map(|_| {
+            let center = &centers[rng.usize(..centers.len())];
+            center.iter().map(|x| x + (rng.f32() - 0.5) * 0.6).collect()
+        })
+        .collect()
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let count = args.next().map_or(Ok(20_000), |a| a.parse())?;
+    let dimensions = args.next().map_or(Ok(256), |a| a.parse())?;
+
+    let mut rng = fastrand::Rng::with_seed(42);
+    let vectors = random_vectors(&mut rng, count, dimensions);
+    let queries = random_vectors(&mut rng, QUERIES, dimensions);
+
+    let mut exact = VectorStore::new(Ollama::default(), "none");
+    let mut approximate = VectorStore::new(Ollama::default(), "none").hnsw(HnswConfig::default());
+
+    for (i, vector) in vectors.iter().enumerate() {
+        exact.insert_embedded(i.to_string(), serde_json::Value::Null, vector)?;
+    }
+
+    let started = Instant::now();
+    for (i, vector) in vectors.iter().enumerate() {
+        approximate.insert_embedded(i.to_string(), serde_json::Value::Null, vector)?;
+    }
+    println!(
+        "{count} x {dimensions}d, HNSW build: {:?}",
+        started.elapsed()
+    );
+
+    let started = Instant::now();
+    let truth = queries
+        .iter()
+        .map(|q| {
+            exact
+                .search_by_embedding(q, K, None)
+                .iter()
+                .map(|hit| hit.document.id)
+                .collect::<HashSet<_>>()
+        })
+        .collect::<Vec<_>>();
+    println!(
+        "Exact:        {:?}/query",
+        started.elapsed() / QUERIES as u32
+    );
+
+    for ef in [16, 32, 64, 128, 256] {
+        approximate.set_ef_search(ef);
+
+        let started = Instant::now();
+        let found = queries
+            .iter()
+            .zip(&truth)
+            .map(|(q, truth)| {
+                approximate
+                    .search_by_embedding(q, K, None)
+                    .iter()
+                    .filter(|hit| truth.contains(&hit.document.id))
+                    .count()
+            })
+            .sum::<usize>();
+        let elapsed = started.elapsed() / QUERIES as u32;
+
+        println!(
+            "HNSW ef {ef:>3}:  {elapsed:?}/query, recall@{K} {:.3}",
+            found as f64 / (QUERIES * K) as f64
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,82 @@
+use ollama_rust::{
+    generation::embed::concurrent::ConcurrentEmbedOptions,
+    ollama::Ollama,
+    store::{Bm25Config, Filter, HybridOptions, VectorStore},
+};
+
+use crate::common::Airport;
+
+pub mod common;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let path = std::env::temp_dir().join("airports.olvs");
+
+    let store = if path.exists() {
+        VectorStore::load(Ollama::default(), &path).await?
+    } else {
+        let mut store = VectorStore::new(Ollama::default(), common::QWEN3_EMBED_4B_2560D)
+            .dimensions(768)
+            .bm25(Bm25Config::default())
+            .embed_options(ConcurrentEmbedOptions::default().chunk_size(100));
+
+        let documents = Airport::load()?
+            .into_values()
+            .take(1000)
+            .map(|airport| {
+                let metadata = serde_json::json!({
+                    "icao": airport.icao,
+                    "country": airport.country,
+                    "elevation": airport.elevation,
+                });
+                (
+                    format!(
+                        "{} ({}) in {}, {}",
+                        airport.name, airport.icao, airport.city, airport.country
+                    ),
+                    metadata,
+                )
+            })
+            .collect();
+
+        store.add_documents(documents).await?;
+        store.save(&path).await?;
+        store
+    };
+
+    println!("Documents: {}", store.len());
+
+    let query = "Airport in the mountains";
+    for hit in store.search(query, 5).await? {
+        println!("{:.3} {}", hit.score, hit.document.text);
+    }
+
+    println!();
+    println!("High elevation only:");
+    let filter = Filter::gte("elevation", 5000.0);
+    for hit in store.search_with_filter(query, 5, &filter).await? {
+        println!(
+            "{:.3} {} ({} ft)",
+            hit.score, hit.document.text, hit.document.metadata["elevation"]
+        );
+    }
+
+    // Embeddings are fuzzy on identifiers, BM25 matches them exactly
+    let icao = store
+        .documents()
+        .find_map(|d| d.metadata["icao"].as_str().map(str::to_string))
+        .unwrap_or_default();
+    let query = icao.as_str();
+    let options = HybridOptions::default();
+
+    println!();
+    println!("Semantic vs hybrid for {query}:");
+    for hit in store.search(query, 3).await? {
+        println!("{:.3} {}", hit.score, hit.document.text);
+    }
+    for hit in store.hybrid_search(query, 3, None, &options).await? {
+        println!("{:.3} {}", hit.score, hit.document.text);
+    }
+
+    Ok(())
+}
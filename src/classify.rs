@@ -0,0 +1,593 @@
+use futures_util::stream::{self, StreamExt, TryStreamExt};
+
+use crate::{
+    OllamaError,
+    generation::{
+        chat::{history::History, message::Message, request::ChatRequest},
+        embed::{
+            concurrent::ConcurrentEmbedOptions,
+            request::EmbedRequest,
+            vector::{dot, normalize, normalized},
+        },
+        generate::response::Logprob,
+        parameters::Think,
+    },
+    model::ModelOptions,
+    ollama::Ollama,
+};
+
+/// A class to assign, optionally with a description and example inputs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    pub name: String,
+    pub description: Option<String>,
+    pub examples: Vec<String>,
+}
+
+impl Label {
+    pub fn new<S: Into<String>>(name: S) -> Self {
+        Self {
+            name: name.into(),
+            description: None,
+            examples: Vec::new(),
+        }
+    }
+
+    #[must_use]
+    pub fn description<S: Into<String>>(mut self, description: S) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    /// Inputs that belong to this label, for few-shot classification.
+    #[must_use]
+    pub fn examples(mut self, examples: Vec<String>) -> Self {
+        self.examples = examples;
+        self
+    }
+
+    fn text(&self) -> String {
+        match &self.description {
+            Some(description) => format!("{}: {description}", self.name),
+            None => self.name.clone(),
+        }
+    }
+}
+
+/// The label assigned to one input.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Classification {
+    pub label: String,
+
+    /// Confidence in `label`, from 0 to 1
+    pub confidence: f32,
+
+    /// Every label with its confidence, best first. Confidences sum to 1.
+    pub confidences: Vec<(String, f32)>,
+}
+
+impl Classification {
+    /// `scores` are the confidences of `labels`, in the same order.
+    fn new(labels: &[Label], scores: &[f32], label: Option<usize>) -> Self {
+        let best = label.unwrap_or_else(|| {
+            (0..scores.len())
+                .max_by(|&a, &b| scores[a].total_cmp(&scores[b]))
+                .unwrap_or(0)
+        });
+
+        let mut confidences = labels
+            .iter()
+            .zip(scores)
+            .map(|(label, score)| (label.name.clone(), *score))
+            .collect::<Vec<_>>();
+        confidences.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        Self {
+            label: labels[best].name.clone(),
+            confidence: scores[best],
+            confidences,
+        }
+    }
+}
+
+/// Nearest centroid classification with embeddings.
+///
+/// Every label's centroid is the mean embedding of its name and description and its
+/// examples. Confidences are a softmax over the cosine similarities to the centroids.
+#[derive(Debug, Clone)]
+pub struct EmbeddingClassifier {
+    ollama: Ollama,
+    model: String,
+    labels: Vec<Label>,
+    centroids: Vec<Vec<f32>>,
+    temperature: f32,
+    embed_options: ConcurrentEmbedOptions,
+}
+
+impl EmbeddingClassifier {
+    /// Embed `labels` with `model`. A softmax temperature of 0.05 is used by default.
+    ///
+    /// # Errors
+    ///
+    /// If `labels` is empty.
+    /// If Ollama does not return one embedding per label text and example.
+    /// Same as `Ollama::generate_embeddings`.
+    pub async fn new<S: Into<String>>(
+        ollama: Ollama,
+        model: S,
+        labels: Vec<Label>,
+    ) -> crate::Result<Self> {
+        if labels.is_empty() {
+            return Err(OllamaError::Other("No labels to classify with".to_string()));
+        }
+
+        let model = model.into();
+        let mut owners = Vec::new();
+        let mut texts = Vec::new();
+        for (index, label) in labels.iter().enumerate() {
+            for text in std::iter::once(label.text()).chain(label.examples.iter().cloned()) {
+                owners.push(index);
+                texts.push(text);
+            }
+        }
+
+        let response = ollama
+            .generate_embeddings(EmbedRequest::new(model.clone(), texts))
+            .await?;
+
+        let centroids = centroids(&response.embeddings, &owners, labels.len())?;
+
+        Ok(Self {
+            ollama,
+            model,
+            labels,
+            centroids,
+            temperature: 0.05,
+            embed_options: ConcurrentEmbedOptions::default(),
+        })
+    }
+
+    /// Softmax temperature. Lower makes confidences more decisive.
+    #[must_use]
+    pub fn temperature(mut self, temperature: f32) -> Self {
+        self.temperature = temperature.max(f32::EPSILON);
+        self
+    }
+
+    /// Batch size and concurrency used by `classify_batch`.
+    #[must_use]
+    pub fn embed_options(mut self, options: ConcurrentEmbedOptions) -> Self {
+        self.embed_options = options;
+        self
+    }
+
+    #[must_use]
+    pub fn labels(&self) -> &[Label] {
+        &self.labels
+    }
+
+    /// Classify an input that was embedded with the classifier's model.
+    #[must_use]
+    pub fn classify_embedding(&self, embedding: &[f32]) -> Classification {
+        let embedding = normalized(embedding);
+        let similarities = self
+            .centroids
+            .iter()
+            .map(|centroid| dot(centroid, &embedding) / self.temperature)
+            .collect::<Vec<_>>();
+
+        Classification::new(&self.labels, &softmax(&similarities), None)
+    }
+
+    /// # Errors
+    ///
+    /// Same as `Ollama::generate_embeddings`.
+    pub async fn classify(&self, text: &str) -> crate::Result<Classification> {
+        let response = self
+            .ollama
+            .generate_embeddings(EmbedRequest::new(self.model.as_str(), text))
+            .await?;
+
+        let embedding = response
+            .embeddings
+            .first()
+            .ok_or_else(|| OllamaError::Other("Ollama returned no embedding".to_string()))?;
+
+        Ok(self.classify_embedding(embedding))
+    }
+
+    /// Classify `texts` in input order, embedded in concurrent batches per `embed_options`.
+    ///
+    /// # Errors
+    ///
+    /// `OllamaError::EmbedChunkFailed` if a batch failed after all retries.
+    /// If Ollama returns a different number of embeddings than a batch has texts.
+    pub async fn classify_batch<S: AsRef<str>>(
+        &self,
+        texts: &[S],
+    ) -> crate::Result<Vec<Classification>> {
+        if texts.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let count = texts.len();
+        let chunk_size = self.embed_options.chunk_size.max(1);
+        let texts = texts
+            .iter()
+            .map(|t| t.as_ref().to_string())
+            .collect::<Vec<_>>();
+        let mut chunks = self.ollama.generate_embeddings_concurrent(
+            EmbedRequest::new(self.model.clone(), texts),
+            self.embed_options.clone(),
+        )?;
+
+        let mut classifications = Vec::with_capacity(count);
+        while let Some(chunk) = chunks.next().await {
+            let chunk = chunk?;
+
+            // Otherwise every following classification would belong to another text
+            let expected = chunk_size.min(count.saturating_sub(chunk.offset));
+            let embeddings = &chunk.response.embeddings;
+            if embeddings.len() != expected {
+                return Err(OllamaError::Other(format!(
+                    "Ollama returned {} embeddings for {expected} texts",
+                    embeddings.len()
+                )));
+            }
+
+            for embedding in embeddings {
+                classifications.push(self.classify_embedding(embedding));
+            }
+        }
+
+        Ok(classifications)
+    }
+}
+
+/// Classification with a chat model, constrained to the labels by a JSON Schema enum.
+///
+/// Label examples are sent as few-shot conversation turns. Confidences come from the
+/// log probabilities of the first token that tells the labels apart. If Ollama returns no
+/// log probabilities, the answered label gets a confidence of 1.
+#[derive(Debug, Clone)]
+pub struct ChatClassifier {
+    ollama: Ollama,
+    model: String,
+    labels: Vec<Label>,
+    instructions: String,
+    concurrency: usize,
+}
+
+impl ChatClassifier {
+    /// 4 requests in flight by default for `classify_batch`.
+    pub fn new<S: Into<String>>(ollama: Ollama, model: S, labels: Vec<Label>) -> Self {
+        Self {
+            ollama,
+            model: model.into(),
+            labels,
+            instructions: "Classify the text with exactly one of the labels.".to_string(),
+            concurrency: 4,
+        }
+    }
+
+    /// Text before the list of labels in the system message.
+    #[must_use]
+    pub fn instructions<S: Into<String>>(mut self, instructions: S) -> Self {
+        self.instructions = instructions.into();
+        self
+    }
+
+    /// Requests in flight at the same time in `classify_batch`.
+    #[must_use]
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency;
+        self
+    }
+
+    #[must_use]
+    pub fn labels(&self) -> &[Label] {
+        &self.labels
+    }
+
+    fn request(&self, text: &str) -> ChatRequest {
+        let labels = self
+            .labels
+            .iter()
+            .map(|label| format!("- {}", label.text()))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let mut messages = vec![Message::system(format!(
+            "{}\n\nLabels:\n{labels}",
+            self.instructions
+        ))];
+
+        // Alternate between labels, so no label dominates the end of the conversation
+        let longest = self.labels.iter().map(|l| l.examples.len()).max();
+        for i in 0..longest.unwrap_or(0) {
+            for label in &self.labels {
+                if let Some(example) = label.examples.get(i) {
+                    messages.push(Message::user(example.as_str()));
+                    messages.push(Message::assistant(
+                        serde_json::Value::from(label.name.as_str()).to_string(),
+                    ));
+                }
+            }
+        }
+        messages.push(Message::user(text));
+
+        let names = self
+            .labels
+            .iter()
+            .map(|l| l.name.as_str())
+            .collect::<Vec<_>>();
+        ChatRequest::new(self.model.clone(), messages)
+            .stream(false)
+            .format(serde_json::json!({ "type": "string", "enum": names }))
+            .think(Think::Disabled)
+            .options(ModelOptions::default().temperature(0.0))
+            .logprobs(true)
+            .top_logprobs(20)
+    }
+
+    /// # Errors
+    ///
+    /// If `labels` is empty.
+    /// If the model answers with something other than a label.
+    /// Same as `Ollama::chat`.
+    pub async fn classify(&self, text: &str) -> crate::Result<Classification> {
+        if self.labels.is_empty() {
+            return Err(OllamaError::Other("No labels to classify with".to_string()));
+        }
+
+        let mut stream = self.ollama.chat(self.request(text), History::default())?;
+        let mut content = String::new();
+        let mut logprobs = Vec::new();
+        while let Some(response) = stream.next().await {
+            let response = response?;
+            content.push_str(&response.message.content);
+            logprobs.extend(response.logprobs.unwrap_or_default());
+        }
+
+        let answer = serde_json::from_str::<String>(content.trim())
+            .unwrap_or_else(|_| content.trim().to_string());
+        let index = self
+            .labels
+            .iter()
+            .position(|l| l.name == answer)
+            .ok_or_else(|| {
+                OllamaError::Other(format!("Model answered with unknown label {answer}"))
+            })?;
+
+        let scores = label_probabilities(&self.labels, &logprobs).unwrap_or_else(|| {
+            let mut scores = vec![0.0; self.labels.len()];
+            scores[index] = 1.0;
+            scores
+        });
+
+        Ok(Classification::new(&self.labels, &scores, Some(index)))
+    }
+
+    /// Classify `texts` in input order, with up to `concurrency` requests in flight.
+    ///
+    /// # Errors
+    ///
+    /// Same as `classify`, for the first input that fails.
+    pub async fn classify_batch<S: AsRef<str>>(
+        &self,
+        texts: &[S],
+    ) -> crate::Result<Vec<Classification>> {
+        stream::iter(texts)
+            .map(|text| self.classify(text.as_ref()))
+            .buffered(self.concurrency.max(1))
+            .try_collect()
+            .await
+    }
+}
+
+/// Normalized mean embedding of every label, `owners` tells the label of each embedding.
+fn centroids(
+    embeddings: &[Vec<f32>],
+    owners: &[usize],
+    labels: usize,
+) -> crate::Result<Vec<Vec<f32>>> {
+    // Otherwise embeddings would be added to the wrong labels, or none to some
+    if embeddings.len() != owners.len() {
+        return Err(OllamaError::Other(format!(
+            "Ollama returned {} embeddings for {} texts",
+            embeddings.len(),
+            owners.len()
+        )));
+    }
+
+    let dimensions = embeddings.first().map_or(0, Vec::len);
+    let mut centroids = vec![vec![0.0; dimensions]; labels];
+    for (embedding, &owner) in embeddings.iter().zip(owners) {
+        for (c, x) in centroids[owner].iter_mut().zip(normalized(embedding)) {
+            *c += x;
+        }
+    }
+    for centroid in &mut centroids {
+        normalize(centroid);
+    }
+
+    Ok(centroids)
+}
+
+/// Probabilities of the labels at the first token where the answer narrows them down.
+/// `None` if there is no such token, e.g. without log probabilities.
+fn label_probabilities(labels: &[Label], logprobs: &[Logprob]) -> Option<Vec<f32>> {
+    // The model answers with a JSON string
+    let answers = labels
+        .iter()
+        .map(|l| serde_json::Value::from(l.name.as_str()).to_string())
+        .collect::<Vec<_>>();
+    let compatible = |text: &str| {
+        let text = text.trim_start();
+        (0..answers.len())
+            .filter(|&i| answers[i].starts_with(text) || text.starts_with(answers[i].as_str()))
+            .collect::<Vec<_>>()
+    };
+
+    let mut prefix = String::new();
+    for position in logprobs {
+        let chosen = format!("{prefix}{}", position.token.token);
+        if compatible(&chosen).len() == answers.len() {
+            prefix = chosen;
+            continue;
+        }
+
+        let candidates = if position.top_logprobs.is_empty() {
+            std::slice::from_ref(&position.token)
+        } else {
+            position.top_logprobs.as_slice()
+        };
+
+        let mut scores = vec![0.0_f64; answers.len()];
+        for candidate in candidates {
+            let matches = compatible(&format!("{prefix}{}", candidate.token));
+            // Split between labels that share the candidate as a prefix
+            #[allow(clippy::cast_precision_loss)]
+            let share = candidate.logprob.exp() / matches.len().max(1) as f64;
+            for i in matches {
+                scores[i] += share;
+            }
+        }
+
+        let total = scores.iter().sum::<f64>();
+        if total <= 0.0 {
+            return None;
+        }
+
+        // Probabilities, the precision of f32 is plenty
+        #[allow(clippy::cast_possible_truncation)]
+        return Some(scores.iter().map(|s| (s / total) as f32).collect());
+    }
+
+    None
+}
+
+fn softmax(values: &[f32]) -> Vec<f32> {
+    let max = values.iter().copied().fold(f32::MIN, f32::max);
+    let exps = values.iter().map(|v| (v - max).exp()).collect::<Vec<_>>();
+    let sum = exps.iter().sum::<f32>();
+
+    exps.into_iter().map(|e| e / sum).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generation::generate::response::TokenLogprob;
+
+    fn token(token: &str, probability: f64) -> TokenLogprob {
+        TokenLogprob {
+            token: token.to_string(),
+            logprob: probability.ln(),
+            bytes: token.as_bytes().to_vec(),
+        }
+    }
+
+    /// `chosen` was sampled, `top` are the alternatives with their probabilities.
+    fn position(chosen: &str, top: &[(&str, f64)]) -> Logprob {
+        Logprob {
+            token: token(chosen, top.first().map_or(1.0, |(_, p)| *p)),
+            top_logprobs: top.iter().map(|(t, p)| token(t, *p)).collect(),
+        }
+    }
+
+    fn labels(names: &[&str]) -> Vec<Label> {
+        names.iter().map(|name| Label::new(*name)).collect()
+    }
+
+    fn assert_close(actual: &[f32], expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-5, "{actual:?} != {expected:?}");
+        }
+    }
+
+    #[test]
+    fn shared_token_is_split_between_labels() {
+        let labels = labels(&["positive", "negative", "neutral"]);
+        let logprobs = [
+            position("\"", &[("\"", 1.0)]),
+            position("ne", &[("ne", 0.5), ("pos", 0.4), ("maybe", 0.1)]),
+            position("gative", &[("gative", 1.0)]),
+            position("\"", &[("\"", 1.0)]),
+        ];
+
+        let probabilities = label_probabilities(&labels, &logprobs).unwrap();
+
+        assert_close(&probabilities, &[0.4 / 0.9, 0.25 / 0.9, 0.25 / 0.9]);
+    }
+
+    #[test]
+    fn label_that_is_a_prefix_of_another() {
+        let labels = labels(&["neg", "negative"]);
+        let logprobs = [
+            position(" \"", &[(" \"", 1.0)]),
+            position("neg", &[("neg", 1.0)]),
+            position("\"", &[("\"", 0.7), ("ative", 0.2), ("lect", 0.1)]),
+        ];
+
+        let probabilities = label_probabilities(&labels, &logprobs).unwrap();
+
+        assert_close(&probabilities, &[0.7 / 0.9, 0.2 / 0.9]);
+    }
+
+    #[test]
+    fn chosen_token_without_alternatives() {
+        let labels = labels(&["yes", "no"]);
+        let logprobs = [
+            position("\"", &[]),
+            position("no", &[]),
+            position("\"", &[]),
+        ];
+
+        let probabilities = label_probabilities(&labels, &logprobs).unwrap();
+
+        assert_close(&probabilities, &[0.0, 1.0]);
+    }
+
+    #[test]
+    fn no_usable_probabilities() {
+        let labels = labels(&["yes", "no"]);
+
+        assert_eq!(label_probabilities(&labels, &[]), None);
+
+        // The answer never narrows the labels down
+        let logprobs = [position("\"", &[("\"", 1.0)])];
+        assert_eq!(label_probabilities(&labels, &logprobs), None);
+
+        // No alternative matches any label
+        let logprobs = [position("\"", &[]), position("maybe", &[("maybe", 1.0)])];
+        assert_eq!(label_probabilities(&labels, &logprobs), None);
+    }
+
+    #[test]
+    fn softmax_sums_to_one() {
+        let probabilities = softmax(&[1.0, 2.0, 3.0]);
+
+        assert_close(&[probabilities.iter().sum()], &[1.0]);
+        assert!(probabilities[2] > probabilities[1] && probabilities[1] > probabilities[0]);
+    }
+
+    #[test]
+    fn centroids_are_normalized_means() {
+        let embeddings = [vec![2.0, 0.0], vec![0.0, 3.0], vec![0.0, -1.0]];
+
+        let centroids = centroids(&embeddings, &[0, 0, 1], 2).unwrap();
+
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        assert_close(&centroids[0], &[half, half]);
+        assert_close(&centroids[1], &[0.0, -1.0]);
+    }
+
+    #[test]
+    fn missing_embeddings_are_rejected() {
+        let embeddings = [vec![1.0, 0.0], vec![0.0, 1.0]];
+
+        assert!(centroids(&embeddings, &[0, 0, 1], 2).is_err());
+        assert!(centroids(&embeddings, &[0], 1).is_err());
+    }
+}
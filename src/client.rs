@@ -0,0 +1,479 @@
+use std::time::Duration;
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use reqwest::{
+    Client, Url,
+    header::{AUTHORIZATION, HeaderMap, HeaderName, HeaderValue},
+};
+
+use crate::{
+    OllamaError, limiter::ConcurrencyLimiter, llama::Llama, ollama::Ollama, retry::RetryPolicy,
+};
+
+/// Environment variable with the Ollama host, in any form the Ollama CLI accepts.
+pub const OLLAMA_HOST_ENV: &str = "OLLAMA_HOST";
+
+/// Environment variable with the llama-server host, in the same forms as `OLLAMA_HOST`.
+pub const LLAMA_HOST_ENV: &str = "LLAMA_HOST";
+
+/// Environment variable with an API key, sent as bearer token.
+pub const OLLAMA_API_KEY_ENV: &str = "OLLAMA_API_KEY";
+
+const OLLAMA_DEFAULT_PORT: u16 = 11434;
+const LLAMA_DEFAULT_PORT: u16 = 8012;
+
+/// Authentication sent with every request, e.g. for a reverse proxy in front of Ollama.
+#[derive(Clone)]
+pub enum Auth {
+    /// `Authorization: Bearer <token>`
+    Bearer(String),
+
+    /// `Authorization: Basic <base64(username:password)>`
+    Basic {
+        username: String,
+        password: Option<String>,
+    },
+}
+
+impl std::fmt::Debug for Auth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Auth::Bearer(_) => f.write_str("Bearer(<redacted>)"),
+            Auth::Basic { username, .. } => f
+                .debug_struct("Basic")
+                .field("username", username)
+                .field("password", &"<redacted>")
+                .finish(),
+        }
+    }
+}
+
+impl Auth {
+    fn header_value(&self) -> crate::Result<HeaderValue> {
+        let value = match self {
+            Auth::Bearer(token) => format!("Bearer {token}"),
+            Auth::Basic { username, password } => {
+                let credentials = format!("{username}:{}", password.as_deref().unwrap_or_default());
+                format!("Basic {}", STANDARD.encode(credentials))
+            }
+        };
+
+        let mut value = HeaderValue::from_str(&value)
+            .map_err(|e| OllamaError::Other(format!("Invalid authorization header: {e}")))?;
+        value.set_sensitive(true);
+
+        Ok(value)
+    }
+}
+
+/// Shared configuration for `Ollama` and `Llama` clients.
+///
+/// ```no_run
+/// # fn main() -> ollama_rust::Result<()> {
+/// use std::time::Duration;
+/// use ollama_rust::client::ClientBuilder;
+///
+/// let ollama = ClientBuilder::from_env()
+///     .bearer_auth("secret")
+///     .connect_timeout(Duration::from_secs(5))
+///     .build_ollama()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ClientBuilder {
+    url: Option<String>,
+    ollama_host: Option<String>,
+    llama_host: Option<String>,
+    headers: Vec<(String, String)>,
+    auth: Option<Auth>,
+    connect_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    pool_idle_timeout: Option<Duration>,
+    user_agent: Option<String>,
+    retry: Option<RetryPolicy>,
+    limiter: Option<ConcurrencyLimiter>,
+}
+
+impl ClientBuilder {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a builder from the environment.
+    ///
+    /// * `OLLAMA_HOST` sets the base URL of `build_ollama`, see `parse_ollama_host` for
+    ///   accepted forms.
+    /// * `LLAMA_HOST` sets the base URL of `build_llama`, in the same forms.
+    /// * `OLLAMA_API_KEY` sets bearer authentication.
+    ///
+    /// A URL set with `url` takes precedence over both.
+    #[must_use]
+    pub fn from_env() -> Self {
+        let mut builder = Self {
+            ollama_host: Self::env_var(OLLAMA_HOST_ENV),
+            llama_host: Self::env_var(LLAMA_HOST_ENV),
+            ..Self::default()
+        };
+
+        if let Some(key) = Self::env_var(OLLAMA_API_KEY_ENV) {
+            builder.auth = Some(Auth::Bearer(key));
+        }
+
+        builder
+    }
+
+    fn env_var(name: &str) -> Option<String> {
+        let value = std::env::var(name).ok()?;
+        let value = value.trim().trim_matches(['"', '\'']).trim();
+
+        if value.is_empty() {
+            None
+        } else {
+            Some(value.to_string())
+        }
+    }
+
+    /// Set the base URL. Bare `host:port` forms as accepted by `OLLAMA_HOST` are allowed,
+    /// a missing port defaults to the port of the built client.
+    #[must_use]
+    pub fn url<S: Into<String>>(mut self, url: S) -> Self {
+        self.url = Some(url.into());
+        self
+    }
+
+    /// Add a header that is sent with every request.
+    #[must_use]
+    pub fn header<K: Into<String>, V: Into<String>>(mut self, name: K, value: V) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Authenticate every request with `Authorization: Bearer <token>`.
+    #[must_use]
+    pub fn bearer_auth<S: Into<String>>(mut self, token: S) -> Self {
+        self.auth = Some(Auth::Bearer(token.into()));
+        self
+    }
+
+    /// Authenticate every request with HTTP basic authentication.
+    #[must_use]
+    pub fn basic_auth<U: Into<String>, P: Into<String>>(
+        mut self,
+        username: U,
+        password: Option<P>,
+    ) -> Self {
+        self.auth = Some(Auth::Basic {
+            username: username.into(),
+            password: password.map(Into::into),
+        });
+        self
+    }
+
+    /// Timeout for establishing a connection.
+    #[must_use]
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Timeout for a whole request, from sending it until the response body is read.
+    ///
+    /// # Note
+    /// This includes the time spent streaming, so long generations will be cut off.
+    #[must_use]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// How long idle connections are kept in the pool.
+    #[must_use]
+    pub fn pool_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.pool_idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Value of the `User-Agent` header.
+    #[must_use]
+    pub fn user_agent<S: Into<String>>(mut self, user_agent: S) -> Self {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// Retry requests that fail for transient reasons according to `policy`.
+    #[must_use]
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
+    }
+
+    /// Limit the number of concurrent requests. Clients built from this builder share the limiter.
+    #[must_use]
+    pub fn limiter(mut self, limiter: ConcurrencyLimiter) -> Self {
+        self.limiter = Some(limiter);
+        self
+    }
+
+    /// Build the underlying `reqwest::Client`.
+    ///
+    /// # Errors
+    /// * If a header name or value is invalid.
+    /// * If the TLS backend cannot be initialized.
+    pub fn build_client(&self) -> crate::Result<Client> {
+        let mut headers = HeaderMap::new();
+
+        for (name, value) in &self.headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|e| OllamaError::Other(format!("Invalid header name {name:?}: {e}")))?;
+            let value = HeaderValue::from_str(value)
+                .map_err(|e| OllamaError::Other(format!("Invalid header value for {name}: {e}")))?;
+            headers.append(name, value);
+        }
+
+        if let Some(auth) = &self.auth {
+            headers.insert(AUTHORIZATION, auth.header_value()?);
+        }
+
+        let mut builder = Client::builder().default_headers(headers);
+
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+
+        if let Some(timeout) = self.pool_idle_timeout {
+            builder = builder.pool_idle_timeout(timeout);
+        }
+
+        if let Some(user_agent) = &self.user_agent {
+            builder = builder.user_agent(user_agent);
+        }
+
+        Ok(builder.build()?)
+    }
+
+    fn build_url(
+        &self,
+        env_host: Option<&String>,
+        default: &str,
+        default_port: u16,
+    ) -> crate::Result<Url> {
+        match self.url.as_ref().or(env_host) {
+            Some(url) => parse_host(url, default_port),
+            None => Ok(Url::parse(default)?),
+        }
+    }
+
+    /// Build an `Ollama` client. Defaults to `http://127.0.0.1:11434`.
+    ///
+    /// # Errors
+    /// * If the URL is invalid.
+    /// * If the client cannot be built, see `build_client`.
+    pub fn build_ollama(&self) -> crate::Result<Ollama> {
+        let client = Ollama::default()
+            .with_url(self.build_url(
+                self.ollama_host.as_ref(),
+                "http://127.0.0.1:11434",
+                OLLAMA_DEFAULT_PORT,
+            )?)?
+            .with_client(self.build_client()?);
+
+        let client = match &self.retry {
+            Some(policy) => client.with_retry(policy.clone()),
+            None => client,
+        };
+
+        Ok(match &self.limiter {
+            Some(limiter) => client.with_limiter(limiter.clone()),
+            None => client,
+        })
+    }
+
+    /// Build a `Llama` client. Defaults to `http://127.0.0.1:8012`.
+    ///
+    /// # Errors
+    /// * If the URL is invalid.
+    /// * If the client cannot be built, see `build_client`.
+    pub fn build_llama(&self) -> crate::Result<Llama> {
+        let client = Llama::default()
+            .with_url(self.build_url(
+                self.llama_host.as_ref(),
+                "http://127.0.0.1:8012",
+                LLAMA_DEFAULT_PORT,
+            )?)?
+            .with_client(self.build_client()?);
+
+        let client = match &self.retry {
+            Some(policy) => client.with_retry(policy.clone()),
+            None => client,
+        };
+
+        Ok(match &self.limiter {
+            Some(limiter) => client.with_limiter(limiter.clone()),
+            None => client,
+        })
+    }
+}
+
+/// Parse a host the same way the Ollama CLI parses `OLLAMA_HOST`.
+///
+/// Accepted forms include `example.com`, `example.com:1234`, `:1234`, `0.0.0.0`, `[::1]:1234`
+/// and full URLs like `https://example.com/ollama`. Without a scheme `http` is used.
+/// The port defaults to 11434, or to 80/443 if the scheme is given explicitly.
+/// A path is kept, requests go to e.g. `https://example.com/ollama/api/chat`.
+///
+/// # Errors
+/// * If the port is not a number from 0 to 65535.
+/// * If the resulting URL is invalid.
+pub fn parse_ollama_host<S: AsRef<str>>(host: S) -> crate::Result<Url> {
+    parse_host(host.as_ref(), OLLAMA_DEFAULT_PORT)
+}
+
+fn parse_host(host: &str, default_port: u16) -> crate::Result<Url> {
+    let host = host.trim().trim_matches(['"', '\'']).trim();
+
+    let (scheme, rest, default_port) = match host.split_once("://") {
+        None => ("http", host, default_port),
+        Some(("http", rest)) => ("http", rest, 80),
+        Some(("https", rest)) => ("https", rest, 443),
+        Some((scheme, rest)) => (scheme, rest, default_port),
+    };
+
+    let (host_port, path) = rest.split_once('/').unwrap_or((rest, ""));
+
+    let (host, port) = match split_host_port(host_port) {
+        Some((host, "")) => (host, default_port),
+        Some((host, port)) => {
+            let port = port.parse::<u16>().map_err(|_| {
+                OllamaError::Other(format!("Invalid port {port:?} in host {host_port:?}"))
+            })?;
+            (host, port)
+        }
+        None => (host_port.trim_matches(['[', ']']), default_port),
+    };
+
+    let host = if host.is_empty() { "127.0.0.1" } else { host };
+    let host = if host.contains(':') {
+        format!("[{host}]")
+    } else {
+        host.to_string()
+    };
+
+    Ok(base_url(Url::parse(&format!(
+        "{scheme}://{host}:{port}/{path}"
+    ))?))
+}
+
+/// `url` with its path ending in `/`, so endpoints joined to it keep the path,
+/// e.g. of a reverse proxy at `https://example.com/ollama`.
+pub(crate) fn base_url(mut url: Url) -> Url {
+    if !url.path().ends_with('/') {
+        let path = format!("{}/", url.path());
+        url.set_path(&path);
+    }
+
+    url
+}
+
+fn split_host_port(host_port: &str) -> Option<(&str, &str)> {
+    if let Some(rest) = host_port.strip_prefix('[') {
+        let (host, after) = rest.split_once(']')?;
+        return Some((host, after.strip_prefix(':')?));
+    }
+
+    let (host, port) = host_port.rsplit_once(':')?;
+    if host.contains(':') {
+        // Bare IPv6 address without a port
+        return None;
+    }
+
+    Some((host, port))
+}
+
+impl Ollama {
+    /// Create a `ClientBuilder` for configuring headers, authentication and timeouts.
+    #[must_use]
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::new()
+    }
+
+    /// Create a client from `OLLAMA_HOST` and `OLLAMA_API_KEY`.
+    ///
+    /// # Errors
+    /// * If `OLLAMA_HOST` is invalid.
+    /// * If the client cannot be built.
+    pub fn from_env() -> crate::Result<Self> {
+        ClientBuilder::from_env().build_ollama()
+    }
+}
+
+impl Llama {
+    /// Create a `ClientBuilder` for configuring headers, authentication and timeouts.
+    #[must_use]
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hosts_are_parsed_like_the_ollama_cli() {
+        let cases = [
+            ("", "http://127.0.0.1:11434/"),
+            ("example.com", "http://example.com:11434/"),
+            ("example.com:1234", "http://example.com:1234/"),
+            ("example.com:", "http://example.com:11434/"),
+            (":1234", "http://127.0.0.1:1234/"),
+            ("0.0.0.0", "http://0.0.0.0:11434/"),
+            ("\"example.com\"", "http://example.com:11434/"),
+            ("[::1]:1234", "http://[::1]:1234/"),
+            ("::1", "http://[::1]:11434/"),
+            ("example.com/ollama", "http://example.com:11434/ollama/"),
+            ("example.com:1234/api/", "http://example.com:1234/api/"),
+            ("http://example.com", "http://example.com/"),
+            ("https://example.com", "https://example.com/"),
+            ("https://example.com:1234", "https://example.com:1234/"),
+            ("https://example.com/ollama", "https://example.com/ollama/"),
+        ];
+
+        for (host, expected) in cases {
+            let url = parse_host(host, OLLAMA_DEFAULT_PORT).unwrap();
+            assert_eq!(url.as_str(), expected, "{host:?}");
+        }
+
+        assert!(parse_host("example.com:port", OLLAMA_DEFAULT_PORT).is_err());
+        assert!(parse_host("example.com:65536", OLLAMA_DEFAULT_PORT).is_err());
+    }
+
+    #[test]
+    fn clients_use_their_own_host() {
+        let builder = ClientBuilder {
+            ollama_host: Some("ollama-box".to_string()),
+            llama_host: Some("llama-box".to_string()),
+            ..ClientBuilder::default()
+        };
+        let ollama = builder.build_ollama().unwrap();
+        let llama = builder.build_llama().unwrap();
+        assert_eq!(ollama.url.as_str(), "http://ollama-box:11434/");
+        assert_eq!(llama.url.as_str(), "http://llama-box:8012/");
+
+        let llama = ClientBuilder {
+            ollama_host: Some("ollama-box".to_string()),
+            ..ClientBuilder::default()
+        }
+        .build_llama()
+        .unwrap();
+        assert_eq!(llama.url.as_str(), "http://127.0.0.1:8012/");
+
+        let llama = builder.url("other-box").build_llama().unwrap();
+        assert_eq!(llama.url.as_str(), "http://other-box:8012/");
+    }
+}
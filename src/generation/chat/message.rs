@@ -74,6 +74,11 @@ impl Message {
         Self::new(content, Role::System)
     }
 
+    /// Model response, e.g. for few-shot examples
+    pub fn assistant<S: Into<String>>(content: S) -> Self {
+        Self::new(content, Role::Assistant)
+    }
+
     /// Once a tool finishes
     pub fn tool<S: Into<String>>(content: S) -> Self {
         Self::new(content, Role::Tool)
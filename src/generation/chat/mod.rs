@@ -6,7 +6,11 @@ use tokio_stream::{Stream, StreamExt};
 
 use crate::{
     OllamaError,
-    generation::chat::{history::History, request::ChatRequest, response::ChatResponse},
+    generation::{
+        chat::{history::History, request::ChatRequest, response::ChatResponse},
+        response_cache::ResponseCache,
+        stream::{Recording, StreamOptions},
+    },
     ollama::Ollama,
 };
 
@@ -33,9 +37,39 @@ impl Ollama {
         request: ChatRequest,
         history: History,
     ) -> crate::Result<ChatResponseStream> {
+        self.chat_with_options(request, history, StreamOptions::default())
+    }
+
+    /// Like `chat`, with cancellation and stall timeouts configured by `options`.
+    /// When tools are called, the idle timeout also covers the tool execution and the follow-up request.
+    ///
+    /// # Errors
+    ///
+    /// Same as `chat`.
+    /// `OllamaError::Cancelled`, `OllamaError::FirstTokenTimeout` or `OllamaError::IdleTimeout`
+    /// is yielded as the last item of the stream.
+    pub fn chat_with_options(
+        &self,
+        request: ChatRequest,
+        history: History,
+        options: StreamOptions,
+    ) -> crate::Result<ChatResponseStream> {
+        let stream = match &self.semantic_cache {
+            Some(cache) => cache.chat(self.clone(), request, history),
+            None => self.chat_uncached(request, history),
+        };
+
+        Ok(options.guard(stream))
+    }
+
+    pub(crate) fn chat_uncached(
+        &self,
+        request: ChatRequest,
+        history: History,
+    ) -> ChatResponseStream {
         let ollama = self.clone();
 
-        Ok(Box::pin(stream! {
+        Box::pin(stream! {
         let mut request = request.clone();
 
         loop {
@@ -46,18 +80,50 @@ impl Ollama {
             history.extend(&request.messages)?;
             request.messages = history.messages()?;
 
-            let response = ollama.post(&request).await?;
-            request.messages.clear();
+            let cache = ollama
+                .response_cache
+                .as_ref()
+                .and_then(|cache| Some((cache, ResponseCache::key("chat", &request)?)));
+
+            if let Some(responses) = cache.and_then(|(cache, key)| cache.get::<ChatResponse>(&key)) {
+                request.messages.clear();
+
+                for mut response in responses {
+                    response.message.done = response.done;
+                    history.push(&response.message.clone().created_at(response.created_at.clone()))?;
+                    yield Ok(response);
+                }
+            } else {
+                let permit = ollama.acquire(&request.model).await?;
+                let response = ollama.post(&request).await?;
+                request.messages.clear();
+
+                let mut stream = Self::stream_request_and_filter(response);
+                let mut recording = Recording::default();
 
-            let mut stream = Self::stream_request_and_filter(response);
+                while let Some(res) = stream.next().await {
+                    let Ok(responses) = res else {
+                        recording.fail();
+                        continue;
+                    };
 
-            while let Some(res) = stream.next().await {
-                if let Ok(responses) = res {
                     for response in responses {
                         history.push(&response.message.clone().created_at(response.created_at.clone()))?;
+                        if cache.is_some() {
+                            recording.push(&response);
+                        }
                         yield Ok(response);
                     }
                 }
+
+                // Tools run without occupying a slot of the limiter
+                drop(permit);
+
+                if let Some((cache, key)) = cache
+                    && let Some(responses) = recording.finish(|r| r.done)
+                {
+                    cache.insert(key, &responses).await;
+                }
             }
 
             if let Some(last) = history.last()? {
@@ -77,20 +143,12 @@ impl Ollama {
                 request.messages = tool_messages;
             }
         }
-        }))
+        })
     }
 
     async fn post(&self, request: &ChatRequest) -> crate::Result<Response> {
-        let url = self.url.join("/api/chat")?;
-        let response = self.client.post(url).json(request).send().await?;
-
-        if !response.status().is_success() {
-            return Err(crate::OllamaError::Other(format!(
-                "Error {}:\n{}",
-                response.status(),
-                response.text().await.unwrap_or_default()
-            )));
-        }
+        let url = self.url.join("api/chat")?;
+        let response = self.send(self.client.post(url).json(request)).await?;
 
         Ok(response)
     }
@@ -48,6 +48,12 @@ pub struct ChatRequest {
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub debug_render_only: Option<bool>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<bool>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_logprobs: Option<u32>,
 }
 
 #[allow(clippy::doc_markdown)]
@@ -66,6 +72,8 @@ impl ChatRequest {
             truncate: None,
             shift: None,
             debug_render_only: None,
+            logprobs: None,
+            top_logprobs: None,
         }
     }
 
@@ -141,4 +149,19 @@ impl ChatRequest {
         self.debug_render_only = Some(debug_render_only);
         self
     }
+
+    /// Logprobs specifies whether to return the log probabilities of the output tokens.
+    #[must_use]
+    pub fn logprobs(mut self, logprobs: bool) -> Self {
+        self.logprobs = Some(logprobs);
+        self
+    }
+
+    /// TopLogprobs is the number of most likely tokens to return at each token position,
+    /// along with their log probabilities. Requires logprobs to be enabled.
+    #[must_use]
+    pub fn top_logprobs(mut self, top_logprobs: u32) -> Self {
+        self.top_logprobs = Some(top_logprobs);
+        self
+    }
 }
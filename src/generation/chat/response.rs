@@ -1,8 +1,8 @@
 // Translation of https://github.com/ollama/ollama/blob/main/api/types.go into Rust
 
-use crate::generation::chat::message::Message;
+use crate::generation::{chat::message::Message, generate::response::Logprob};
 
-#[derive(Debug, Clone, serde::Deserialize)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct ChatResponse {
     /// Model name
     pub model: String,
@@ -28,6 +28,9 @@ pub struct ChatResponse {
     /// Debug information for template rendering
     pub debug_info: Option<DebugInfo>,
 
+    /// Log probabilities of the generated tokens, if requested
+    pub logprobs: Option<Vec<Logprob>>,
+
     // -- metrics below --
     /// Time spent generating the response in nanoseconds
     pub total_duration: Option<u64>,
@@ -48,7 +51,7 @@ pub struct ChatResponse {
     pub eval_duration: Option<u64>,
 }
 
-#[derive(Debug, Clone, serde::Deserialize)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct DebugInfo {
     /// The rendered template used for generation
     pub rendered_template: String,
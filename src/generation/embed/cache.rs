@@ -0,0 +1,547 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex, PoisonError,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncWriteExt;
+
+use crate::{
+    OllamaError,
+    generation::embed::{
+        request::{EmbedInput, EmbedRequest},
+        response::EmbedResponse,
+    },
+    ollama::Ollama,
+    store::codec::{Reader, Writer, invalid},
+};
+
+const MAGIC: [u8; 4] = *b"OLEC";
+const VERSION: u8 = 1;
+
+/// Key, length prefixes and map overhead per entry, on top of the embedding itself.
+const ENTRY_OVERHEAD: u64 = 64;
+
+type CacheKey = [u8; 32];
+
+/// Cache in front of `Ollama::generate_embeddings`, see `Ollama::with_embedding_cache`.
+///
+/// Entries are keyed by model, `dimensions`, `truncate` and a SHA-256 of the input text.
+/// Only inputs that are not cached are sent to Ollama. When the cache grows over its size
+/// limit, the least recently used entries are evicted.
+///
+/// A cache opened with `open` appends new entries to its file, and rewrites the file when
+/// it contains too many evicted entries. A failed write does not fail the request, see
+/// `CacheStats::errors`.
+///
+/// This struct uses Arc internally, clones share the same entries.
+#[derive(Clone)]
+pub struct EmbeddingCache {
+    inner: Arc<CacheInner>,
+}
+
+struct CacheInner {
+    path: Option<PathBuf>,
+    max_bytes: u64,
+    state: Mutex<CacheState>,
+    file: tokio::sync::Mutex<()>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+    errors: AtomicU64,
+}
+
+#[derive(Default)]
+struct CacheState {
+    entries: HashMap<CacheKey, Entry>,
+    bytes: u64,
+    tick: u64,
+
+    /// Size of all records in the file, including evicted and overwritten ones
+    file_bytes: u64,
+}
+
+struct Entry {
+    embedding: Vec<f32>,
+    last_used: u64,
+}
+
+/// Snapshot of an `EmbeddingCache`, for monitoring.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Inputs answered from the cache
+    pub hits: u64,
+
+    /// Inputs sent to Ollama
+    pub misses: u64,
+
+    /// Entries removed to stay within the size limit
+    pub evictions: u64,
+
+    /// Entries currently in the cache
+    pub entries: usize,
+
+    /// Approximate memory used by the entries
+    pub bytes: u64,
+
+    /// Failed lookups or writes that did not fail the request
+    pub errors: u64,
+}
+
+impl CacheStats {
+    /// Fraction of inputs answered from the cache.
+    #[must_use]
+    pub fn hit_rate(&self) -> Option<f64> {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            return None;
+        }
+
+        // Precision loss only matters beyond 2^52 requests
+        #[allow(clippy::cast_precision_loss)]
+        Some(self.hits as f64 / total as f64)
+    }
+}
+
+impl std::fmt::Debug for EmbeddingCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EmbeddingCache")
+            .field("path", &self.inner.path)
+            .field("max_bytes", &self.inner.max_bytes)
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+impl EmbeddingCache {
+    /// A cache that only lives in memory, using at most about `max_bytes`.
+    #[must_use]
+    pub fn in_memory(max_bytes: u64) -> Self {
+        Self::with_state(None, max_bytes, CacheState::default())
+    }
+
+    /// Open the cache file at `path`, or create it. Uses at most about `max_bytes`,
+    /// in memory and on disk.
+    ///
+    /// A file that ends in an incomplete entry, e.g. after a crash, is repaired.
+    ///
+    /// # Errors
+    ///
+    /// If the file cannot be read or created.
+    /// If the file is not a cache file.
+    pub async fn open<P: AsRef<Path>>(path: P, max_bytes: u64) -> crate::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+
+        let mut state = CacheState::default();
+        let mut complete = true;
+
+        match tokio::fs::read(&path).await {
+            Ok(bytes) => {
+                let (mut reader, version) = Reader::new(&bytes, MAGIC)?;
+                if version != VERSION {
+                    return Err(invalid(&format!("unsupported version {version}")));
+                }
+
+                while !reader.is_empty() {
+                    let Ok((key, embedding)) = read_entry(&mut reader) else {
+                        complete = false;
+                        break;
+                    };
+
+                    state.file_bytes += record_len(&embedding);
+                    state.insert(key, embedding);
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => complete = false,
+            Err(e) => return Err(e.into()),
+        }
+
+        let cache = Self::with_state(Some(path), max_bytes, state);
+        let evicted = cache.evict();
+
+        if !complete || evicted > 0 {
+            cache.compact().await?;
+        }
+
+        Ok(cache)
+    }
+
+    fn with_state(path: Option<PathBuf>, max_bytes: u64, state: CacheState) -> Self {
+        Self {
+            inner: Arc::new(CacheInner {
+                path,
+                max_bytes,
+                state: Mutex::new(state),
+                file: tokio::sync::Mutex::new(()),
+                hits: AtomicU64::new(0),
+                misses: AtomicU64::new(0),
+                evictions: AtomicU64::new(0),
+                errors: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, CacheState> {
+        self.inner
+            .state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Current hit rate, size and evictions. `errors` counts the writes to the file that failed.
+    #[must_use]
+    pub fn stats(&self) -> CacheStats {
+        let state = self.state();
+
+        CacheStats {
+            hits: self.inner.hits.load(Ordering::Relaxed),
+            misses: self.inner.misses.load(Ordering::Relaxed),
+            evictions: self.inner.evictions.load(Ordering::Relaxed),
+            entries: state.entries.len(),
+            bytes: state.bytes,
+            errors: self.inner.errors.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Remove all entries, also from the file.
+    ///
+    /// # Errors
+    ///
+    /// If the file cannot be written.
+    pub async fn clear(&self) -> crate::Result<()> {
+        *self.state() = CacheState::default();
+        self.compact().await
+    }
+
+    /// Embeddings for `request`, sending only uncached inputs to Ollama.
+    ///
+    /// Durations and token counts of the response only cover the uncached inputs,
+    /// they are unset if everything was cached.
+    pub(crate) async fn generate_embeddings(
+        &self,
+        ollama: &Ollama,
+        request: EmbedRequest,
+    ) -> crate::Result<EmbedResponse> {
+        let keys = request
+            .input
+            .iter()
+            .map(|text| key(&request, text))
+            .collect::<Vec<_>>();
+
+        let mut embeddings = self.lookup(&keys);
+        let missing = embeddings
+            .iter()
+            .enumerate()
+            .filter_map(|(i, e)| e.is_none().then_some(i))
+            .collect::<Vec<_>>();
+
+        let mut response = if missing.is_empty() {
+            EmbedResponse {
+                model: request.model.clone(),
+                embeddings: Vec::new(),
+                total_duration: None,
+                load_duration: None,
+                prompt_eval_count: None,
+                input_text: None,
+            }
+        } else {
+            let texts = request.input.iter().collect::<Vec<_>>();
+            let mut uncached = request.clone();
+            uncached.input =
+                EmbedInput::Multiple(missing.iter().map(|&i| texts[i].to_string()).collect());
+
+            let response = ollama.generate_embeddings_uncached(uncached).await?;
+            if response.embeddings.len() != missing.len() {
+                return Err(OllamaError::Other(format!(
+                    "Ollama returned {} embeddings for {} inputs",
+                    response.embeddings.len(),
+                    missing.len()
+                )));
+            }
+
+            let new = missing
+                .iter()
+                .zip(&response.embeddings)
+                .map(|(&i, embedding)| (keys[i], embedding.clone()))
+                .collect::<Vec<_>>();
+
+            for (&i, embedding) in missing.iter().zip(&response.embeddings) {
+                embeddings[i] = Some(embedding.clone());
+            }
+
+            self.insert(new).await;
+            response
+        };
+
+        response.embeddings = embeddings.into_iter().flatten().collect();
+        response.input_text = Some(request.input);
+
+        Ok(response)
+    }
+
+    fn lookup(&self, keys: &[CacheKey]) -> Vec<Option<Vec<f32>>> {
+        let mut state = self.state();
+
+        keys.iter()
+            .map(|key| {
+                state.tick += 1;
+                let tick = state.tick;
+
+                let found = state.entries.get_mut(key).map(|entry| {
+                    entry.last_used = tick;
+                    entry.embedding.clone()
+                });
+
+                let counter = match found {
+                    Some(_) => &self.inner.hits,
+                    None => &self.inner.misses,
+                };
+                counter.fetch_add(1, Ordering::Relaxed);
+
+                found
+            })
+            .collect()
+    }
+
+    /// Add `entries`, also to the file. A failed write only counts as error,
+    /// the embeddings were received anyway.
+    async fn insert(&self, entries: Vec<(CacheKey, Vec<f32>)>) {
+        if self.write(entries).await.is_err() {
+            self.inner.errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    async fn write(&self, entries: Vec<(CacheKey, Vec<f32>)>) -> crate::Result<()> {
+        let mut writer = Writer::default();
+        {
+            let mut state = self.state();
+            for (key, embedding) in entries {
+                write_entry(&mut writer, &key, &embedding);
+                state.file_bytes += record_len(&embedding);
+                state.insert(key, embedding);
+            }
+        }
+
+        self.evict();
+
+        let Some(path) = &self.inner.path else {
+            return Ok(());
+        };
+
+        let needs_compaction = {
+            let state = self.state();
+            state.file_bytes > state.bytes.max(self.inner.max_bytes / 2) * 2
+        };
+
+        if needs_compaction {
+            return self.compact().await;
+        }
+
+        let _file = self.inner.file.lock().await;
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+
+        // The file was deleted since it was opened
+        if file.metadata().await?.len() == 0 {
+            file.write_all(&Writer::new(MAGIC, VERSION).into_bytes())
+                .await?;
+        }
+        file.write_all(&writer.into_bytes()).await?;
+        file.flush().await?;
+
+        Ok(())
+    }
+
+    /// Remove the least recently used entries until the cache fits into 90% of its limit,
+    /// so eviction does not run on every insert. Returns the number of evicted entries.
+    fn evict(&self) -> usize {
+        let mut state = self.state();
+        if state.bytes <= self.inner.max_bytes {
+            return 0;
+        }
+
+        let target = self.inner.max_bytes / 10 * 9;
+        let mut by_age = state
+            .entries
+            .iter()
+            .map(|(key, entry)| (entry.last_used, *key))
+            .collect::<Vec<_>>();
+        by_age.sort_unstable();
+
+        let mut evicted = 0;
+        for (_, key) in by_age {
+            if state.bytes <= target {
+                break;
+            }
+
+            if let Some(entry) = state.entries.remove(&key) {
+                state.bytes -= entry_len(&entry.embedding);
+                evicted += 1;
+            }
+        }
+
+        self.inner
+            .evictions
+            .fetch_add(evicted as u64, Ordering::Relaxed);
+        evicted
+    }
+
+    /// Rewrite the file with only the current entries.
+    async fn compact(&self) -> crate::Result<()> {
+        let Some(path) = &self.inner.path else {
+            return Ok(());
+        };
+
+        let _file = self.inner.file.lock().await;
+        let bytes = {
+            let mut state = self.state();
+            let mut writer = Writer::new(MAGIC, VERSION);
+            let mut file_bytes = 0;
+
+            for (key, entry) in &state.entries {
+                write_entry(&mut writer, key, &entry.embedding);
+                file_bytes += record_len(&entry.embedding);
+            }
+
+            state.file_bytes = file_bytes;
+            writer.into_bytes()
+        };
+
+        // Write to a temporary file first, so a crash never leaves a half written cache
+        let temporary = path.with_extension("tmp");
+        tokio::fs::write(&temporary, bytes).await?;
+        tokio::fs::rename(&temporary, path).await?;
+
+        Ok(())
+    }
+}
+
+impl CacheState {
+    fn insert(&mut self, key: CacheKey, embedding: Vec<f32>) {
+        self.tick += 1;
+        self.bytes += entry_len(&embedding);
+
+        let entry = Entry {
+            embedding,
+            last_used: self.tick,
+        };
+
+        if let Some(old) = self.entries.insert(key, entry) {
+            self.bytes -= entry_len(&old.embedding);
+        }
+    }
+}
+
+fn key(request: &EmbedRequest, text: &str) -> CacheKey {
+    let mut hasher = Sha256::new();
+
+    hasher.update(request.model.as_bytes());
+    hasher.update([0]);
+    hasher.update(
+        request
+            .dimensions
+            .map_or_else(String::new, |d| d.to_string()),
+    );
+    hasher.update([0]);
+    hasher.update(match request.truncate {
+        Some(true) => "true",
+        Some(false) => "false",
+        None => "",
+    });
+    hasher.update([0]);
+    hasher.update(text.as_bytes());
+
+    hasher.finalize().into()
+}
+
+fn entry_len(embedding: &[f32]) -> u64 {
+    embedding.len() as u64 * 4 + ENTRY_OVERHEAD
+}
+
+/// Bytes of one entry in the file.
+fn record_len(embedding: &[f32]) -> u64 {
+    8 + 32 + 8 + embedding.len() as u64 * 4
+}
+
+fn write_entry(writer: &mut Writer, key: &CacheKey, embedding: &[f32]) {
+    writer.bytes(key);
+    writer.len(embedding.len());
+    writer.f32s(embedding);
+}
+
+fn read_entry(reader: &mut Reader<'_>) -> crate::Result<(CacheKey, Vec<f32>)> {
+    let key = reader.bytes()?.try_into().map_err(|_| invalid("bad key"))?;
+    let len = reader.len()?;
+
+    Ok((key, reader.f32s(len)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temporary_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("ollama-rust-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn entry(byte: u8) -> (CacheKey, Vec<f32>) {
+        ([byte; 32], vec![f32::from(byte); 4])
+    }
+
+    #[tokio::test]
+    async fn deleted_file_is_recreated() {
+        let path = temporary_path("embedding-deleted");
+        let cache = EmbeddingCache::open(&path, 1 << 20).await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        cache.insert(vec![entry(1)]).await;
+        assert_eq!(cache.stats().errors, 0);
+        drop(cache);
+
+        let cache = EmbeddingCache::open(&path, 1 << 20).await.unwrap();
+        assert_eq!(cache.lookup(&[[1; 32]]), vec![Some(entry(1).1)]);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn failed_write_keeps_entry_in_memory() {
+        let directory = temporary_path("embedding-directory");
+        std::fs::create_dir_all(&directory).unwrap();
+        let cache = EmbeddingCache::open(directory.join("cache"), 1 << 20)
+            .await
+            .unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        cache.insert(vec![entry(1)]).await;
+
+        assert_eq!(cache.stats().errors, 1);
+        assert_eq!(cache.lookup(&[[1; 32]]), vec![Some(entry(1).1)]);
+    }
+
+    #[tokio::test]
+    async fn least_recently_used_entries_are_evicted() {
+        // Room for three entries
+        let cache = EmbeddingCache::in_memory(entry_len(&entry(0).1) * 3);
+        cache.insert(vec![entry(1), entry(2), entry(3)]).await;
+
+        let _ = cache.lookup(&[[1; 32]]);
+        cache.insert(vec![entry(4)]).await;
+
+        let found = cache.lookup(&[[1; 32], [2; 32], [3; 32], [4; 32]]);
+        assert_eq!(
+            found.iter().map(Option::is_some).collect::<Vec<_>>(),
+            vec![true, false, false, true]
+        );
+        assert_eq!(cache.stats().evictions, 2);
+    }
+}
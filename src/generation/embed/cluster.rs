@@ -0,0 +1,609 @@
+use futures_util::stream::{self, StreamExt, TryStreamExt};
+
+use crate::{
+    OllamaError,
+    generation::{
+        embed::{
+            response::EmbedResponse,
+            vector::{dot, normalize, normalized},
+        },
+        generate::request::GenerateRequest,
+        parameters::Think,
+    },
+    model::ModelOptions,
+    ollama::Ollama,
+};
+
+/// Result of `kmeans` or `agglomerative`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Clustering {
+    /// Cluster of every vector, numbered from 0 in order of first appearance
+    pub labels: Vec<usize>,
+
+    /// Normalized mean of every cluster's vectors
+    pub centroids: Vec<Vec<f32>>,
+}
+
+impl Clustering {
+    /// Group already normalized `vectors` by `labels`, which may be sparse.
+    fn from_labels(vectors: &[Vec<f32>], labels: &[usize]) -> Self {
+        let mut renumbered = vec![usize::MAX; labels.iter().max().map_or(0, |m| m + 1)];
+        let mut next = 0;
+        let labels = labels
+            .iter()
+            .map(|&label| {
+                if renumbered[label] == usize::MAX {
+                    renumbered[label] = next;
+                    next += 1;
+                }
+                renumbered[label]
+            })
+            .collect::<Vec<_>>();
+
+        let dimensions = vectors.first().map_or(0, Vec::len);
+        let mut centroids = vec![vec![0.0; dimensions]; next];
+        for (vector, &label) in vectors.iter().zip(&labels) {
+            for (c, x) in centroids[label].iter_mut().zip(vector) {
+                *c += x;
+            }
+        }
+        for centroid in &mut centroids {
+            normalize(centroid);
+        }
+
+        Self { labels, centroids }
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.centroids.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.centroids.is_empty()
+    }
+
+    /// Indices of the vectors in every cluster.
+    #[must_use]
+    pub fn clusters(&self) -> Vec<Vec<usize>> {
+        let mut clusters = vec![Vec::new(); self.len()];
+        for (index, &label) in self.labels.iter().enumerate() {
+            clusters[label].push(index);
+        }
+
+        clusters
+    }
+
+    /// Up to `n` members of `cluster` closest to its centroid, closest first.
+    /// `vectors` must be the vectors that were clustered.
+    #[must_use]
+    pub fn representatives<V: AsRef<[f32]>>(
+        &self,
+        vectors: &[V],
+        cluster: usize,
+        n: usize,
+    ) -> Vec<usize> {
+        let Some(centroid) = self.centroids.get(cluster) else {
+            return Vec::new();
+        };
+
+        let mut members = self
+            .labels
+            .iter()
+            .enumerate()
+            .filter(|(_, label)| **label == cluster)
+            .map(|(index, _)| (index, dot(centroid, &normalized(vectors[index].as_ref()))))
+            .collect::<Vec<_>>();
+
+        members.sort_by(|a, b| b.1.total_cmp(&a.1));
+        members
+            .into_iter()
+            .take(n)
+            .map(|(index, _)| index)
+            .collect()
+    }
+}
+
+/// Options for `kmeans`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KMeansOptions {
+    /// Number of clusters, at least 1
+    pub k: usize,
+
+    pub max_iterations: usize,
+
+    /// Stop when no centroid moves by more than this cosine distance
+    pub tolerance: f32,
+
+    /// Seed for the k-means++ initialization
+    pub seed: u64,
+}
+
+impl KMeansOptions {
+    /// At most 100 iterations, tolerance 1e-4.
+    #[must_use]
+    pub fn new(k: usize) -> Self {
+        Self {
+            k,
+            max_iterations: 100,
+            tolerance: 1e-4,
+            seed: 0x5eed,
+        }
+    }
+
+    #[must_use]
+    pub fn max_iterations(mut self, max_iterations: usize) -> Self {
+        self.max_iterations = max_iterations;
+        self
+    }
+
+    #[must_use]
+    pub fn tolerance(mut self, tolerance: f32) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    #[must_use]
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+}
+
+/// Spherical k-means: vectors are assigned to the centroid with the highest cosine
+/// similarity. Centroids are initialized with k-means++.
+///
+/// Returns fewer than `k` clusters if there are fewer distinct vectors.
+/// A `k` of 0 is treated as 1, so all vectors end up in one cluster.
+#[must_use]
+pub fn kmeans<V: AsRef<[f32]>>(vectors: &[V], options: &KMeansOptions) -> Clustering {
+    let vectors = vectors
+        .iter()
+        .map(|v| normalized(v.as_ref()))
+        .collect::<Vec<_>>();
+    if vectors.is_empty() {
+        return Clustering::from_labels(&vectors, &[]);
+    }
+
+    let mut rng = fastrand::Rng::with_seed(options.seed);
+    let mut centroids = kmeans_plus_plus(&vectors, options.k.max(1), &mut rng);
+    let mut labels = vec![0; vectors.len()];
+
+    for _ in 0..options.max_iterations {
+        for (vector, label) in vectors.iter().zip(&mut labels) {
+            *label = nearest(&centroids, vector).0;
+        }
+
+        if update_centroids(&vectors, &mut centroids, &labels) <= options.tolerance {
+            break;
+        }
+    }
+
+    for (vector, label) in vectors.iter().zip(&mut labels) {
+        *label = nearest(&centroids, vector).0;
+    }
+
+    Clustering::from_labels(&vectors, &labels)
+}
+
+/// Move every centroid to the mean of its vectors. Returns how far the centroids moved,
+/// as the largest cosine distance.
+fn update_centroids(vectors: &[Vec<f32>], centroids: &mut [Vec<f32>], labels: &[usize]) -> f32 {
+    let mut sums = vec![vec![0.0; vectors[0].len()]; centroids.len()];
+    let mut counts = vec![0; centroids.len()];
+    for (vector, &label) in vectors.iter().zip(labels) {
+        counts[label] += 1;
+        for (s, x) in sums[label].iter_mut().zip(vector) {
+            *s += x;
+        }
+    }
+
+    // Restart empty clusters at the vectors farthest from their centroids, each at
+    // a different one. Decided before any centroid moves.
+    let mut restarted = vec![false; vectors.len()];
+    for (cluster, sum) in sums.iter_mut().enumerate() {
+        if counts[cluster] > 0 {
+            continue;
+        }
+
+        let farthest = (0..vectors.len())
+            .filter(|&index| !restarted[index])
+            .min_by(|&a, &b| {
+                let a = dot(&centroids[labels[a]], &vectors[a]);
+                let b = dot(&centroids[labels[b]], &vectors[b]);
+                a.total_cmp(&b)
+            });
+        if let Some(farthest) = farthest {
+            sum.clone_from(&vectors[farthest]);
+            restarted[farthest] = true;
+        }
+    }
+
+    let mut moved = 0.0_f32;
+    for (centroid, mut sum) in centroids.iter_mut().zip(sums) {
+        normalize(&mut sum);
+        moved = moved.max(1.0 - dot(&sum, centroid));
+        *centroid = sum;
+    }
+
+    moved
+}
+
+/// Pick `k` distinct vectors, each with a probability proportional to its squared distance
+/// to the closest one already picked.
+fn kmeans_plus_plus(vectors: &[Vec<f32>], k: usize, rng: &mut fastrand::Rng) -> Vec<Vec<f32>> {
+    let mut centroids = vec![vectors[rng.usize(..vectors.len())].clone()];
+    // Squared euclidean distance of normalized vectors
+    let distance = |a: &[f32], b: &[f32]| (2.0 - 2.0 * dot(a, b)).max(0.0);
+
+    let mut distances = vectors
+        .iter()
+        .map(|v| distance(v, &centroids[0]))
+        .collect::<Vec<_>>();
+
+    while centroids.len() < k {
+        let total = distances.iter().sum::<f32>();
+        if total <= f32::EPSILON {
+            break;
+        }
+
+        let mut target = rng.f32() * total;
+        let mut picked = distances.len() - 1;
+        for (index, d) in distances.iter().enumerate() {
+            if target < *d {
+                picked = index;
+                break;
+            }
+            target -= d;
+        }
+
+        let centroid = vectors[picked].clone();
+        for (d, v) in distances.iter_mut().zip(vectors) {
+            *d = d.min(distance(v, &centroid));
+        }
+        centroids.push(centroid);
+    }
+
+    centroids
+}
+
+fn nearest(centroids: &[Vec<f32>], vector: &[f32]) -> (usize, f32) {
+    centroids
+        .iter()
+        .enumerate()
+        .map(|(index, centroid)| (index, dot(centroid, vector)))
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .unwrap_or((0, 0.0))
+}
+
+/// Average linkage agglomerative clustering: clusters are merged while the average cosine
+/// similarity between their members is at least `threshold`. The number of clusters
+/// follows from the data.
+///
+/// Needs `O(n²)` memory and time, e.g. 400 MB for 10 000 vectors.
+#[must_use]
+pub fn agglomerative<V: AsRef<[f32]>>(vectors: &[V], threshold: f32) -> Clustering {
+    let vectors = vectors
+        .iter()
+        .map(|v| normalized(v.as_ref()))
+        .collect::<Vec<_>>();
+    let n = vectors.len();
+
+    let mut similarity = vec![0.0_f32; n * n];
+    for i in 0..n {
+        for j in i + 1..n {
+            let s = dot(&vectors[i], &vectors[j]);
+            similarity[i * n + j] = s;
+            similarity[j * n + i] = s;
+        }
+    }
+
+    // Nearest neighbor chain. Average linkage is reducible, so merges come out in a
+    // different order than greedy merging, but the hierarchy is the same.
+    let mut active = vec![true; n];
+    let mut sizes = vec![1_usize; n];
+    let mut parents = (0..n).collect::<Vec<_>>();
+    let mut chain = Vec::<usize>::new();
+    let mut remaining = n;
+
+    while remaining > 1 {
+        if chain.is_empty() {
+            chain.push(active.iter().position(|a| *a).unwrap_or(0));
+        }
+
+        let a = chain[chain.len() - 1];
+        let previous = (chain.len() > 1).then(|| chain[chain.len() - 2]);
+
+        // Prefer the previous chain element on ties, otherwise the chain can cycle
+        let mut best = previous.map(|p| (p, similarity[a * n + p]));
+        for b in (0..n).filter(|&b| active[b] && b != a) {
+            if best.is_none_or(|(_, s)| similarity[a * n + b] > s) {
+                best = Some((b, similarity[a * n + b]));
+            }
+        }
+        let Some((b, s)) = best else {
+            break;
+        };
+
+        if Some(b) != previous {
+            chain.push(b);
+            continue;
+        }
+
+        chain.truncate(chain.len() - 2);
+
+        // Merges below the threshold are not applied, every later merge involving
+        // them is below the threshold as well
+        if s >= threshold {
+            let root = find(&mut parents, b);
+            parents[root] = find(&mut parents, a);
+        }
+
+        // `a` becomes the merged cluster
+        #[allow(clippy::cast_precision_loss)]
+        let (size_a, size_b) = (sizes[a] as f32, sizes[b] as f32);
+        for k in (0..n).filter(|&k| active[k] && k != a && k != b) {
+            let merged = (size_a * similarity[a * n + k] + size_b * similarity[b * n + k])
+                / (size_a + size_b);
+            similarity[a * n + k] = merged;
+            similarity[k * n + a] = merged;
+        }
+
+        sizes[a] += sizes[b];
+        active[b] = false;
+        remaining -= 1;
+    }
+
+    let labels = (0..n).map(|i| find(&mut parents, i)).collect::<Vec<_>>();
+    Clustering::from_labels(&vectors, &labels)
+}
+
+fn find(parents: &mut [usize], mut i: usize) -> usize {
+    while parents[i] != i {
+        parents[i] = parents[parents[i]];
+        i = parents[i];
+    }
+
+    i
+}
+
+/// Pairs of vectors with a cosine similarity of at least `threshold`, as
+/// `(first, second, similarity)` with `first < second`.
+#[must_use]
+pub fn near_duplicates<V: AsRef<[f32]>>(vectors: &[V], threshold: f32) -> Vec<(usize, usize, f32)> {
+    let vectors = vectors
+        .iter()
+        .map(|v| normalized(v.as_ref()))
+        .collect::<Vec<_>>();
+
+    let mut pairs = Vec::new();
+    for i in 0..vectors.len() {
+        for j in i + 1..vectors.len() {
+            let s = dot(&vectors[i], &vectors[j]);
+            if s >= threshold {
+                pairs.push((i, j, s));
+            }
+        }
+    }
+
+    pairs
+}
+
+/// Indices of the vectors to keep when dropping near-duplicates: the first of every group
+/// of vectors connected by a similarity of at least `threshold`, in input order.
+#[must_use]
+pub fn deduplicate<V: AsRef<[f32]>>(vectors: &[V], threshold: f32) -> Vec<usize> {
+    let mut parents = (0..vectors.len()).collect::<Vec<_>>();
+    for (i, j, _) in near_duplicates(vectors, threshold) {
+        let (root_i, root_j) = (find(&mut parents, i), find(&mut parents, j));
+        // The smaller index stays the root, so the first of a group is kept
+        parents[root_i.max(root_j)] = root_i.min(root_j);
+    }
+
+    (0..vectors.len())
+        .filter(|&i| find(&mut parents, i) == i)
+        .collect()
+}
+
+impl EmbedResponse {
+    /// See `kmeans`.
+    #[must_use]
+    pub fn kmeans(&self, options: &KMeansOptions) -> Clustering {
+        kmeans(&self.embeddings, options)
+    }
+
+    /// See `agglomerative`.
+    #[must_use]
+    pub fn agglomerative(&self, threshold: f32) -> Clustering {
+        agglomerative(&self.embeddings, threshold)
+    }
+
+    /// See `near_duplicates`.
+    #[must_use]
+    pub fn near_duplicates(&self, threshold: f32) -> Vec<(usize, usize, f32)> {
+        near_duplicates(&self.embeddings, threshold)
+    }
+
+    /// See `deduplicate`.
+    #[must_use]
+    pub fn deduplicate(&self, threshold: f32) -> Vec<usize> {
+        deduplicate(&self.embeddings, threshold)
+    }
+}
+
+impl Ollama {
+    /// Ask `model` for a short name of every cluster, from up to `samples` of its members
+    /// closest to the centroid. `texts` and `vectors` are what was clustered.
+    ///
+    /// # Errors
+    ///
+    /// If `texts`, `vectors` and `clustering.labels` differ in length.
+    /// Same as `Ollama::generate_without_stream`, for the first cluster that fails.
+    pub async fn name_clusters<S, V>(
+        &self,
+        model: &str,
+        texts: &[S],
+        vectors: &[V],
+        clustering: &Clustering,
+        samples: usize,
+    ) -> crate::Result<Vec<String>>
+    where
+        S: AsRef<str>,
+        V: AsRef<[f32]>,
+    {
+        if texts.len() != vectors.len() || vectors.len() != clustering.labels.len() {
+            return Err(OllamaError::Other(format!(
+                "Got {} texts and {} vectors for {} clustered vectors",
+                texts.len(),
+                vectors.len(),
+                clustering.labels.len()
+            )));
+        }
+
+        let requests = (0..clustering.len())
+            .map(|cluster| {
+                let members = clustering
+                    .representatives(vectors, cluster, samples)
+                    .into_iter()
+                    .map(|i| format!("- {}", texts[i].as_ref()))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+
+                GenerateRequest::new(
+                    model.to_string(),
+                    format!(
+                        "These texts belong to one group:\n\n{members}\n\n\
+                        Give the group a short descriptive name of at most five words. \
+                        Answer only with the name."
+                    ),
+                )
+                .stream(false)
+                .think(Think::Disabled)
+                .options(ModelOptions::default().temperature(0.0).num_predict(16))
+            })
+            .collect::<Vec<_>>();
+
+        stream::iter(requests)
+            .map(|request| async move {
+                let response = self.generate_without_stream(request).await?;
+                let name = response.response.trim().trim_matches(['"', '.', '*']);
+                crate::Result::Ok(name.to_string())
+            })
+            .buffered(4)
+            .try_collect()
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two tight groups around the x and y axes, and one around z.
+    fn groups() -> Vec<Vec<f32>> {
+        vec![
+            vec![1.0, 0.1, 0.0],
+            vec![0.0, 1.0, 0.1],
+            vec![1.0, 0.0, 0.1],
+            vec![0.1, 0.0, 1.0],
+            vec![0.1, 1.0, 0.0],
+            vec![0.0, 0.1, 1.0],
+        ]
+    }
+
+    #[test]
+    fn kmeans_finds_groups() {
+        let clustering = kmeans(&groups(), &KMeansOptions::new(3));
+
+        assert_eq!(clustering.labels, vec![0, 1, 0, 2, 1, 2]);
+        assert_eq!(
+            clustering.clusters(),
+            vec![vec![0, 2], vec![1, 4], vec![3, 5]]
+        );
+    }
+
+    #[test]
+    fn kmeans_without_enough_distinct_vectors() {
+        let vectors = vec![vec![1.0, 0.0], vec![1.0, 0.0], vec![0.0, 1.0]];
+
+        assert_eq!(kmeans(&vectors, &KMeansOptions::new(5)).len(), 2);
+        assert_eq!(
+            kmeans(&vectors, &KMeansOptions::new(0)).labels,
+            vec![0, 0, 0]
+        );
+        assert!(kmeans(&Vec::<Vec<f32>>::new(), &KMeansOptions::new(2)).is_empty());
+    }
+
+    #[test]
+    fn empty_clusters_restart_on_different_vectors() {
+        let vectors = [[1.0, 0.0], [0.8, 0.6], [0.6, 0.8]]
+            .map(|v| normalized(&v))
+            .to_vec();
+        let mut centroids = vec![vec![1.0, 0.0], vec![-1.0, 0.0], vec![0.0, -1.0]];
+
+        update_centroids(&vectors, &mut centroids, &[0, 0, 0]);
+
+        assert!(dot(&centroids[1], &vectors[2]) > 0.9999);
+        assert!(dot(&centroids[2], &vectors[1]) > 0.9999);
+    }
+
+    #[test]
+    fn agglomerative_merges_above_threshold() {
+        let clustering = agglomerative(&groups(), 0.9);
+
+        assert_eq!(clustering.labels, vec![0, 1, 0, 2, 1, 2]);
+        assert_eq!(agglomerative(&groups(), -1.0).len(), 1);
+        assert_eq!(agglomerative(&groups(), 1.1).len(), groups().len());
+    }
+
+    #[test]
+    fn agglomerative_with_ties() {
+        // All pairs are equally similar, the nearest neighbor chain must still end
+        let identical = vec![vec![1.0, 1.0]; 5];
+        assert_eq!(agglomerative(&identical, 0.9).labels, vec![0; 5]);
+
+        // Corners of a square, every point has two equally close neighbors
+        let square = vec![
+            vec![1.0, 0.0],
+            vec![0.0, 1.0],
+            vec![-1.0, 0.0],
+            vec![0.0, -1.0],
+        ];
+        assert_eq!(agglomerative(&square, 0.5).len(), 4);
+        assert_eq!(agglomerative(&square, -0.5).len(), 1);
+    }
+
+    #[test]
+    fn deduplicate_keeps_first_of_group() {
+        let vectors = vec![
+            vec![1.0, 0.0],
+            vec![0.0, 1.0],
+            vec![0.99, 0.01],
+            vec![0.98, 0.03],
+            vec![0.01, 0.99],
+        ];
+
+        assert_eq!(deduplicate(&vectors, 0.999), vec![0, 1]);
+        assert_eq!(deduplicate(&vectors, 1.1), vec![0, 1, 2, 3, 4]);
+        assert_eq!(
+            near_duplicates(&vectors, 0.999)
+                .into_iter()
+                .map(|(i, j, _)| (i, j))
+                .collect::<Vec<_>>(),
+            vec![(0, 2), (0, 3), (1, 4), (2, 3)]
+        );
+    }
+
+    #[tokio::test]
+    async fn name_clusters_checks_lengths() {
+        let vectors = groups();
+        let clustering = kmeans(&vectors, &KMeansOptions::new(3));
+
+        let result = Ollama::default()
+            .name_clusters("model", &["only one text"], &vectors, &clustering, 3)
+            .await;
+
+        assert!(matches!(result, Err(OllamaError::Other(_))));
+    }
+}
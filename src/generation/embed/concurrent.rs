@@ -0,0 +1,154 @@
+use std::pin::Pin;
+
+use futures_util::stream::{self, StreamExt};
+use tokio_stream::Stream;
+
+use crate::{
+    OllamaError,
+    generation::embed::{
+        request::{EmbedInput, EmbedRequest},
+        response::EmbedResponse,
+    },
+    ollama::Ollama,
+    retry::RetryPolicy,
+};
+
+pub type EmbedChunkStream = Pin<Box<dyn Stream<Item = crate::Result<EmbedChunk>>>>;
+
+/// Embeddings of one chunk of the input.
+#[derive(Debug, Clone)]
+pub struct EmbedChunk {
+    /// Index of the first input of this chunk
+    pub offset: usize,
+
+    /// Number of attempts it took to embed this chunk
+    pub attempts: u32,
+
+    pub response: EmbedResponse,
+}
+
+/// Options for `Ollama::generate_embeddings_concurrent`.
+#[derive(Debug, Clone)]
+pub struct ConcurrentEmbedOptions {
+    /// Number of inputs per request
+    pub chunk_size: usize,
+
+    /// Number of requests in flight at the same time
+    pub concurrency: usize,
+
+    /// How often and how fast a failed chunk is retried, and which failures are transient
+    pub retry: RetryPolicy,
+}
+
+impl Default for ConcurrentEmbedOptions {
+    /// 64 inputs per chunk, 4 chunks in flight, `RetryPolicy::default()` per chunk.
+    fn default() -> Self {
+        Self {
+            chunk_size: 64,
+            concurrency: 4,
+            retry: RetryPolicy::default(),
+        }
+    }
+}
+
+impl ConcurrentEmbedOptions {
+    /// Number of inputs per request.
+    #[must_use]
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    /// Number of requests in flight at the same time.
+    #[must_use]
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency;
+        self
+    }
+
+    /// How often and how fast a failed chunk is retried.
+    /// Only the policy's `retry_statuses` and network errors are retried, other failures,
+    /// e.g. 400 for an input that is too long, fail the chunk right away.
+    #[must_use]
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+}
+
+impl Ollama {
+    /// Ollama's `/api/embed` endpoint, with several chunks in flight at the same time.
+    ///
+    /// Chunks are yielded in input order, each with the offset of its first input.
+    /// A failed chunk is retried on its own according to `options.retry`, other chunks are
+    /// not affected. If it still fails, `OllamaError::EmbedChunkFailed` is yielded in its
+    /// place and the stream continues.
+    ///
+    /// # Errors
+    ///
+    /// `OllamaError::EmbedChunkFailed` for every chunk that failed after all attempts.
+    pub fn generate_embeddings_concurrent(
+        &self,
+        request: EmbedRequest,
+        options: ConcurrentEmbedOptions,
+    ) -> crate::Result<EmbedChunkStream> {
+        let inputs = request.input.clone().into_iter().collect::<Vec<_>>();
+        let chunk_size = options.chunk_size.max(1);
+
+        let chunks = inputs
+            .chunks(chunk_size)
+            .enumerate()
+            .map(|(i, chunk)| (i * chunk_size, chunk.to_vec()))
+            .collect::<Vec<_>>();
+
+        let ollama = self.clone();
+        let retry = options.retry;
+
+        let requests = chunks.into_iter().map(move |(offset, chunk)| {
+            let ollama = ollama.clone();
+            let retry = retry.clone();
+            let len = chunk.len();
+
+            let mut request = request.clone();
+            request.input = EmbedInput::Multiple(chunk);
+
+            async move {
+                let mut attempts = 1;
+                loop {
+                    match ollama.generate_embeddings(request.clone()).await {
+                        Ok(response) => {
+                            return Ok(EmbedChunk {
+                                offset,
+                                attempts,
+                                response,
+                            });
+                        }
+                        Err(e) if attempts < retry.max_attempts && is_retryable(&retry, &e) => {
+                            tokio::time::sleep(retry.delay(attempts)).await;
+                            attempts += 1;
+                        }
+                        Err(e) => {
+                            return Err(OllamaError::EmbedChunkFailed {
+                                offset,
+                                len,
+                                source: Box::new(e),
+                            });
+                        }
+                    }
+                }
+            }
+        });
+
+        Ok(Box::pin(
+            stream::iter(requests).buffered(options.concurrency.max(1)),
+        ))
+    }
+}
+
+fn is_retryable(policy: &RetryPolicy, error: &OllamaError) -> bool {
+    match error {
+        OllamaError::Status { status, .. } => policy.is_retryable_status(*status),
+        OllamaError::NetworkError(e) => policy.is_retryable_error(e),
+        _ => false,
+    }
+}
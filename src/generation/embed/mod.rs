@@ -4,12 +4,20 @@ use async_stream::stream;
 use tokio_stream::Stream;
 
 use crate::{
-    generation::embed::{request::EmbedRequest, response::EmbedResponse},
+    generation::{
+        embed::{request::EmbedRequest, response::EmbedResponse},
+        stream::StreamOptions,
+    },
     ollama::Ollama,
 };
 
+pub mod cache;
+pub mod cluster;
+pub mod concurrent;
+pub mod quantize;
 pub mod request;
 pub mod response;
+pub mod vector;
 
 pub type EmbedResponseStream = Pin<Box<dyn Stream<Item = crate::Result<EmbedResponse>>>>;
 
@@ -20,17 +28,21 @@ impl Ollama {
     ///
     /// If Ollama rejects the request, e.g. bad parameters.
     /// If the response cannot be parsed.
+    /// If an embedding cache is configured and its file cannot be written.
     pub async fn generate_embeddings(&self, request: EmbedRequest) -> crate::Result<EmbedResponse> {
-        let url = self.url.join("/api/embed")?;
-        let response = self.client.post(url).json(&request).send().await?;
-
-        if !response.status().is_success() {
-            return Err(crate::OllamaError::Other(format!(
-                "Error {}:\n{}",
-                response.status(),
-                response.text().await.unwrap_or_default()
-            )));
+        match &self.embedding_cache {
+            Some(cache) => cache.generate_embeddings(self, request).await,
+            None => self.generate_embeddings_uncached(request).await,
         }
+    }
+
+    pub(crate) async fn generate_embeddings_uncached(
+        &self,
+        request: EmbedRequest,
+    ) -> crate::Result<EmbedResponse> {
+        let url = self.url.join("api/embed")?;
+        let _permit = self.acquire(&request.model).await?;
+        let response = self.send(self.client.post(url).json(&request)).await?;
 
         let mut response = response.json::<EmbedResponse>().await?;
         response.input_text = Some(request.input);
@@ -57,7 +69,24 @@ impl Ollama {
         request: EmbedRequest,
         chunk_size: usize,
     ) -> crate::Result<EmbedResponseStream> {
-        let url = self.url.join("/api/embed")?;
+        self.generate_embeddings_chunked_with_options(request, chunk_size, StreamOptions::default())
+    }
+
+    /// Like `generate_embeddings_chunked`, with cancellation and stall timeouts configured by
+    /// `options`. The idle timeout applies to each chunk after the first one.
+    ///
+    /// # Errors
+    ///
+    /// Same as `generate_embeddings_chunked`.
+    /// `OllamaError::Cancelled`, `OllamaError::FirstTokenTimeout` or `OllamaError::IdleTimeout`
+    /// is yielded as the last item of the stream.
+    pub fn generate_embeddings_chunked_with_options(
+        &self,
+        request: EmbedRequest,
+        chunk_size: usize,
+        options: StreamOptions,
+    ) -> crate::Result<EmbedResponseStream> {
+        let url = self.url.join("api/embed")?;
 
         let mut chunks = vec![];
 
@@ -73,33 +102,30 @@ impl Ollama {
         }
 
         let ollama = self.clone();
-        let stream = Box::pin(stream! {
+        let stream = options.guard(Box::pin(stream! {
             for chunk in chunks {
                 let mut request = request.clone();
                 request.input = request::EmbedInput::Multiple(chunk);
-                let response = ollama
-                    .client
-                    .post(url.clone())
-                    .json(&request)
-                    .send()
-                    .await?;
-
-                if !response.status().is_success() {
-                    yield Err(crate::OllamaError::Other(format!(
-                        "Error {}:\n{}",
-                        response.status(),
-                        response.text().await.unwrap_or_default()
-                    )));
-
-                    continue;
-                }
+                let _permit = ollama.acquire(&request.model).await?;
+                let response = match ollama
+                    .send(ollama.client.post(url.clone()).json(&request))
+                    .await
+                {
+                    Ok(response) => response,
+                    // Network errors end the stream, rejected chunks are skipped
+                    Err(crate::OllamaError::NetworkError(e)) => Err(e)?,
+                    Err(e) => {
+                        yield Err(e);
+                        continue;
+                    }
+                };
 
                 let mut response = response.json::<EmbedResponse>().await?;
                 response.input_text = Some(request.input);
 
                 yield Ok(response);
             }
-        });
+        }));
 
         Ok(stream)
     }
@@ -0,0 +1,314 @@
+//! Compact representations of embeddings for storage, with similarity functions that work
+//! on the compact form directly.
+//!
+//! | Format | Bytes per dimension | Similarity |
+//! |--------|---------------------|------------|
+//! | `f32` | 4 | exact |
+//! | `f16` | 2 | almost exact |
+//! | `Int8Embedding` | 1 | close, one scale per vector |
+//! | `BinaryEmbedding` | 1/8 | coarse, for candidate selection |
+
+pub use half::f16;
+
+use crate::generation::embed::{response::EmbedResponse, vector};
+
+/// Convert to half precision.
+#[must_use]
+pub fn to_f16(v: &[f32]) -> Vec<f16> {
+    v.iter().copied().map(f16::from_f32).collect()
+}
+
+/// Convert back to single precision.
+#[must_use]
+pub fn from_f16(v: &[f16]) -> Vec<f32> {
+    v.iter().copied().map(f16::to_f32).collect()
+}
+
+/// Dot product of two half precision vectors, accumulated in `f32`.
+#[must_use]
+pub fn dot_f16(a: &[f16], b: &[f16]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x.to_f32() * y.to_f32()).sum()
+}
+
+/// Cosine similarity of two half precision vectors. 0 if one of them has zero length.
+#[must_use]
+pub fn cosine_similarity_f16(a: &[f16], b: &[f16]) -> f32 {
+    let denominator = (dot_f16(a, a) * dot_f16(b, b)).sqrt();
+    if denominator == 0.0 {
+        return 0.0;
+    }
+
+    dot_f16(a, b) / denominator
+}
+
+/// Symmetric scalar quantization to `i8`: `value ≈ values[i] * scale`.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Int8Embedding {
+    pub values: Vec<i8>,
+    pub scale: f32,
+}
+
+impl Int8Embedding {
+    /// Quantize with the largest absolute value mapped to 127.
+    #[must_use]
+    pub fn quantize(v: &[f32]) -> Self {
+        let max = v.iter().fold(0.0f32, |max, x| max.max(x.abs()));
+        if max == 0.0 {
+            return Self {
+                values: vec![0; v.len()],
+                scale: 0.0,
+            };
+        }
+
+        let scale = max / 127.0;
+        let inverse = 1.0 / scale;
+
+        // Values are within [-127, 127] after scaling, the cast cannot truncate
+        #[allow(clippy::cast_possible_truncation)]
+        let values = v
+            .iter()
+            .map(|x| (x * inverse).round().clamp(-127.0, 127.0) as i8)
+            .collect();
+
+        Self { values, scale }
+    }
+
+    /// Approximate original vector.
+    #[must_use]
+    pub fn dequantize(&self) -> Vec<f32> {
+        self.values
+            .iter()
+            .map(|&x| f32::from(x) * self.scale)
+            .collect()
+    }
+
+    /// Approximate dot product of the original vectors, computed in integers.
+    #[must_use]
+    pub fn dot(&self, other: &Self) -> f32 {
+        let sum = self
+            .values
+            .iter()
+            .zip(&other.values)
+            .map(|(&x, &y)| i32::from(x) * i32::from(y))
+            .sum::<i32>();
+
+        // An i32 sum of i8 products is exact up to 2^31 / 127^2 ≈ 133k dimensions
+        #[allow(clippy::cast_precision_loss)]
+        let sum = sum as f32;
+        sum * self.scale * other.scale
+    }
+
+    /// Approximate cosine similarity of the original vectors.
+    #[must_use]
+    pub fn cosine_similarity(&self, other: &Self) -> f32 {
+        let denominator = (self.dot(self) * other.dot(other)).sqrt();
+        if denominator == 0.0 {
+            return 0.0;
+        }
+
+        self.dot(other) / denominator
+    }
+
+    /// Dot product with a full precision query, e.g. to search quantized documents with an
+    /// unquantized query.
+    #[must_use]
+    pub fn dot_f32(&self, query: &[f32]) -> f32 {
+        self.values
+            .iter()
+            .zip(query)
+            .map(|(&x, y)| f32::from(x) * y)
+            .sum::<f32>()
+            * self.scale
+    }
+}
+
+/// One bit per dimension, set if the value is positive.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct BinaryEmbedding {
+    pub bits: Vec<u64>,
+
+    /// Number of dimensions
+    pub len: usize,
+}
+
+impl BinaryEmbedding {
+    #[must_use]
+    pub fn quantize(v: &[f32]) -> Self {
+        let mut bits = vec![0u64; v.len().div_ceil(64)];
+        for (i, x) in v.iter().enumerate() {
+            if *x > 0.0 {
+                bits[i / 64] |= 1 << (i % 64);
+            }
+        }
+
+        Self { bits, len: v.len() }
+    }
+
+    /// Number of dimensions with a different sign.
+    #[must_use]
+    pub fn hamming_distance(&self, other: &Self) -> u32 {
+        self.bits
+            .iter()
+            .zip(&other.bits)
+            .map(|(a, b)| (a ^ b).count_ones())
+            .sum()
+    }
+
+    /// Fraction of dimensions with the same sign, scaled to `[-1, 1]` like cosine.
+    #[must_use]
+    pub fn similarity(&self, other: &Self) -> f32 {
+        let len = self.len.min(other.len);
+        if len == 0 {
+            return 0.0;
+        }
+
+        // Both are at most a few thousand, far below f32's exact integer range
+        #[allow(clippy::cast_precision_loss)]
+        let (distance, len) = (self.hamming_distance(other) as f32, len as f32);
+        1.0 - 2.0 * distance / len
+    }
+}
+
+/// Matryoshka truncation: keep the first `dimensions` values and normalize again.
+///
+/// Only meaningful for models trained with Matryoshka representation learning,
+/// e.g. `qwen3-embedding`. Same as requesting `EmbedRequest::dimensions` from Ollama,
+/// but one full size embedding can be truncated to several sizes.
+#[must_use]
+pub fn truncate(v: &[f32], dimensions: usize) -> Vec<f32> {
+    let mut truncated = v[..dimensions.min(v.len())].to_vec();
+    vector::normalize(&mut truncated);
+    truncated
+}
+
+impl EmbedResponse {
+    /// Matryoshka truncation of every embedding, see `quantize::truncate`.
+    pub fn truncate_dimensions(&mut self, dimensions: usize) {
+        for embedding in &mut self.embeddings {
+            *embedding = truncate(embedding, dimensions);
+        }
+    }
+
+    /// Embeddings in half precision.
+    #[must_use]
+    pub fn to_f16(&self) -> Vec<Vec<f16>> {
+        self.embeddings.iter().map(|e| to_f16(e)).collect()
+    }
+
+    /// Embeddings quantized to `i8`.
+    #[must_use]
+    pub fn to_int8(&self) -> Vec<Int8Embedding> {
+        self.embeddings
+            .iter()
+            .map(|e| Int8Embedding::quantize(e))
+            .collect()
+    }
+
+    /// Embeddings as sign bits.
+    #[must_use]
+    pub fn to_binary(&self) -> Vec<BinaryEmbedding> {
+        self.embeddings
+            .iter()
+            .map(|e| BinaryEmbedding::quantize(e))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic values in `[-1, 1)`, like a normalized embedding's scale.
+    fn embedding(seed: u64, len: usize) -> Vec<f32> {
+        let mut rng = fastrand::Rng::with_seed(seed);
+        (0..len).map(|_| rng.f32() * 2.0 - 1.0).collect()
+    }
+
+    #[test]
+    fn f16_round_trip_keeps_11_bits() {
+        let v = embedding(1, 256);
+
+        for (x, y) in v.iter().zip(from_f16(&to_f16(&v))) {
+            // Subnormals below 2^-14 have less precision, they do not occur here
+            assert!((x - y).abs() <= x.abs() / 2048.0, "{x} -> {y}");
+        }
+
+        let (a, b) = (embedding(2, 256), embedding(3, 256));
+        let exact = vector::cosine_similarity(&a, &b);
+        let half = cosine_similarity_f16(&to_f16(&a), &to_f16(&b));
+        assert!((exact - half).abs() < 1e-3, "{exact} != {half}");
+    }
+
+    #[test]
+    fn int8_round_trip_is_within_half_a_step() {
+        let v = embedding(4, 256);
+        let quantized = Int8Embedding::quantize(&v);
+
+        let max = v.iter().fold(0.0f32, |max, x| max.max(x.abs()));
+        assert!((quantized.scale * 127.0 - max).abs() < 1e-6);
+        assert_eq!(
+            quantized.values.iter().map(|x| x.unsigned_abs()).max(),
+            Some(127)
+        );
+
+        for (x, y) in v.iter().zip(quantized.dequantize()) {
+            assert!((x - y).abs() <= quantized.scale / 2.0 + 1e-6, "{x} -> {y}");
+        }
+    }
+
+    #[test]
+    fn int8_similarities_are_close() {
+        let a = vector::normalized(&embedding(5, 256));
+        let b = vector::normalized(&embedding(6, 256));
+        let (qa, qb) = (Int8Embedding::quantize(&a), Int8Embedding::quantize(&b));
+
+        let exact = vector::dot(&a, &b);
+        assert!(
+            (qa.dot(&qb) - exact).abs() < 0.01,
+            "{} != {exact}",
+            qa.dot(&qb)
+        );
+        assert!((qa.dot_f32(&b) - exact).abs() < 0.01);
+
+        let exact = vector::cosine_similarity(&a, &b);
+        assert!((qa.cosine_similarity(&qb) - exact).abs() < 0.01);
+        assert!((qa.cosine_similarity(&qa) - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn int8_zero_vector() {
+        let quantized = Int8Embedding::quantize(&[0.0; 3]);
+
+        assert_eq!(quantized.values, [0; 3]);
+        assert_eq!(quantized.dequantize(), [0.0; 3]);
+        assert!(quantized.cosine_similarity(&quantized).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn binary_bits_cross_word_boundaries() {
+        let v = (0..70)
+            .map(|i| if i % 3 == 0 { 1.0 } else { -1.0 })
+            .collect::<Vec<_>>();
+        let binary = BinaryEmbedding::quantize(&v);
+        assert_eq!((binary.bits.len(), binary.len), (2, 70));
+
+        let negated = BinaryEmbedding::quantize(&v.iter().map(|x| -x).collect::<Vec<_>>());
+        assert_eq!(binary.hamming_distance(&negated), 70);
+        assert!((binary.similarity(&negated) + 1.0).abs() < f32::EPSILON);
+        assert!((binary.similarity(&binary) - 1.0).abs() < f32::EPSILON);
+
+        // Zero counts as negative
+        let zero = BinaryEmbedding::quantize(&[0.0; 70]);
+        assert_eq!(zero.hamming_distance(&binary), 24);
+        assert!(BinaryEmbedding::quantize(&[]).similarity(&zero).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn truncation_is_normalized() {
+        let truncated = truncate(&[3.0, 4.0, 12.0], 2);
+        assert_eq!(truncated, [0.6, 0.8]);
+
+        assert_eq!(truncate(&[2.0], 8), [1.0]);
+        assert!(truncate(&[1.0, 2.0], 0).is_empty());
+    }
+}
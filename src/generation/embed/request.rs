@@ -27,6 +27,18 @@ impl From<Vec<String>> for EmbedInput {
     }
 }
 
+impl EmbedInput {
+    /// The input texts, in order.
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        let texts = match self {
+            EmbedInput::Single(s) => std::slice::from_ref(s),
+            EmbedInput::Multiple(v) => v.as_slice(),
+        };
+
+        texts.iter().map(String::as_str)
+    }
+}
+
 impl IntoIterator for EmbedInput {
     type Item = String;
     type IntoIter = std::vec::IntoIter<String>;
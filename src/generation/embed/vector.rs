@@ -0,0 +1,340 @@
+//! Similarity metrics, normalization and nearest neighbour search over embeddings.
+//!
+//! The loops accumulate into several independent lanes so the compiler can vectorize them.
+
+use std::{cmp::Ordering, collections::BinaryHeap};
+
+use crate::generation::embed::{request::EmbedInput, response::EmbedResponse};
+
+const LANES: usize = 8;
+
+/// How two embeddings are compared. Higher scores are always more similar.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Similarity {
+    /// Cosine of the angle between the vectors, in `[-1, 1]`
+    #[default]
+    Cosine,
+
+    /// Dot product, equal to cosine for normalized vectors but cheaper
+    Dot,
+
+    /// Negated euclidean distance
+    Euclidean,
+}
+
+impl Similarity {
+    #[must_use]
+    pub fn score(self, a: &[f32], b: &[f32]) -> f32 {
+        match self {
+            Similarity::Cosine => cosine_similarity(a, b),
+            Similarity::Dot => dot(a, b),
+            Similarity::Euclidean => -euclidean_distance(a, b),
+        }
+    }
+}
+
+/// A result of `top_k`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Neighbor {
+    /// Index of the candidate
+    pub index: usize,
+
+    /// Similarity to the query
+    pub score: f32,
+}
+
+impl Eq for Neighbor {}
+
+impl PartialOrd for Neighbor {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Neighbor {
+    /// Reversed, so a `BinaryHeap` keeps the worst neighbor on top.
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .score
+            .total_cmp(&self.score)
+            .then(self.index.cmp(&other.index))
+    }
+}
+
+/// Dot product of two vectors. Extra elements of the longer vector are ignored.
+#[must_use]
+pub fn dot(a: &[f32], b: &[f32]) -> f32 {
+    let len = a.len().min(b.len());
+    let (a, b) = (&a[..len], &b[..len]);
+
+    let mut sums = [0.0f32; LANES];
+    let a_chunks = a.chunks_exact(LANES);
+    let b_chunks = b.chunks_exact(LANES);
+    let tail = a_chunks
+        .remainder()
+        .iter()
+        .zip(b_chunks.remainder())
+        .map(|(x, y)| x * y)
+        .sum::<f32>();
+
+    for (x, y) in a_chunks.zip(b_chunks) {
+        for i in 0..LANES {
+            sums[i] += x[i] * y[i];
+        }
+    }
+
+    sums.iter().sum::<f32>() + tail
+}
+
+/// Euclidean length of a vector.
+#[must_use]
+pub fn norm(v: &[f32]) -> f32 {
+    dot(v, v).sqrt()
+}
+
+/// Cosine similarity of two vectors. 0 if one of them has zero length.
+#[must_use]
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let denominator = norm(a) * norm(b);
+    if denominator == 0.0 {
+        return 0.0;
+    }
+
+    dot(a, b) / denominator
+}
+
+/// Euclidean distance between two vectors. Extra elements of the longer vector are ignored.
+#[must_use]
+pub fn euclidean_distance(a: &[f32], b: &[f32]) -> f32 {
+    let len = a.len().min(b.len());
+    let (a, b) = (&a[..len], &b[..len]);
+
+    let mut sums = [0.0f32; LANES];
+    let a_chunks = a.chunks_exact(LANES);
+    let b_chunks = b.chunks_exact(LANES);
+    let tail = a_chunks
+        .remainder()
+        .iter()
+        .zip(b_chunks.remainder())
+        .map(|(x, y)| (x - y) * (x - y))
+        .sum::<f32>();
+
+    for (x, y) in a_chunks.zip(b_chunks) {
+        for i in 0..LANES {
+            let d = x[i] - y[i];
+            sums[i] += d * d;
+        }
+    }
+
+    (sums.iter().sum::<f32>() + tail).sqrt()
+}
+
+/// Scale a vector to length 1 in place. Vectors of zero length are left unchanged.
+pub fn normalize(v: &mut [f32]) {
+    let norm = norm(v);
+    if norm == 0.0 {
+        return;
+    }
+
+    let inverse = 1.0 / norm;
+    for x in v {
+        *x *= inverse;
+    }
+}
+
+/// Copy of `v` scaled to length 1.
+#[must_use]
+pub fn normalized(v: &[f32]) -> Vec<f32> {
+    let mut v = v.to_vec();
+    normalize(&mut v);
+    v
+}
+
+/// Similarity of every vector in `rows` to every vector in `columns`.
+/// `matrix[i][j]` compares `rows[i]` with `columns[j]`.
+#[must_use]
+pub fn similarity_matrix<A, B>(rows: &[A], columns: &[B], similarity: Similarity) -> Vec<Vec<f32>>
+where
+    A: AsRef<[f32]>,
+    B: AsRef<[f32]>,
+{
+    // Cosine over normalized copies is a dot product, so every norm is computed only once
+    if similarity == Similarity::Cosine {
+        let rows = rows
+            .iter()
+            .map(|v| normalized(v.as_ref()))
+            .collect::<Vec<_>>();
+        let columns = columns
+            .iter()
+            .map(|v| normalized(v.as_ref()))
+            .collect::<Vec<_>>();
+
+        return similarity_matrix(&rows, &columns, Similarity::Dot);
+    }
+
+    rows.iter()
+        .map(|row| {
+            columns
+                .iter()
+                .map(|column| similarity.score(row.as_ref(), column.as_ref()))
+                .collect()
+        })
+        .collect()
+}
+
+/// Similarity of every pair of `vectors`. The matrix is symmetric.
+#[must_use]
+pub fn pairwise_similarity<V: AsRef<[f32]>>(
+    vectors: &[V],
+    similarity: Similarity,
+) -> Vec<Vec<f32>> {
+    similarity_matrix(vectors, vectors, similarity)
+}
+
+/// The `k` candidates most similar to `query`, best first.
+#[must_use]
+pub fn top_k<V: AsRef<[f32]>>(
+    query: &[f32],
+    candidates: &[V],
+    k: usize,
+    similarity: Similarity,
+) -> Vec<Neighbor> {
+    if k == 0 {
+        return Vec::new();
+    }
+
+    let mut heap = BinaryHeap::with_capacity(k + 1);
+    for (index, candidate) in candidates.iter().enumerate() {
+        heap.push(Neighbor {
+            index,
+            score: similarity.score(query, candidate.as_ref()),
+        });
+
+        if heap.len() > k {
+            heap.pop();
+        }
+    }
+
+    heap.into_sorted_vec()
+}
+
+impl EmbedResponse {
+    /// Pairs of input text and embedding.
+    /// Empty if `input_text` is not set, i.e. the response was not created by this crate.
+    pub fn iter_with_input(&self) -> impl Iterator<Item = (&str, &[f32])> {
+        self.input_text
+            .iter()
+            .flat_map(EmbedInput::iter)
+            .zip(self.embeddings.iter().map(Vec::as_slice))
+    }
+
+    /// Scale every embedding to length 1, so `Similarity::Dot` can be used instead of cosine.
+    pub fn normalize(&mut self) {
+        for embedding in &mut self.embeddings {
+            normalize(embedding);
+        }
+    }
+
+    /// Similarity of every pair of embeddings.
+    #[must_use]
+    pub fn similarity_matrix(&self, similarity: Similarity) -> Vec<Vec<f32>> {
+        pairwise_similarity(&self.embeddings, similarity)
+    }
+
+    /// The `k` embeddings most similar to `query`, best first.
+    #[must_use]
+    pub fn top_k(&self, query: &[f32], k: usize, similarity: Similarity) -> Vec<Neighbor> {
+        top_k(query, &self.embeddings, k, similarity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 1e-5, "{actual} != {expected}");
+    }
+
+    /// Long enough for full lanes and a tail.
+    fn vector(offset: f32) -> Vec<f32> {
+        (0..19u8).map(|i| f32::from(i) * 0.25 - offset).collect()
+    }
+
+    #[test]
+    fn lanes_and_tail_are_summed() {
+        let (a, b) = (vector(1.0), vector(3.0));
+
+        let expected = a.iter().zip(&b).map(|(x, y)| x * y).sum();
+        assert_close(dot(&a, &b), expected);
+
+        let expected = a
+            .iter()
+            .zip(&b)
+            .map(|(x, y)| (x - y) * (x - y))
+            .sum::<f32>();
+        assert_close(euclidean_distance(&a, &b), expected.sqrt());
+    }
+
+    #[test]
+    fn extra_elements_are_ignored() {
+        assert_close(dot(&[1.0, 2.0], &[3.0, 4.0, 5.0]), 11.0);
+        assert_close(euclidean_distance(&[1.0, 2.0, 9.0], &[1.0, 4.0]), 2.0);
+        assert_close(dot(&[], &[1.0]), 0.0);
+    }
+
+    #[test]
+    fn zero_vectors() {
+        let zero = [0.0; 4];
+        let one = [1.0, 0.0, 0.0, 0.0];
+
+        assert_close(cosine_similarity(&zero, &one), 0.0);
+        assert_close(cosine_similarity(&zero, &zero), 0.0);
+        assert_close(cosine_similarity(&[], &[]), 0.0);
+        assert_eq!(normalized(&zero), zero);
+
+        let matrix = similarity_matrix(&[zero, one], &[one], Similarity::Cosine);
+        assert_eq!(matrix, [[0.0], [1.0]]);
+    }
+
+    #[test]
+    fn normalized_vectors_have_unit_length() {
+        let v = normalized(&vector(2.0));
+
+        assert_close(norm(&v), 1.0);
+        assert_close(cosine_similarity(&v, &vector(2.0)), 1.0);
+        assert_close(Similarity::Dot.score(&v, &v), 1.0);
+    }
+
+    #[test]
+    fn cosine_matrix_matches_cosine_similarity() {
+        let vectors = [vector(0.0), vector(2.0), vector(4.5)];
+        let matrix = pairwise_similarity(&vectors, Similarity::Cosine);
+
+        for (i, row) in matrix.iter().enumerate() {
+            for (j, &score) in row.iter().enumerate() {
+                assert_close(score, cosine_similarity(&vectors[i], &vectors[j]));
+            }
+        }
+    }
+
+    #[test]
+    fn top_k_is_best_first() {
+        let candidates = [[0.0, 1.0], [1.0, 0.0], [0.6, 0.8], [1.0, 0.0], [-1.0, 0.0]];
+        let indices = |k, similarity| {
+            top_k(&[1.0, 0.0], &candidates, k, similarity)
+                .iter()
+                .map(|n| n.index)
+                .collect::<Vec<_>>()
+        };
+
+        // Ties keep the earlier candidate first
+        assert_eq!(indices(3, Similarity::Cosine), [1, 3, 2]);
+        assert_eq!(indices(10, Similarity::Dot), [1, 3, 2, 0, 4]);
+        assert_eq!(indices(2, Similarity::Euclidean), [1, 3]);
+        assert!(indices(0, Similarity::Cosine).is_empty());
+
+        let neighbors = top_k(&[1.0, 0.0], &candidates, 3, Similarity::Cosine);
+        assert_close(neighbors[2].score, 0.6);
+    }
+}
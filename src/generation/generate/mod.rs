@@ -1,17 +1,29 @@
 use std::pin::Pin;
 
+use async_stream::stream;
+use reqwest::Response;
 use tokio_stream::{Stream, StreamExt};
 
 use crate::{
     OllamaError,
-    generation::generate::{request::GenerateRequest, response::GenerateResponse},
+    generation::{
+        generate::{request::GenerateRequest, response::GenerateResponse},
+        response_cache::ResponseCache,
+        stream::StreamOptions,
+    },
+    limiter,
     ollama::Ollama,
 };
 
 pub mod request;
 pub mod response;
 
-pub type GenerateResponseStream = Pin<Box<dyn Stream<Item = crate::Result<Vec<GenerateResponse>>>>>;
+pub type GenerateResponseStream = Pin<Box<dyn Stream<Item = crate::Result<GenerateResponse>>>>;
+
+/// Stream of `GenerateResponse` batches, one batch per HTTP chunk received from Ollama.
+/// This is the item type `generate` had before it was flattened.
+pub type GenerateResponseChunkStream =
+    Pin<Box<dyn Stream<Item = crate::Result<Vec<GenerateResponse>>>>>;
 
 impl Ollama {
     /// Ollama's `/api/generate` endpoint. Returns a stream of `GenerateResponse`.
@@ -25,30 +37,82 @@ impl Ollama {
         &self,
         request: GenerateRequest,
     ) -> crate::Result<GenerateResponseStream> {
-        let url = self.url.join("/api/generate")?;
-        let response = self.client.post(url).json(&request).send().await?;
-
-        if !response.status().is_success() {
-            return Err(crate::OllamaError::Other(format!(
-                "Error {}:\n{}",
-                response.status(),
-                response.text().await.unwrap_or_default()
-            )));
+        match &self.semantic_cache {
+            Some(cache) => cache.generate(self, request).await,
+            None => self.generate_uncached(request).await,
         }
+    }
 
-        let stream = response.bytes_stream().map(|r| match r {
-            Ok(bytes) => {
-                let iter = serde_json::Deserializer::from_slice(&bytes).into_iter();
-                let res = iter
-                    .filter_map(Result::ok)
-                    .collect::<Vec<GenerateResponse>>();
+    pub(crate) async fn generate_uncached(
+        &self,
+        request: GenerateRequest,
+    ) -> crate::Result<GenerateResponseStream> {
+        let cache = self
+            .response_cache
+            .as_ref()
+            .and_then(|cache| Some((cache, ResponseCache::key("generate", &request)?)));
+        if let Some((cache, key)) = cache
+            && let Some(responses) = cache.get::<GenerateResponse>(&key)
+        {
+            return Ok(Box::pin(tokio_stream::iter(responses.into_iter().map(Ok))));
+        }
 
-                Ok(res)
+        let permit = self.acquire(&request.model).await?;
+        let response = self.post_generate(&request).await?;
+        let mut stream = Self::stream_generate(response);
+
+        if let Some((cache, key)) = cache {
+            stream = cache.record(key, stream, |r| r.done);
+        }
+
+        Ok(limiter::hold(permit, stream))
+    }
+
+    /// Like `generate`, with cancellation and stall timeouts configured by `options`.
+    /// The request is sent when the stream is first polled, so the first token timeout
+    /// includes the time until Ollama responds.
+    ///
+    /// # Errors
+    ///
+    /// Same as `generate`, but yielded by the stream.
+    /// `OllamaError::Cancelled`, `OllamaError::FirstTokenTimeout` or `OllamaError::IdleTimeout`
+    /// is yielded as the last item of the stream.
+    pub fn generate_with_options(
+        &self,
+        request: GenerateRequest,
+        options: StreamOptions,
+    ) -> crate::Result<GenerateResponseStream> {
+        let ollama = self.clone();
+
+        Ok(options.guard(Box::pin(stream! {
+            let mut stream = ollama.generate(request).await?;
+
+            while let Some(res) = stream.next().await {
+                yield res;
             }
-            Err(e) => Err(OllamaError::Other(format!("Failed to parse response: {e}"))),
-        });
+        })))
+    }
 
-        Ok(Box::pin(stream))
+    /// Ollama's `/api/generate` endpoint. Returns a stream of `Vec<GenerateResponse>`,
+    /// one `Vec` per HTTP chunk.
+    ///
+    /// Compatibility adapter for code written against the previous `generate` signature.
+    ///
+    /// # Errors
+    ///
+    /// If Ollama rejects the request, e.g. the Model does not support thinking.
+    /// If the response cannot be parsed.
+    #[deprecated(note = "use `generate`, which yields one `GenerateResponse` per item")]
+    pub async fn generate_chunked(
+        &self,
+        request: GenerateRequest,
+    ) -> crate::Result<GenerateResponseChunkStream> {
+        let permit = self.acquire(&request.model).await?;
+        let response = self.post_generate(&request).await?;
+        Ok(limiter::hold(
+            permit,
+            Self::stream_generate_chunks(response),
+        ))
     }
 
     /// Ollama's `/api/generate` endpoint. Returns one `GenerateResponse`.
@@ -69,17 +133,77 @@ impl Ollama {
             ));
         }
 
-        let url = self.url.join("/api/generate")?;
-        let response = self.client.post(url).json(&request).send().await?;
+        match &self.semantic_cache {
+            Some(cache) => cache.generate_without_stream(self, request).await,
+            None => self.generate_without_stream_uncached(request).await,
+        }
+    }
 
-        if !response.status().is_success() {
-            return Err(crate::OllamaError::Other(format!(
-                "Error {}:\n{}",
-                response.status(),
-                response.text().await.unwrap_or_default()
-            )));
+    pub(crate) async fn generate_without_stream_uncached(
+        &self,
+        request: GenerateRequest,
+    ) -> crate::Result<GenerateResponse> {
+        let cache = self
+            .response_cache
+            .as_ref()
+            .and_then(|cache| Some((cache, ResponseCache::key("generate", &request)?)));
+        if let Some((cache, key)) = cache
+            && let Some(response) = cache
+                .get::<GenerateResponse>(&key)
+                .and_then(|mut r| r.pop())
+        {
+            return Ok(response);
         }
 
-        Ok(response.json::<GenerateResponse>().await?)
+        let permit = self.acquire(&request.model).await?;
+        let response = self.post_generate(&request).await?;
+        let response = response.json::<GenerateResponse>().await?;
+        drop(permit);
+
+        if let Some((cache, key)) = cache {
+            cache.insert(key, std::slice::from_ref(&response)).await;
+        }
+
+        Ok(response)
+    }
+
+    async fn post_generate(&self, request: &GenerateRequest) -> crate::Result<Response> {
+        let url = self.url.join("api/generate")?;
+        let response = self.send(self.client.post(url).json(request)).await?;
+
+        Ok(response)
+    }
+
+    fn stream_generate(response: Response) -> GenerateResponseStream {
+        let mut chunks = Self::stream_generate_chunks(response);
+
+        Box::pin(stream! {
+            while let Some(res) = chunks.next().await {
+                match res {
+                    Ok(responses) => {
+                        for response in responses {
+                            yield Ok(response);
+                        }
+                    }
+                    Err(e) => yield Err(e),
+                }
+            }
+        })
+    }
+
+    fn stream_generate_chunks(response: Response) -> GenerateResponseChunkStream {
+        let stream = response.bytes_stream().map(|r| match r {
+            Ok(bytes) => {
+                let iter = serde_json::Deserializer::from_slice(&bytes).into_iter();
+                let res = iter
+                    .filter_map(Result::ok)
+                    .collect::<Vec<GenerateResponse>>();
+
+                Ok(res)
+            }
+            Err(e) => Err(OllamaError::Other(format!("Failed to parse response: {e}"))),
+        });
+
+        Box::pin(stream)
     }
 }
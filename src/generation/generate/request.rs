@@ -49,6 +49,12 @@ pub struct GenerateRequest {
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub debug_render_only: Option<bool>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<bool>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_logprobs: Option<u32>,
 }
 
 #[allow(clippy::doc_markdown)]
@@ -71,6 +77,8 @@ impl GenerateRequest {
             truncate: None,
             shift: None,
             debug_render_only: None,
+            logprobs: None,
+            top_logprobs: None,
         }
     }
 
@@ -173,4 +181,19 @@ impl GenerateRequest {
         self.debug_render_only = Some(debug_render_only);
         self
     }
+
+    /// Logprobs specifies whether to return the log probabilities of the output tokens.
+    #[must_use]
+    pub fn logprobs(mut self, logprobs: bool) -> Self {
+        self.logprobs = Some(logprobs);
+        self
+    }
+
+    /// TopLogprobs is the number of most likely tokens to return at each token position,
+    /// along with their log probabilities. Requires logprobs to be enabled.
+    #[must_use]
+    pub fn top_logprobs(mut self, top_logprobs: u32) -> Self {
+        self.top_logprobs = Some(top_logprobs);
+        self
+    }
 }
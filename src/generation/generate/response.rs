@@ -1,6 +1,6 @@
 // Translation of https://github.com/ollama/ollama/blob/main/api/types.go into Rust
 
-#[derive(Debug, Clone, serde::Deserialize)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct GenerateResponse {
     /// Model name
     pub model: String,
@@ -33,6 +33,9 @@ pub struct GenerateResponse {
     /// Debug information for template rendering
     pub debug_info: Option<DebugInfo>,
 
+    /// Log probabilities of the generated tokens, if requested
+    pub logprobs: Option<Vec<Logprob>>,
+
     // -- metrics below --
     /// Time spent generating the response in nanoseconds
     pub total_duration: Option<u64>,
@@ -53,7 +56,7 @@ pub struct GenerateResponse {
     pub eval_duration: Option<u64>,
 }
 
-#[derive(Debug, Clone, serde::Deserialize)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct DebugInfo {
     /// The rendered template used for generation
     pub rendered_template: String,
@@ -61,3 +64,26 @@ pub struct DebugInfo {
     /// Number of images included in the generation
     pub image_count: Option<u32>,
 }
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct TokenLogprob {
+    /// The text of the token
+    pub token: String,
+
+    /// The log probability of the token
+    pub logprob: f64,
+
+    /// The raw bytes of the token
+    #[serde(default)]
+    pub bytes: Vec<u8>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Logprob {
+    #[serde(flatten)]
+    pub token: TokenLogprob,
+
+    /// The most likely tokens at this position, if requested
+    #[serde(default)]
+    pub top_logprobs: Vec<TokenLogprob>,
+}
@@ -0,0 +1,309 @@
+use std::{
+    collections::HashMap,
+    iter::Sum,
+    ops::{Add, AddAssign},
+    time::{Duration, Instant},
+};
+
+use crate::generation::{
+    chat::response::ChatResponse, embed::response::EmbedResponse,
+    generate::response::GenerateResponse,
+};
+
+/// Performance metrics of one or more requests.
+///
+/// Ollama reports the raw values in nanoseconds on the final response of a request.
+/// `Metrics` can be added together to aggregate many requests, see `ModelMetrics` for
+/// keeping them per model.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Metrics {
+    /// Number of requests these metrics were collected from
+    pub requests: u64,
+
+    /// Time spent generating the response
+    pub total_duration: Duration,
+
+    /// Time spent loading the model
+    pub load_duration: Duration,
+
+    /// Number of input tokens in the prompt
+    pub prompt_eval_count: u64,
+
+    /// Time spent evaluating the prompt, not reported for embeddings
+    pub prompt_eval_duration: Duration,
+
+    /// Number of output tokens generated in the response
+    pub eval_count: u64,
+
+    /// Time spent generating tokens
+    pub eval_duration: Duration,
+
+    /// Sum of the client-side measured times to the first token
+    pub first_token_duration: Duration,
+
+    /// Number of requests that contributed to `first_token_duration`
+    pub first_token_samples: u64,
+}
+
+impl Metrics {
+    fn from_nanos(
+        total_duration: Option<u64>,
+        load_duration: Option<u64>,
+        prompt_eval_count: Option<u64>,
+        prompt_eval_duration: Option<u64>,
+        eval_count: Option<u64>,
+        eval_duration: Option<u64>,
+    ) -> Self {
+        Self {
+            requests: 1,
+            total_duration: Duration::from_nanos(total_duration.unwrap_or_default()),
+            load_duration: Duration::from_nanos(load_duration.unwrap_or_default()),
+            prompt_eval_count: prompt_eval_count.unwrap_or_default(),
+            prompt_eval_duration: Duration::from_nanos(prompt_eval_duration.unwrap_or_default()),
+            eval_count: eval_count.unwrap_or_default(),
+            eval_duration: Duration::from_nanos(eval_duration.unwrap_or_default()),
+            first_token_duration: Duration::ZERO,
+            first_token_samples: 0,
+        }
+    }
+
+    /// Attach a client-side measured time to the first token.
+    #[must_use]
+    pub fn with_time_to_first_token(mut self, time_to_first_token: Duration) -> Self {
+        self.first_token_duration = time_to_first_token;
+        self.first_token_samples = 1;
+        self
+    }
+
+    /// Time spent on the request without loading the model.
+    #[must_use]
+    pub fn generation_duration(&self) -> Duration {
+        self.total_duration.saturating_sub(self.load_duration)
+    }
+
+    /// Prompt evaluation throughput in tokens per second.
+    /// `None` for embeddings, see `prompt_eval_duration`.
+    #[must_use]
+    pub fn prompt_tokens_per_second(&self) -> Option<f64> {
+        Self::per_second(self.prompt_eval_count, self.prompt_eval_duration)
+    }
+
+    /// Generation throughput in tokens per second.
+    #[must_use]
+    pub fn eval_tokens_per_second(&self) -> Option<f64> {
+        Self::per_second(self.eval_count, self.eval_duration)
+    }
+
+    /// Prompt and generated tokens per second of `generation_duration`.
+    /// Useful for embeddings, where Ollama does not report the prompt evaluation time.
+    #[must_use]
+    pub fn generation_tokens_per_second(&self) -> Option<f64> {
+        Self::per_second(
+            self.prompt_eval_count + self.eval_count,
+            self.generation_duration(),
+        )
+    }
+
+    /// Average time to the first token, if it was measured.
+    #[must_use]
+    pub fn time_to_first_token(&self) -> Option<Duration> {
+        let samples = u32::try_from(self.first_token_samples).ok()?;
+        self.first_token_duration.checked_div(samples)
+    }
+
+    /// Average total duration per request.
+    #[must_use]
+    pub fn average_total_duration(&self) -> Option<Duration> {
+        let requests = u32::try_from(self.requests).ok()?;
+        self.total_duration.checked_div(requests)
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn per_second(count: u64, duration: Duration) -> Option<f64> {
+        if duration.is_zero() {
+            return None;
+        }
+
+        Some(count as f64 / duration.as_secs_f64())
+    }
+}
+
+impl Add for Metrics {
+    type Output = Metrics;
+
+    fn add(mut self, rhs: Self) -> Self::Output {
+        self += rhs;
+        self
+    }
+}
+
+impl AddAssign for Metrics {
+    fn add_assign(&mut self, rhs: Self) {
+        self.requests += rhs.requests;
+        self.total_duration += rhs.total_duration;
+        self.load_duration += rhs.load_duration;
+        self.prompt_eval_count += rhs.prompt_eval_count;
+        self.prompt_eval_duration += rhs.prompt_eval_duration;
+        self.eval_count += rhs.eval_count;
+        self.eval_duration += rhs.eval_duration;
+        self.first_token_duration += rhs.first_token_duration;
+        self.first_token_samples += rhs.first_token_samples;
+    }
+}
+
+impl Sum for Metrics {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Metrics::default(), Add::add)
+    }
+}
+
+/// A response that carries performance metrics.
+pub trait MetricsSource {
+    /// Metrics of the request, only available on the final response.
+    fn metrics(&self) -> Option<Metrics>;
+
+    /// True if the response contains generated output.
+    fn has_output(&self) -> bool;
+}
+
+impl MetricsSource for ChatResponse {
+    fn metrics(&self) -> Option<Metrics> {
+        if !self.done {
+            return None;
+        }
+
+        Some(Metrics::from_nanos(
+            self.total_duration,
+            self.load_duration,
+            self.prompt_eval_count,
+            self.prompt_eval_duration,
+            self.eval_count,
+            self.eval_duration,
+        ))
+    }
+
+    fn has_output(&self) -> bool {
+        !self.message.content.is_empty()
+            || self
+                .message
+                .thinking
+                .as_ref()
+                .is_some_and(|t| !t.is_empty())
+            || !self.message.tool_calls.is_empty()
+    }
+}
+
+impl MetricsSource for GenerateResponse {
+    fn metrics(&self) -> Option<Metrics> {
+        if !self.done {
+            return None;
+        }
+
+        Some(Metrics::from_nanos(
+            self.total_duration,
+            self.load_duration,
+            self.prompt_eval_count,
+            self.prompt_eval_duration,
+            self.eval_count,
+            self.eval_duration,
+        ))
+    }
+
+    fn has_output(&self) -> bool {
+        !self.response.is_empty() || self.thinking.as_ref().is_some_and(|t| !t.is_empty())
+    }
+}
+
+impl MetricsSource for EmbedResponse {
+    /// Ollama does not report the prompt evaluation time for embeddings, so
+    /// `prompt_eval_duration` stays zero, see `Metrics::generation_tokens_per_second`.
+    fn metrics(&self) -> Option<Metrics> {
+        Some(Metrics::from_nanos(
+            self.total_duration,
+            self.load_duration,
+            self.prompt_eval_count,
+            None,
+            None,
+            None,
+        ))
+    }
+
+    fn has_output(&self) -> bool {
+        !self.embeddings.is_empty()
+    }
+}
+
+/// Measures the client-side time to the first token of a streamed response.
+///
+/// Start it right before sending the request and pass every received response to `observe`.
+#[derive(Debug, Clone, Copy)]
+pub struct MetricsTimer {
+    started_at: Instant,
+    first_token: Option<Duration>,
+}
+
+impl Default for MetricsTimer {
+    fn default() -> Self {
+        Self::start()
+    }
+}
+
+impl MetricsTimer {
+    #[must_use]
+    pub fn start() -> Self {
+        Self {
+            started_at: Instant::now(),
+            first_token: None,
+        }
+    }
+
+    /// Record a received response.
+    /// Returns the metrics of the request, including the time to the first token,
+    /// once the final response is observed.
+    pub fn observe<R: MetricsSource>(&mut self, response: &R) -> Option<Metrics> {
+        if self.first_token.is_none() && response.has_output() {
+            self.first_token = Some(self.started_at.elapsed());
+        }
+
+        let metrics = response.metrics()?;
+        Some(match self.first_token {
+            Some(first_token) => metrics.with_time_to_first_token(first_token),
+            None => metrics,
+        })
+    }
+
+    /// Time to the first token, if one was received yet.
+    #[must_use]
+    pub fn time_to_first_token(&self) -> Option<Duration> {
+        self.first_token
+    }
+}
+
+/// Aggregated `Metrics` keyed by model name.
+#[derive(Debug, Clone, Default)]
+pub struct ModelMetrics {
+    inner: HashMap<String, Metrics>,
+}
+
+impl ModelMetrics {
+    /// Add the metrics of a request to the totals of `model`.
+    pub fn record<S: Into<String>>(&mut self, model: S, metrics: Metrics) {
+        *self.inner.entry(model.into()).or_default() += metrics;
+    }
+
+    /// Aggregated metrics of `model`, if any were recorded.
+    #[must_use]
+    pub fn get(&self, model: &str) -> Option<&Metrics> {
+        self.inner.get(model)
+    }
+
+    /// Aggregated metrics over all models.
+    #[must_use]
+    pub fn total(&self) -> Metrics {
+        self.inner.values().copied().sum()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Metrics)> {
+        self.inner.iter()
+    }
+}
@@ -1,6 +1,12 @@
 pub mod chat;
 pub mod embed;
 pub mod generate;
+pub mod metrics;
 pub mod parameters;
 pub mod rerank;
+pub mod response_cache;
+pub mod semantic_cache;
+pub mod stream;
+pub mod think;
+pub mod tokenize;
 pub mod tools;
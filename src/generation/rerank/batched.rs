@@ -0,0 +1,276 @@
+use futures_util::stream::{self, StreamExt, TryStreamExt};
+
+use crate::{
+    generation::rerank::{
+        request::RerankRequest,
+        response::{RerankResponse, RerankResponseUsage},
+    },
+    llama::Llama,
+    splitter::{CharEstimate, TokenCounter},
+};
+
+/// Options for `Llama::rerank_batched`.
+#[derive(Debug, Clone)]
+pub struct BatchRerankOptions {
+    /// Documents per request
+    pub max_documents: usize,
+
+    /// Estimated tokens per request, counting the query once per document.
+    /// Should not exceed the server's `--batch-size`.
+    pub max_batch_tokens: usize,
+
+    /// Requests in flight at the same time
+    pub concurrency: usize,
+
+    /// Used to estimate the tokens of the query and the documents
+    pub estimate: CharEstimate,
+}
+
+impl Default for BatchRerankOptions {
+    /// 32 documents or 2048 tokens per request, llama-server's default batch size,
+    /// and 4 requests in flight.
+    fn default() -> Self {
+        Self {
+            max_documents: 32,
+            max_batch_tokens: 2048,
+            concurrency: 4,
+            estimate: CharEstimate::default(),
+        }
+    }
+}
+
+impl BatchRerankOptions {
+    /// Documents per request.
+    #[must_use]
+    pub fn max_documents(mut self, max_documents: usize) -> Self {
+        self.max_documents = max_documents;
+        self
+    }
+
+    /// Estimated tokens per request.
+    #[must_use]
+    pub fn max_batch_tokens(mut self, max_batch_tokens: usize) -> Self {
+        self.max_batch_tokens = max_batch_tokens;
+        self
+    }
+
+    /// Requests in flight at the same time.
+    #[must_use]
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency;
+        self
+    }
+
+    /// Characters per token for the estimate.
+    #[must_use]
+    pub fn chars_per_token(mut self, chars_per_token: f32) -> Self {
+        self.estimate = CharEstimate { chars_per_token };
+        self
+    }
+
+    /// Split `documents` into `(offset, len)` batches within the limits.
+    /// A single document over `max_batch_tokens` gets a batch of its own.
+    fn batches(&self, query: &str, documents: &[String]) -> Vec<(usize, usize)> {
+        let query_tokens = self.estimate.count(query, 0..query.len());
+        let max_documents = self.max_documents.max(1);
+
+        let mut batches = Vec::new();
+        let mut start = 0;
+        let mut tokens = 0;
+
+        for (i, document) in documents.iter().enumerate() {
+            let document_tokens = query_tokens + self.estimate.count(document, 0..document.len());
+            let len = i - start;
+
+            if len > 0 && (len == max_documents || tokens + document_tokens > self.max_batch_tokens)
+            {
+                batches.push((start, len));
+                start = i;
+                tokens = 0;
+            }
+            tokens += document_tokens;
+        }
+
+        if start < documents.len() {
+            batches.push((start, documents.len() - start));
+        }
+
+        batches
+    }
+}
+
+impl Llama {
+    /// Llama.cpp `/rerank` endpoint for more documents than fit into one request.
+    ///
+    /// The documents are split into batches within `options`, which are reranked
+    /// concurrently. Relevance scores are per query and document, so the results are merged
+    /// into one ranking with indices into `request.documents`. `request.top_n` applies to the
+    /// merged ranking.
+    ///
+    /// # Errors
+    ///
+    /// Same as `Llama::rerank`, for the first batch that fails.
+    pub async fn rerank_batched(
+        &self,
+        request: RerankRequest,
+        options: &BatchRerankOptions,
+    ) -> crate::Result<RerankResponse> {
+        let batches = options.batches(&request.query, &request.documents);
+
+        let requests = batches.into_iter().map(|(offset, len)| {
+            let batch = RerankRequest::new(
+                request.model.as_str(),
+                request.query.as_str(),
+                request.documents[offset..offset + len].to_vec(),
+            );
+
+            async move { crate::Result::Ok((offset, self.rerank(batch).await?)) }
+        });
+
+        let responses = stream::iter(requests)
+            .buffer_unordered(options.concurrency.max(1))
+            .try_collect::<Vec<_>>()
+            .await?;
+
+        Ok(merge(request.model, responses, request.top_n))
+    }
+}
+
+/// One ranking from `(offset, response)` batches, which finish in any order.
+/// Ties are broken by index, so the result does not depend on that order.
+fn merge(
+    model: String,
+    responses: Vec<(usize, RerankResponse)>,
+    top_n: Option<usize>,
+) -> RerankResponse {
+    let mut merged = RerankResponse {
+        model,
+        usage: RerankResponseUsage {
+            prompt_tokens: 0,
+            total_tokens: 0,
+        },
+        results: Vec::new(),
+    };
+
+    for (offset, response) in responses {
+        merged.model = response.model;
+        merged.usage.prompt_tokens += response.usage.prompt_tokens;
+        merged.usage.total_tokens += response.usage.total_tokens;
+        merged
+            .results
+            .extend(response.results.into_iter().map(|mut item| {
+                item.index += offset;
+                item
+            }));
+    }
+
+    merged.results.sort_by(|a, b| {
+        b.relevance_score
+            .total_cmp(&a.relevance_score)
+            .then(a.index.cmp(&b.index))
+    });
+    if let Some(top_n) = top_n {
+        merged.results.truncate(top_n);
+    }
+
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generation::rerank::response::RerankResponseItem;
+
+    fn documents(lens: &[usize]) -> Vec<String> {
+        lens.iter().map(|&len| "x".repeat(len)).collect()
+    }
+
+    /// One token per character, so a document costs its length plus one for the query.
+    fn options() -> BatchRerankOptions {
+        BatchRerankOptions::default()
+            .chars_per_token(1.0)
+            .max_batch_tokens(10)
+    }
+
+    fn response(model: &str, scores: &[(usize, f32)]) -> RerankResponse {
+        RerankResponse {
+            model: model.to_string(),
+            usage: RerankResponseUsage {
+                prompt_tokens: scores.len(),
+                total_tokens: 2 * scores.len(),
+            },
+            results: scores
+                .iter()
+                .map(|&(index, relevance_score)| RerankResponseItem {
+                    index,
+                    relevance_score,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn batches_stay_within_the_token_budget() {
+        let batches = options().batches("q", &documents(&[4, 4, 2, 1, 1, 1]));
+
+        assert_eq!(batches, [(0, 2), (2, 4)]);
+    }
+
+    #[test]
+    fn batches_stay_within_the_document_limit() {
+        let options = options().max_batch_tokens(usize::MAX).max_documents(2);
+        assert_eq!(
+            options.batches("q", &documents(&[1; 5])),
+            [(0, 2), (2, 2), (4, 1)]
+        );
+
+        let options = options.max_documents(0);
+        assert_eq!(options.batches("q", &documents(&[1; 2])), [(0, 1), (1, 1)]);
+    }
+
+    #[test]
+    fn oversized_document_gets_its_own_batch() {
+        let batches = options().batches("q", &documents(&[2, 20, 2, 30]));
+
+        assert_eq!(batches, [(0, 1), (1, 1), (2, 1), (3, 1)]);
+        assert!(options().batches("q", &[]).is_empty());
+    }
+
+    #[test]
+    fn merged_indices_point_into_all_documents() {
+        // The second batch finished first
+        let responses = vec![
+            (3, response("model", &[(1, 0.9), (0, 0.2)])),
+            (0, response("model", &[(2, 0.8), (0, 0.5), (1, 0.1)])),
+        ];
+
+        let merged = merge("requested".to_string(), responses, None);
+        let indices = merged.results.iter().map(|r| r.index).collect::<Vec<_>>();
+        assert_eq!(indices, [4, 2, 0, 3, 1]);
+        assert_eq!(merged.model, "model");
+        assert_eq!(
+            (merged.usage.prompt_tokens, merged.usage.total_tokens),
+            (5, 10)
+        );
+    }
+
+    #[test]
+    fn merged_ties_and_top_n_do_not_depend_on_batch_order() {
+        let batches = [
+            (0, response("model", &[(0, 0.5), (1, 0.1)])),
+            (2, response("model", &[(0, 0.5), (1, 0.5)])),
+        ];
+
+        let forward = merge("model".to_string(), batches.to_vec(), Some(2));
+        let mut reversed = batches.to_vec();
+        reversed.reverse();
+        let reversed = merge("model".to_string(), reversed, Some(2));
+
+        for merged in [forward, reversed] {
+            let indices = merged.results.iter().map(|r| r.index).collect::<Vec<_>>();
+            assert_eq!(indices, [0, 2]);
+        }
+
+        assert!(merge("model".to_string(), vec![], None).results.is_empty());
+    }
+}
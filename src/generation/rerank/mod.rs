@@ -3,7 +3,10 @@ use crate::{
     llama::Llama,
 };
 
+pub mod batched;
+pub mod ranked;
 pub mod request;
+pub mod reranker;
 pub mod response;
 
 impl Llama {
@@ -14,16 +17,9 @@ impl Llama {
     /// If Llama.cpp rejects the request, e.g. the Model does not support reranking.
     /// If the response cannot be parsed.
     pub async fn rerank(&self, request: RerankRequest) -> crate::Result<RerankResponse> {
-        let url = self.url.join("/rerank")?;
-        let response = self.client.post(url).json(&request).send().await?;
-
-        if !response.status().is_success() {
-            return Err(crate::OllamaError::Other(format!(
-                "Error {}:\n{}",
-                response.status(),
-                response.text().await.unwrap_or_default()
-            )));
-        }
+        let url = self.url.join("rerank")?;
+        let _permit = self.acquire(&request.model).await?;
+        let response = self.send(self.client.post(url).json(&request)).await?;
 
         Ok(response.json::<RerankResponse>().await?)
     }
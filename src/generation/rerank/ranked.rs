@@ -0,0 +1,288 @@
+use crate::{
+    generation::rerank::{
+        request::RerankRequest,
+        response::{RerankResponse, RerankResponseItem},
+    },
+    llama::Llama,
+};
+
+/// A document with its rerank score and its position in the documents that were sent.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ranked<T> {
+    pub document: T,
+    pub score: f32,
+    pub index: usize,
+}
+
+/// How relevance scores are rescaled before the threshold is applied.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ScoreNormalization {
+    /// Raw scores as returned by the reranker
+    #[default]
+    None,
+
+    /// `1 / (1 + e^-score)`, maps logits into 0..1 independent of the other results
+    Sigmoid,
+
+    /// Rescale to 0..1 relative to the lowest and highest score of this response
+    MinMax,
+}
+
+impl ScoreNormalization {
+    /// Normalize the scores of `items` in place.
+    pub fn apply(self, items: &mut [RerankResponseItem]) {
+        match self {
+            Self::None => {}
+            Self::Sigmoid => {
+                for item in items {
+                    item.relevance_score = 1.0 / (1.0 + (-item.relevance_score).exp());
+                }
+            }
+            Self::MinMax => {
+                let (min, max) = items.iter().fold((f32::MAX, f32::MIN), |(min, max), item| {
+                    (min.min(item.relevance_score), max.max(item.relevance_score))
+                });
+
+                let range = max - min;
+                for item in items {
+                    item.relevance_score = if range > 0.0 {
+                        (item.relevance_score - min) / range
+                    } else {
+                        1.0
+                    };
+                }
+            }
+        }
+    }
+}
+
+/// Options for `RerankResponse::rank` and `Llama::rerank_documents`.
+#[derive(Debug, Clone, Default)]
+pub struct RankOptions {
+    pub normalization: ScoreNormalization,
+
+    /// Drop documents scoring below this, after normalization
+    pub threshold: Option<f32>,
+
+    /// Keep at most this many documents
+    pub top_n: Option<usize>,
+}
+
+impl RankOptions {
+    #[must_use]
+    pub fn normalization(mut self, normalization: ScoreNormalization) -> Self {
+        self.normalization = normalization;
+        self
+    }
+
+    /// Drop documents scoring below `threshold`, after normalization.
+    #[must_use]
+    pub fn threshold(mut self, threshold: f32) -> Self {
+        self.threshold = Some(threshold);
+        self
+    }
+
+    /// Keep at most `n` documents.
+    #[must_use]
+    pub fn top_n(mut self, n: usize) -> Self {
+        self.top_n = Some(n);
+        self
+    }
+}
+
+impl RerankResponse {
+    /// The documents that were sent, best first, with normalized scores.
+    ///
+    /// `documents` must be in the order of `RerankRequest::documents`.
+    /// Results pointing outside of `documents` are skipped.
+    #[must_use]
+    pub fn rank<'a, T>(&self, documents: &'a [T], options: &RankOptions) -> Vec<Ranked<&'a T>> {
+        let mut results = self
+            .results
+            .iter()
+            .filter(|item| item.index < documents.len())
+            .cloned()
+            .collect::<Vec<_>>();
+
+        options.normalization.apply(&mut results);
+        results.sort_by(|a, b| b.relevance_score.total_cmp(&a.relevance_score));
+
+        if let Some(threshold) = options.threshold {
+            results.retain(|item| item.relevance_score >= threshold);
+        }
+        if let Some(top_n) = options.top_n {
+            results.truncate(top_n);
+        }
+
+        results
+            .into_iter()
+            .map(|item| Ranked {
+                document: &documents[item.index],
+                score: item.relevance_score,
+                index: item.index,
+            })
+            .collect()
+    }
+
+    /// Like `rank`, but takes ownership of the documents.
+    #[must_use]
+    pub fn rank_owned<T>(&self, documents: Vec<T>, options: &RankOptions) -> Vec<Ranked<T>> {
+        let order = self
+            .rank(&documents, options)
+            .into_iter()
+            .map(|ranked| (ranked.index, ranked.score))
+            .collect::<Vec<_>>();
+
+        let mut documents = documents.into_iter().map(Some).collect::<Vec<_>>();
+        order
+            .into_iter()
+            .filter_map(|(index, score)| {
+                documents[index].take().map(|document| Ranked {
+                    document,
+                    score,
+                    index,
+                })
+            })
+            .collect()
+    }
+}
+
+impl Llama {
+    /// Rerank any kind of document, `text` extracts what is sent to the reranker.
+    ///
+    /// Returns the documents best first, see `RerankResponse::rank`.
+    ///
+    /// # Errors
+    ///
+    /// Same as `Llama::rerank`.
+    pub async fn rerank_documents<T, F>(
+        &self,
+        model: &str,
+        query: &str,
+        documents: Vec<T>,
+        text: F,
+        options: &RankOptions,
+    ) -> crate::Result<Vec<Ranked<T>>>
+    where
+        F: Fn(&T) -> &str,
+    {
+        if documents.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let texts = documents.iter().map(|d| text(d).to_string()).collect();
+        let response = self.rerank(RerankRequest::new(model, query, texts)).await?;
+
+        Ok(response.rank_owned(documents, options))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generation::rerank::response::RerankResponseUsage;
+
+    fn response(scores: &[(usize, f32)]) -> RerankResponse {
+        RerankResponse {
+            model: "model".to_string(),
+            usage: RerankResponseUsage {
+                prompt_tokens: 0,
+                total_tokens: 0,
+            },
+            results: scores
+                .iter()
+                .map(|&(index, relevance_score)| RerankResponseItem {
+                    index,
+                    relevance_score,
+                })
+                .collect(),
+        }
+    }
+
+    fn documents() -> Vec<String> {
+        ["a", "b", "c", "d"].map(String::from).to_vec()
+    }
+
+    fn order<T>(ranked: &[Ranked<T>]) -> Vec<usize> {
+        ranked.iter().map(|r| r.index).collect()
+    }
+
+    #[test]
+    fn best_first_whatever_the_response_order() {
+        let response = response(&[(2, 0.1), (0, 3.0), (3, -1.0), (1, 0.5)]);
+        let documents = documents();
+
+        let ranked = response.rank(&documents, &RankOptions::default());
+        assert_eq!(order(&ranked), [0, 1, 2, 3]);
+        assert_eq!(ranked[0].document, "a");
+        assert!((ranked[3].score + 1.0).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn threshold_and_top_n() {
+        let response = response(&[(2, 0.1), (0, 3.0), (3, -1.0), (1, 0.5)]);
+        let documents = documents();
+
+        let options = RankOptions::default().threshold(0.2);
+        assert_eq!(order(&response.rank(&documents, &options)), [0, 1]);
+
+        let options = RankOptions::default().top_n(3);
+        assert_eq!(order(&response.rank(&documents, &options)), [0, 1, 2]);
+
+        // Sorted before truncating, so the best one is kept
+        let options = options.threshold(0.2).top_n(1);
+        assert_eq!(order(&response.rank(&documents, &options)), [0]);
+
+        let options = RankOptions::default().top_n(0);
+        assert!(response.rank(&documents, &options).is_empty());
+    }
+
+    #[test]
+    fn threshold_applies_after_normalization() {
+        let response = response(&[(0, 0.0), (1, 4.0), (2, 2.0)]);
+        let documents = documents();
+
+        let options = RankOptions::default()
+            .normalization(ScoreNormalization::MinMax)
+            .threshold(0.5);
+        let ranked = response.rank(&documents, &options);
+        assert_eq!(order(&ranked), [1, 2]);
+        assert!((ranked[0].score - 1.0).abs() < f32::EPSILON);
+        assert!((ranked[1].score - 0.5).abs() < f32::EPSILON);
+
+        let options = RankOptions::default()
+            .normalization(ScoreNormalization::Sigmoid)
+            .threshold(0.5);
+        let ranked = response.rank(&documents, &options);
+        assert_eq!(order(&ranked), [1, 2, 0]);
+        assert!((ranked[2].score - 0.5).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn equal_scores_are_all_the_best() {
+        let mut items = response(&[(0, 2.0), (1, 2.0)]).results;
+        ScoreNormalization::MinMax.apply(&mut items);
+
+        assert!(
+            items
+                .iter()
+                .all(|i| (i.relevance_score - 1.0).abs() < f32::EPSILON)
+        );
+    }
+
+    #[test]
+    fn unknown_and_repeated_indices() {
+        let response = response(&[(7, 9.0), (1, 2.0), (1, 1.0), (0, 0.0)]);
+
+        let documents = documents();
+
+        let ranked = response.rank(&documents, &RankOptions::default());
+        assert_eq!(order(&ranked), [1, 1, 0]);
+
+        // A document can only be moved out once
+        let ranked = response.rank_owned(documents, &RankOptions::default());
+        assert_eq!(order(&ranked), [1, 0]);
+        assert_eq!(ranked[0].document, "b");
+        assert!((ranked[0].score - 2.0).abs() < f32::EPSILON);
+    }
+}
@@ -0,0 +1,344 @@
+use std::pin::Pin;
+
+use futures_util::stream::{self, StreamExt, TryStreamExt};
+
+use crate::{
+    generation::{
+        generate::{request::GenerateRequest, response::GenerateResponse},
+        parameters::Think,
+        rerank::{
+            batched::BatchRerankOptions,
+            request::RerankRequest,
+            response::{RerankResponse, RerankResponseItem, RerankResponseUsage},
+        },
+    },
+    llama::Llama,
+    model::ModelOptions,
+    ollama::Ollama,
+};
+
+pub type RerankFuture<'a> =
+    Pin<Box<dyn Future<Output = crate::Result<RerankResponse>> + Send + 'a>>;
+
+/// A backend that scores documents by relevance to a query.
+///
+/// Results are sorted best first, with indices into `documents`, so
+/// `RerankResponse::rank` maps them back to the documents.
+pub trait Reranker: Send + Sync {
+    fn rerank<'a>(&'a self, query: &'a str, documents: &'a [String]) -> RerankFuture<'a>;
+}
+
+impl std::fmt::Debug for dyn Reranker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Reranker").finish_non_exhaustive()
+    }
+}
+
+/// Reranks with llama.cpp's `/rerank` endpoint and a reranking model.
+#[derive(Debug, Clone)]
+pub struct LlamaReranker {
+    llama: Llama,
+    model: String,
+    batch: Option<BatchRerankOptions>,
+}
+
+impl LlamaReranker {
+    pub fn new<S: Into<String>>(llama: Llama, model: S) -> Self {
+        Self {
+            llama,
+            model: model.into(),
+            batch: None,
+        }
+    }
+
+    /// Split large candidate sets into batches, see `Llama::rerank_batched`.
+    #[must_use]
+    pub fn batched(mut self, options: BatchRerankOptions) -> Self {
+        self.batch = Some(options);
+        self
+    }
+}
+
+impl Reranker for LlamaReranker {
+    fn rerank<'a>(&'a self, query: &'a str, documents: &'a [String]) -> RerankFuture<'a> {
+        Box::pin(async move {
+            let request = RerankRequest::new(self.model.as_str(), query, documents.to_vec());
+
+            if let Some(options) = &self.batch {
+                return self.llama.rerank_batched(request, options).await;
+            }
+
+            let mut response = self.llama.rerank(request).await?;
+            response
+                .results
+                .sort_by(|a, b| b.relevance_score.total_cmp(&a.relevance_score));
+            Ok(response)
+        })
+    }
+}
+
+/// How `OllamaReranker` turns a generation into a relevance score.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RelevanceScoring {
+    /// Ask for yes or no and score by the probability of yes, 0..1.
+    /// Falls back to the answer itself if neither token is in the top logprobs.
+    #[default]
+    Logprobs,
+
+    /// Ask for a number from 0 to 10 and parse it, scaled to 0..1.
+    /// Answers without a number score 0.
+    Numeric,
+}
+
+/// Reranks with a chat model over Ollama's `/api/generate`, one request per document.
+///
+/// Slower than a reranking model, but works with any model Ollama serves.
+#[derive(Debug, Clone)]
+pub struct OllamaReranker {
+    ollama: Ollama,
+    model: String,
+    scoring: RelevanceScoring,
+    concurrency: usize,
+}
+
+impl OllamaReranker {
+    /// Logprobs scoring with 4 requests in flight by default.
+    pub fn new<S: Into<String>>(ollama: Ollama, model: S) -> Self {
+        Self {
+            ollama,
+            model: model.into(),
+            scoring: RelevanceScoring::default(),
+            concurrency: 4,
+        }
+    }
+
+    #[must_use]
+    pub fn scoring(mut self, scoring: RelevanceScoring) -> Self {
+        self.scoring = scoring;
+        self
+    }
+
+    /// Requests in flight at the same time.
+    #[must_use]
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency;
+        self
+    }
+
+    fn request(&self, query: &str, document: &str) -> GenerateRequest {
+        let (question, num_predict) = match self.scoring {
+            RelevanceScoring::Logprobs => (
+                "Is the document relevant to the query? Answer only yes or no.",
+                1,
+            ),
+            RelevanceScoring::Numeric => (
+                "How relevant is the document to the query, from 0 (unrelated) to 10 \
+                (answers it completely)? Answer only with the number.",
+                4,
+            ),
+        };
+
+        let request = GenerateRequest::new(
+            self.model.clone(),
+            format!("Query: {query}\n\nDocument: {document}\n\n{question}"),
+        )
+        .stream(false)
+        .think(Think::Disabled)
+        .options(
+            ModelOptions::default()
+                .temperature(0.0)
+                .num_predict(num_predict),
+        );
+
+        match self.scoring {
+            RelevanceScoring::Logprobs => request.logprobs(true).top_logprobs(20),
+            RelevanceScoring::Numeric => request,
+        }
+    }
+
+    fn score(&self, response: &GenerateResponse) -> f32 {
+        match self.scoring {
+            RelevanceScoring::Logprobs => yes_probability(response),
+            RelevanceScoring::Numeric => parse_score(&response.response),
+        }
+    }
+}
+
+impl Reranker for OllamaReranker {
+    fn rerank<'a>(&'a self, query: &'a str, documents: &'a [String]) -> RerankFuture<'a> {
+        Box::pin(async move {
+            let requests = documents
+                .iter()
+                .map(|document| self.request(query, document))
+                .collect::<Vec<_>>();
+
+            let responses = stream::iter(requests.into_iter().enumerate())
+                .map(|(index, request)| async move {
+                    let response = self.ollama.generate_without_stream(request).await?;
+                    crate::Result::Ok((index, response))
+                })
+                .buffer_unordered(self.concurrency.max(1))
+                .try_collect::<Vec<_>>()
+                .await?;
+
+            let mut usage = RerankResponseUsage {
+                prompt_tokens: 0,
+                total_tokens: 0,
+            };
+            let mut results = Vec::with_capacity(responses.len());
+
+            for (index, response) in responses {
+                let prompt_tokens = response.prompt_eval_count.unwrap_or(0);
+                let eval_tokens = response.eval_count.unwrap_or(0);
+                usage.prompt_tokens += usize::try_from(prompt_tokens).unwrap_or(usize::MAX);
+                usage.total_tokens +=
+                    usize::try_from(prompt_tokens + eval_tokens).unwrap_or(usize::MAX);
+
+                results.push(RerankResponseItem {
+                    index,
+                    relevance_score: self.score(&response),
+                });
+            }
+
+            results.sort_by(|a, b| b.relevance_score.total_cmp(&a.relevance_score));
+
+            Ok(RerankResponse {
+                model: self.model.clone(),
+                usage,
+                results,
+            })
+        })
+    }
+}
+
+/// `P(yes) / (P(yes) + P(no))` over the top logprobs of the first token.
+fn yes_probability(response: &GenerateResponse) -> f32 {
+    let (mut yes, mut no) = (0.0, 0.0);
+
+    // The top logprobs include the sampled token, if there are any
+    let candidates = match response.logprobs.as_deref() {
+        Some([first, ..]) if first.top_logprobs.is_empty() => std::slice::from_ref(&first.token),
+        Some([first, ..]) => first.top_logprobs.as_slice(),
+        _ => &[],
+    };
+
+    for candidate in candidates {
+        match candidate.token.trim().to_lowercase().as_str() {
+            "yes" => yes += candidate.logprob.exp(),
+            "no" => no += candidate.logprob.exp(),
+            _ => {}
+        }
+    }
+
+    if yes + no > 0.0 {
+        // A probability, the precision of f32 is plenty
+        #[allow(clippy::cast_possible_truncation)]
+        let score = (yes / (yes + no)) as f32;
+        return score;
+    }
+
+    if response.response.trim().to_lowercase().starts_with("yes") {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+/// First number in `answer`, clamped to 0..10 and scaled to 0..1.
+fn parse_score(answer: &str) -> f32 {
+    let number = answer
+        .split(|c: char| !c.is_ascii_digit() && c != '.')
+        .find_map(|s| s.parse::<f32>().ok());
+
+    number.map_or(0.0, |n| n.clamp(0.0, 10.0) / 10.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn response(text: &str, logprobs: serde_json::Value) -> GenerateResponse {
+        serde_json::from_value(json!({
+            "model": "model",
+            "created_at": "",
+            "response": text,
+            "done": true,
+            "logprobs": logprobs,
+        }))
+        .unwrap()
+    }
+
+    /// Logprobs of a first token with `candidates` as top logprobs.
+    fn top(candidates: &[(&str, f64)]) -> serde_json::Value {
+        let candidates = candidates
+            .iter()
+            .map(|(token, logprob)| json!({ "token": token, "logprob": logprob }))
+            .collect::<Vec<_>>();
+
+        json!([{ "token": candidates[0]["token"], "logprob": -0.1, "top_logprobs": candidates }])
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 1e-5, "{actual} != {expected}");
+    }
+
+    #[test]
+    fn yes_and_no_variants_are_summed() {
+        let half = 0.5f64.ln();
+        let quarter = 0.25f64.ln();
+
+        let score = yes_probability(&response(
+            "Yes",
+            top(&[
+                ("Yes", quarter),
+                (" yes", quarter),
+                ("NO", quarter),
+                ("maybe", -9.0),
+            ]),
+        ));
+        assert_close(score, 2.0 / 3.0);
+
+        let score = yes_probability(&response("no", top(&[(" No\n", half), ("YES ", quarter)])));
+        assert_close(score, 1.0 / 3.0);
+    }
+
+    #[test]
+    fn sampled_token_is_used_without_top_logprobs() {
+        let logprobs = json!([{ "token": " yes", "logprob": -0.2 }]);
+        assert_close(yes_probability(&response("yes", logprobs)), 1.0);
+
+        let logprobs = json!([{ "token": "No", "logprob": -0.2 }]);
+        assert_close(yes_probability(&response("No", logprobs)), 0.0);
+    }
+
+    #[test]
+    fn answer_is_used_without_yes_or_no_logprobs() {
+        let logprobs = top(&[("Maybe", -0.1)]);
+        assert_close(yes_probability(&response(" Yes, it is", logprobs)), 1.0);
+
+        assert_close(yes_probability(&response("YES", json!(null))), 1.0);
+        assert_close(yes_probability(&response("Nope", json!([]))), 0.0);
+    }
+
+    #[test]
+    fn scores_are_scaled_to_one() {
+        let cases = [
+            ("7", 0.7),
+            ("Score: 8/10", 0.8),
+            ("10.", 1.0),
+            ("7.5 out of 10", 0.75),
+            ("0", 0.0),
+            ("15", 1.0),
+            ("100 points", 1.0),
+            (".", 0.0),
+            ("no score", 0.0),
+            ("", 0.0),
+        ];
+
+        for (answer, expected) in cases {
+            assert_close(parse_score(answer), expected);
+        }
+    }
+}
@@ -0,0 +1,429 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{
+        Arc, Mutex, PoisonError,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use serde::{Serialize, de::DeserializeOwned};
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncWriteExt;
+use tokio_stream::Stream;
+
+use crate::{
+    OllamaError,
+    generation::{embed::cache::CacheStats, stream},
+    store::codec::{Reader, Writer, invalid},
+};
+
+const MAGIC: [u8; 4] = *b"OLRC";
+const VERSION: u8 = 1;
+
+pub(crate) type CacheKey = [u8; 32];
+
+/// Exact match cache in front of Ollama's `/api/chat` and `/api/generate`, see
+/// `Ollama::with_response_cache`.
+///
+/// Entries are keyed by a SHA-256 of the request as sent to Ollama, i.e. model, messages or
+/// prompt, options, format, tools and `stream`, but not `keep_alive` or message timestamps.
+/// A cached request is not sent again, its recorded responses are replayed instead.
+/// Chat requests that call tools are cached per round trip, the tools still run.
+///
+/// Responses are replayed whatever the sampling options. Use it where repeating a request
+/// would give the same answer anyway, e.g. with `ModelOptions::seed` or a temperature of 0.
+///
+/// A cache opened with `open` appends new entries to its file. A failed write does not fail
+/// the request, see `CacheStats::errors`. Entries are never evicted, see `clear`.
+///
+/// This struct uses Arc internally, clones share the same entries.
+#[derive(Clone)]
+pub struct ResponseCache {
+    inner: Arc<ResponseCacheInner>,
+}
+
+struct ResponseCacheInner {
+    path: Option<PathBuf>,
+
+    /// Key to the recorded responses, as a JSON array
+    entries: Mutex<HashMap<CacheKey, String>>,
+    file: tokio::sync::Mutex<()>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    errors: AtomicU64,
+}
+
+impl std::fmt::Debug for ResponseCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ResponseCache")
+            .field("path", &self.inner.path)
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+impl ResponseCache {
+    /// A cache that only lives in memory.
+    #[must_use]
+    pub fn in_memory() -> Self {
+        Self::with_entries(None, HashMap::new())
+    }
+
+    /// Open the cache file at `path`, or create it.
+    ///
+    /// A file that ends in an incomplete entry, e.g. after a crash, is repaired.
+    ///
+    /// # Errors
+    ///
+    /// If the file cannot be read or created.
+    /// If the file is not a cache file.
+    pub async fn open<P: AsRef<Path>>(path: P) -> crate::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+
+        let mut entries = HashMap::new();
+        let mut complete = true;
+
+        match tokio::fs::read(&path).await {
+            Ok(bytes) => {
+                let (mut reader, version) = Reader::new(&bytes, MAGIC)?;
+                if version != VERSION {
+                    return Err(invalid(&format!("unsupported version {version}")));
+                }
+
+                while !reader.is_empty() {
+                    let Ok((key, responses)) = read_entry(&mut reader) else {
+                        complete = false;
+                        break;
+                    };
+
+                    entries.insert(key, responses);
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => complete = false,
+            Err(e) => return Err(e.into()),
+        }
+
+        let cache = Self::with_entries(Some(path), entries);
+        if !complete {
+            cache.compact().await?;
+        }
+
+        Ok(cache)
+    }
+
+    fn with_entries(path: Option<PathBuf>, entries: HashMap<CacheKey, String>) -> Self {
+        Self {
+            inner: Arc::new(ResponseCacheInner {
+                path,
+                entries: Mutex::new(entries),
+                file: tokio::sync::Mutex::new(()),
+                hits: AtomicU64::new(0),
+                misses: AtomicU64::new(0),
+                errors: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    fn entries(&self) -> std::sync::MutexGuard<'_, HashMap<CacheKey, String>> {
+        self.inner
+            .entries
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Current hit rate and size. `bytes` counts the recorded responses as JSON,
+    /// `errors` the entries that could not be written to the file.
+    #[must_use]
+    pub fn stats(&self) -> CacheStats {
+        let entries = self.entries();
+
+        CacheStats {
+            hits: self.inner.hits.load(Ordering::Relaxed),
+            misses: self.inner.misses.load(Ordering::Relaxed),
+            evictions: 0,
+            entries: entries.len(),
+            bytes: entries.values().map(|e| e.len() as u64).sum(),
+            errors: self.inner.errors.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Remove all entries, also from the file.
+    ///
+    /// # Errors
+    ///
+    /// If the file cannot be written.
+    pub async fn clear(&self) -> crate::Result<()> {
+        self.entries().clear();
+        self.compact().await
+    }
+
+    /// Key of `request` sent to `endpoint`. `None` if the request cannot be serialized,
+    /// such a request is not cached.
+    pub(crate) fn key<R: Serialize>(endpoint: &str, request: &R) -> Option<CacheKey> {
+        let mut request = serde_json::to_value(request).ok()?;
+
+        // Neither changes the response
+        if let Some(request) = request.as_object_mut() {
+            request.remove("keep_alive");
+
+            let messages = request
+                .get_mut("messages")
+                .and_then(serde_json::Value::as_array_mut);
+            for message in messages.into_iter().flatten() {
+                if let Some(message) = message.as_object_mut() {
+                    message.remove("created_at");
+                }
+            }
+        }
+
+        let mut hasher = Sha256::new();
+        hasher.update(endpoint.as_bytes());
+        hasher.update([0]);
+        hasher.update(request.to_string().as_bytes());
+
+        Some(hasher.finalize().into())
+    }
+
+    /// The responses recorded for `key`, if any.
+    pub(crate) fn get<T: DeserializeOwned>(&self, key: &CacheKey) -> Option<Vec<T>> {
+        let responses = self
+            .entries()
+            .get(key)
+            .and_then(|responses| serde_json::from_str(responses).ok());
+
+        let counter = match responses {
+            Some(_) => &self.inner.hits,
+            None => &self.inner.misses,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+
+        responses
+    }
+
+    /// Record `responses` for `key`, also in the file. A failed write only counts as error,
+    /// the responses were received anyway.
+    pub(crate) async fn insert<T: Serialize>(&self, key: CacheKey, responses: &[T]) {
+        if self.write(key, responses).await.is_err() {
+            self.inner.errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    async fn write<T: Serialize>(&self, key: CacheKey, responses: &[T]) -> crate::Result<()> {
+        let responses = serde_json::to_string(responses)
+            .map_err(|e| OllamaError::Other(format!("Failed to serialize responses: {e}")))?;
+
+        let mut writer = Writer::default();
+        write_entry(&mut writer, &key, &responses);
+        self.entries().insert(key, responses);
+
+        let Some(path) = &self.inner.path else {
+            return Ok(());
+        };
+
+        let _file = self.inner.file.lock().await;
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+
+        // The file was deleted since it was opened
+        if file.metadata().await?.len() == 0 {
+            file.write_all(&Writer::new(MAGIC, VERSION).into_bytes())
+                .await?;
+        }
+        file.write_all(&writer.into_bytes()).await?;
+        file.flush().await?;
+
+        Ok(())
+    }
+
+    /// Pass `stream` through and record it for `key` once it completed without errors.
+    /// `done` tells whether a response is the last one.
+    pub(crate) fn record<T>(
+        &self,
+        key: CacheKey,
+        stream: Pin<Box<dyn Stream<Item = crate::Result<T>>>>,
+        done: fn(&T) -> bool,
+    ) -> Pin<Box<dyn Stream<Item = crate::Result<T>>>>
+    where
+        T: Serialize + Clone + 'static,
+    {
+        let cache = self.clone();
+
+        stream::record(stream, done, move |responses| async move {
+            cache.insert(key, &responses).await;
+        })
+    }
+
+    /// Rewrite the file with only the current entries.
+    async fn compact(&self) -> crate::Result<()> {
+        let Some(path) = &self.inner.path else {
+            return Ok(());
+        };
+
+        let _file = self.inner.file.lock().await;
+        let bytes = {
+            let entries = self.entries();
+            let mut writer = Writer::new(MAGIC, VERSION);
+            for (key, responses) in entries.iter() {
+                write_entry(&mut writer, key, responses);
+            }
+
+            writer.into_bytes()
+        };
+
+        // Write to a temporary file first, so a crash never leaves a half written cache
+        let temporary = path.with_extension("tmp");
+        tokio::fs::write(&temporary, bytes).await?;
+        tokio::fs::rename(&temporary, path).await?;
+
+        Ok(())
+    }
+}
+
+fn write_entry(writer: &mut Writer, key: &CacheKey, responses: &str) {
+    writer.bytes(key);
+    writer.str(responses);
+}
+
+fn read_entry(reader: &mut Reader<'_>) -> crate::Result<(CacheKey, String)> {
+    let key = reader.bytes()?.try_into().map_err(|_| invalid("bad key"))?;
+
+    Ok((key, reader.str()?.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generation::{
+        chat::{message::Message, request::ChatRequest},
+        parameters::KeepAlive,
+    };
+
+    fn temporary_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("ollama-rust-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn responses(text: &str) -> Vec<String> {
+        vec![text.to_string(), format!("{text} done")]
+    }
+
+    #[tokio::test]
+    async fn entries_survive_reopening() {
+        let path = temporary_path("reopen");
+        let cache = ResponseCache::open(&path).await.unwrap();
+        cache.insert([1; 32], &responses("first")).await;
+        cache.insert([2; 32], &responses("second")).await;
+        drop(cache);
+
+        let cache = ResponseCache::open(&path).await.unwrap();
+        assert_eq!(cache.get::<String>(&[1; 32]), Some(responses("first")));
+        assert_eq!(cache.get::<String>(&[2; 32]), Some(responses("second")));
+        assert_eq!(cache.get::<String>(&[3; 32]), None);
+        assert_eq!(cache.stats().errors, 0);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn truncated_file_is_repaired() {
+        let path = temporary_path("truncated");
+        let cache = ResponseCache::open(&path).await.unwrap();
+        cache.insert([1; 32], &responses("first")).await;
+        cache.insert([2; 32], &responses("second")).await;
+        drop(cache);
+
+        // Cut the last entry in half, as a crash while appending would
+        let len = std::fs::metadata(&path).unwrap().len();
+        let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(len - 10).unwrap();
+
+        let cache = ResponseCache::open(&path).await.unwrap();
+        assert_eq!(cache.get::<String>(&[1; 32]), Some(responses("first")));
+        assert_eq!(cache.get::<String>(&[2; 32]), None);
+
+        // New entries are appended after the repaired ones
+        cache.insert([3; 32], &responses("third")).await;
+        drop(cache);
+
+        let cache = ResponseCache::open(&path).await.unwrap();
+        assert_eq!(cache.get::<String>(&[1; 32]), Some(responses("first")));
+        assert_eq!(cache.get::<String>(&[3; 32]), Some(responses("third")));
+        assert_eq!(cache.stats().entries, 2);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn deleted_file_is_recreated() {
+        let path = temporary_path("deleted");
+        let cache = ResponseCache::open(&path).await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+        cache.insert([1; 32], &responses("first")).await;
+        assert_eq!(cache.stats().errors, 0);
+        drop(cache);
+
+        let cache = ResponseCache::open(&path).await.unwrap();
+        assert_eq!(cache.get::<String>(&[1; 32]), Some(responses("first")));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn other_files_are_rejected() {
+        let path = temporary_path("other");
+        std::fs::write(&path, b"not a cache").unwrap();
+
+        assert!(ResponseCache::open(&path).await.is_err());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn key_ignores_keep_alive_and_timestamps() {
+        let request = |created_at: &str| {
+            ChatRequest::new(
+                "model",
+                vec![
+                    Message::user("question").created_at(created_at),
+                    Message::assistant("answer").created_at(created_at),
+                ],
+            )
+        };
+
+        let key = ResponseCache::key("chat", &request("2025-01-01T00:00:00Z"));
+        assert_eq!(
+            key,
+            ResponseCache::key("chat", &request("2025-06-01T12:00:00Z"))
+        );
+        assert_eq!(
+            key,
+            ResponseCache::key(
+                "chat",
+                &request("2025-01-01T00:00:00Z").keep_alive(KeepAlive::Forever)
+            )
+        );
+
+        assert_ne!(
+            key,
+            ResponseCache::key("generate", &request("2025-01-01T00:00:00Z"))
+        );
+        assert_ne!(
+            key,
+            ResponseCache::key("chat", &request("2025-01-01T00:00:00Z").stream(false))
+        );
+    }
+
+    #[test]
+    fn unserializable_requests_have_no_key() {
+        // JSON object keys have to be strings
+        let request = HashMap::from([((1, 2), "value")]);
+
+        assert_eq!(ResponseCache::key("chat", &request), None);
+    }
+}
@@ -0,0 +1,619 @@
+use std::sync::{
+    Arc, Mutex, PoisonError,
+    atomic::{AtomicU64, Ordering},
+};
+
+use async_stream::stream;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tokio_stream::StreamExt;
+
+use crate::{
+    OllamaError,
+    generation::{
+        chat::{
+            ChatResponseStream,
+            history::History,
+            message::{Message, Role},
+            request::ChatRequest,
+            response::ChatResponse,
+        },
+        embed::{
+            cache::CacheStats,
+            request::EmbedRequest,
+            vector::{dot, normalized},
+        },
+        generate::{GenerateResponseStream, request::GenerateRequest, response::GenerateResponse},
+        stream,
+    },
+    ollama::Ollama,
+};
+
+/// Hash of everything besides the query that changes the answer.
+type Scope = [u8; 32];
+
+/// Cache in front of `Ollama::chat`, `Ollama::generate` and `Ollama::generate_without_stream`
+/// that answers queries similar to ones answered before, see `Ollama::with_semantic_cache`.
+///
+/// The final user message, or the prompt, is embedded with the cache's embedding model.
+/// Its answer is replayed from the most similar cached query with the same model, system
+/// prompt and options, including `format`, `think` and log probabilities, if their cosine
+/// similarity reaches the threshold. When the cache is full, the least recently used entry
+/// is evicted.
+///
+/// Only single questions are cached. Conversations with earlier answers, requests with
+/// tools or images, and generate requests with a `context` always go to Ollama.
+/// So do queries that cannot be embedded, e.g. if the embedding model is missing.
+///
+/// This struct uses Arc internally, clones share the same entries.
+#[derive(Clone)]
+pub struct SemanticCache {
+    inner: Arc<SemanticCacheInner>,
+}
+
+struct SemanticCacheInner {
+    model: String,
+    threshold: f32,
+    max_entries: usize,
+    state: Mutex<SemanticCacheState>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+    errors: AtomicU64,
+}
+
+#[derive(Default)]
+struct SemanticCacheState {
+    entries: Vec<Entry>,
+    tick: u64,
+}
+
+struct Entry {
+    scope: Scope,
+    embedding: Vec<f32>,
+    answer: Answer,
+    last_used: u64,
+}
+
+#[derive(Clone)]
+enum Answer {
+    Chat(Vec<ChatResponse>),
+    Generate(Vec<GenerateResponse>),
+}
+
+impl std::fmt::Debug for SemanticCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SemanticCache")
+            .field("model", &self.inner.model)
+            .field("threshold", &self.inner.threshold)
+            .field("max_entries", &self.inner.max_entries)
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+impl SemanticCache {
+    /// A cache that embeds queries with `embedding_model` and answers those with a cosine
+    /// similarity of at least `threshold` to a cached query, keeping at most `max_entries`.
+    pub fn new<S: Into<String>>(embedding_model: S, threshold: f32, max_entries: usize) -> Self {
+        Self {
+            inner: Arc::new(SemanticCacheInner {
+                model: embedding_model.into(),
+                threshold,
+                max_entries,
+                state: Mutex::new(SemanticCacheState::default()),
+                hits: AtomicU64::new(0),
+                misses: AtomicU64::new(0),
+                evictions: AtomicU64::new(0),
+                errors: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, SemanticCacheState> {
+        self.inner
+            .state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Current hit rate, size and evictions. `bytes` counts the embeddings and answer texts,
+    /// `errors` the queries that could not be embedded.
+    #[must_use]
+    pub fn stats(&self) -> CacheStats {
+        let state = self.state();
+
+        CacheStats {
+            hits: self.inner.hits.load(Ordering::Relaxed),
+            misses: self.inner.misses.load(Ordering::Relaxed),
+            evictions: self.inner.evictions.load(Ordering::Relaxed),
+            entries: state.entries.len(),
+            bytes: state.entries.iter().map(Entry::bytes).sum(),
+            errors: self.inner.errors.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Remove all entries.
+    pub fn clear(&self) {
+        *self.state() = SemanticCacheState::default();
+    }
+
+    /// Like `Ollama::chat`, answered from the cache where possible.
+    pub(crate) fn chat(
+        &self,
+        ollama: Ollama,
+        request: ChatRequest,
+        history: History,
+    ) -> ChatResponseStream {
+        let cache = self.clone();
+
+        Box::pin(stream! {
+            let query = chat_query(&request, &history.messages()?);
+            let Some((scope, embedding)) = cache.prepare(&ollama, query).await else {
+                let mut stream = ollama.chat_uncached(request, history);
+                while let Some(res) = stream.next().await {
+                    yield res;
+                }
+                return;
+            };
+
+            if let Some(Answer::Chat(responses)) = cache.lookup(&scope, &embedding) {
+                history.extend(&request.messages)?;
+                for response in replay(responses, request.stream, merge_chat) {
+                    history.push(&response.message.clone().created_at(response.created_at.clone()))?;
+                    yield Ok(response);
+                }
+                return;
+            }
+
+            let stream = ollama.chat_uncached(request, history);
+            let mut stream = stream::record(stream, |r| r.done, move |responses| async move {
+                cache.insert(scope, embedding, Answer::Chat(responses));
+            });
+            while let Some(res) = stream.next().await {
+                yield res;
+            }
+        })
+    }
+
+    /// Like `Ollama::generate`, answered from the cache where possible.
+    pub(crate) async fn generate(
+        &self,
+        ollama: &Ollama,
+        request: GenerateRequest,
+    ) -> crate::Result<GenerateResponseStream> {
+        let Some((scope, embedding)) = self.prepare(ollama, generate_query(&request)).await else {
+            return ollama.generate_uncached(request).await;
+        };
+
+        if let Some(Answer::Generate(responses)) = self.lookup(&scope, &embedding) {
+            let responses = replay(responses, request.stream, merge_generate);
+            return Ok(Box::pin(tokio_stream::iter(responses.into_iter().map(Ok))));
+        }
+
+        let stream = ollama.generate_uncached(request).await?;
+        let cache = self.clone();
+
+        Ok(stream::record(
+            stream,
+            |r| r.done,
+            move |responses| async move {
+                cache.insert(scope, embedding, Answer::Generate(responses));
+            },
+        ))
+    }
+
+    /// Like `Ollama::generate_without_stream`, answered from the cache where possible.
+    pub(crate) async fn generate_without_stream(
+        &self,
+        ollama: &Ollama,
+        request: GenerateRequest,
+    ) -> crate::Result<GenerateResponse> {
+        let Some((scope, embedding)) = self.prepare(ollama, generate_query(&request)).await else {
+            return ollama.generate_without_stream_uncached(request).await;
+        };
+
+        if let Some(Answer::Generate(responses)) = self.lookup(&scope, &embedding)
+            && let Some(response) = responses.into_iter().reduce(merge_generate)
+        {
+            return Ok(response);
+        }
+
+        let response = ollama.generate_without_stream_uncached(request).await?;
+        self.insert(scope, embedding, Answer::Generate(vec![response.clone()]));
+
+        Ok(response)
+    }
+
+    /// Scope and embedding of a cacheable query. `None` if the query is not cacheable, or
+    /// cannot be embedded, so the request goes to Ollama instead of failing.
+    async fn prepare(
+        &self,
+        ollama: &Ollama,
+        query: Option<(Scope, String)>,
+    ) -> Option<(Scope, Vec<f32>)> {
+        let (scope, query) = query?;
+
+        let embedding = self.embed(ollama, &query).await;
+        if embedding.is_err() {
+            self.inner.errors.fetch_add(1, Ordering::Relaxed);
+        }
+
+        Some((scope, embedding.ok()?))
+    }
+
+    async fn embed(&self, ollama: &Ollama, query: &str) -> crate::Result<Vec<f32>> {
+        let response = ollama
+            .generate_embeddings(EmbedRequest::new(self.inner.model.as_str(), query))
+            .await?;
+
+        response
+            .embeddings
+            .first()
+            .map(|embedding| normalized(embedding))
+            .ok_or_else(|| OllamaError::Other("Ollama returned no embedding".to_string()))
+    }
+
+    fn lookup(&self, scope: &Scope, embedding: &[f32]) -> Option<Answer> {
+        let mut state = self.state();
+        state.tick += 1;
+        let tick = state.tick;
+
+        let best = state
+            .entries
+            .iter_mut()
+            .filter(|entry| entry.scope == *scope)
+            .map(|entry| (dot(&entry.embedding, embedding), entry))
+            .filter(|(similarity, _)| *similarity >= self.inner.threshold)
+            .max_by(|a, b| a.0.total_cmp(&b.0));
+
+        let Some((_, entry)) = best else {
+            self.inner.misses.fetch_add(1, Ordering::Relaxed);
+            return None;
+        };
+
+        entry.last_used = tick;
+        self.inner.hits.fetch_add(1, Ordering::Relaxed);
+        Some(entry.answer.clone())
+    }
+
+    fn insert(&self, scope: Scope, embedding: Vec<f32>, answer: Answer) {
+        if self.inner.max_entries == 0 {
+            return;
+        }
+
+        let mut state = self.state();
+        state.tick += 1;
+        let entry = Entry {
+            scope,
+            embedding,
+            answer,
+            last_used: state.tick,
+        };
+
+        if state.entries.len() >= self.inner.max_entries {
+            let oldest = (0..state.entries.len()).min_by_key(|&i| state.entries[i].last_used);
+            if let Some(oldest) = oldest {
+                state.entries.swap_remove(oldest);
+                self.inner.evictions.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        state.entries.push(entry);
+    }
+}
+
+impl Entry {
+    fn bytes(&self) -> u64 {
+        let text = match &self.answer {
+            Answer::Chat(responses) => responses
+                .iter()
+                .map(|r| {
+                    r.message.content.len() + r.message.thinking.as_ref().map_or(0, String::len)
+                })
+                .sum::<usize>(),
+            Answer::Generate(responses) => responses
+                .iter()
+                .map(|r| r.response.len() + r.thinking.as_ref().map_or(0, String::len))
+                .sum(),
+        };
+
+        (self.embedding.len() * 4 + text) as u64
+    }
+}
+
+/// Scope and query of a chat request that consists of system messages and one question.
+fn chat_query(request: &ChatRequest, history: &[Message]) -> Option<(Scope, String)> {
+    if !request.tools.is_empty() {
+        return None;
+    }
+
+    let mut system = Vec::new();
+    let mut query = None;
+    for message in history.iter().chain(&request.messages) {
+        match message.role {
+            Role::System => system.push(message.content.as_str()),
+            Role::User if query.is_none() && message.images.is_empty() => {
+                query = Some(message.content.clone());
+            }
+            _ => return None,
+        }
+    }
+
+    let scope = scope(&[
+        "chat",
+        &request.model,
+        &system.join("\n\n"),
+        &json(&request.options),
+        &json(&request.format),
+        &json(&request.think),
+        &json(&(request.logprobs, request.top_logprobs)),
+    ]);
+
+    Some((scope, query?))
+}
+
+/// Scope and prompt of a generate request without conversation context or images.
+fn generate_query(request: &GenerateRequest) -> Option<(Scope, String)> {
+    if !request.context.is_empty() || !request.images.is_empty() {
+        return None;
+    }
+
+    let scope = scope(&[
+        "generate",
+        &request.model,
+        request.system.as_deref().unwrap_or_default(),
+        request.template.as_deref().unwrap_or_default(),
+        request.suffix.as_deref().unwrap_or_default(),
+        &json(&request.raw),
+        &json(&request.options),
+        &json(&request.format),
+        &json(&request.think),
+        &json(&(request.logprobs, request.top_logprobs)),
+    ]);
+
+    Some((scope, request.prompt.clone()))
+}
+
+fn scope(parts: &[&str]) -> Scope {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update(part.as_bytes());
+        hasher.update([0]);
+    }
+
+    hasher.finalize().into()
+}
+
+fn json<T: Serialize>(value: &T) -> String {
+    serde_json::to_string(value).unwrap_or_default()
+}
+
+/// The recorded responses, merged into one if the request does not stream.
+fn replay<T>(responses: Vec<T>, stream: Option<bool>, merge: fn(T, T) -> T) -> Vec<T> {
+    if stream == Some(false) {
+        responses.into_iter().reduce(merge).into_iter().collect()
+    } else {
+        responses
+    }
+}
+
+/// Text and log probabilities of both responses, with the metrics of the later one.
+fn merge_chat(mut merged: ChatResponse, response: ChatResponse) -> ChatResponse {
+    merged.message.merge_from(&response.message);
+    if let Some(logprobs) = &response.logprobs {
+        merged
+            .logprobs
+            .get_or_insert_default()
+            .extend_from_slice(logprobs);
+    }
+
+    ChatResponse {
+        message: merged.message,
+        logprobs: merged.logprobs,
+        ..response
+    }
+}
+
+/// Text and log probabilities of both responses, with the context and metrics of the later one.
+fn merge_generate(mut merged: GenerateResponse, response: GenerateResponse) -> GenerateResponse {
+    merged.response.push_str(&response.response);
+    if let Some(thinking) = &response.thinking {
+        merged.thinking.get_or_insert_default().push_str(thinking);
+    }
+    if let Some(logprobs) = &response.logprobs {
+        merged
+            .logprobs
+            .get_or_insert_default()
+            .extend_from_slice(logprobs);
+    }
+
+    GenerateResponse {
+        response: merged.response,
+        thinking: merged.thinking,
+        logprobs: merged.logprobs,
+        ..response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::model::ModelOptions;
+
+    fn chat(messages: Vec<Message>) -> ChatRequest {
+        ChatRequest::new("model", messages)
+    }
+
+    fn chat_response(content: &str, done: bool, eval_count: u64) -> ChatResponse {
+        serde_json::from_value(json!({
+            "model": "model",
+            "created_at": format!("{eval_count}"),
+            "message": { "role": "assistant", "content": content },
+            "done": done,
+            "logprobs": [{ "token": content, "logprob": -0.5 }],
+            "eval_count": eval_count,
+        }))
+        .unwrap()
+    }
+
+    fn generate_response(text: &str, done: bool, context: Vec<i32>) -> GenerateResponse {
+        serde_json::from_value(json!({
+            "model": "model",
+            "created_at": "",
+            "response": text,
+            "thinking": text,
+            "done": done,
+            "context": context,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn chat_query_is_the_single_question() {
+        let (_, query) = chat_query(
+            &chat(vec![Message::system("be brief"), Message::user("why?")]),
+            &[],
+        )
+        .unwrap();
+        assert_eq!(query, "why?");
+
+        // The question can also come from the history
+        let (_, query) = chat_query(&chat(vec![]), &[Message::user("why?")]).unwrap();
+        assert_eq!(query, "why?");
+    }
+
+    #[test]
+    fn conversations_and_images_are_not_cached() {
+        let answered = [Message::user("why?"), Message::assistant("because")];
+        assert!(chat_query(&chat(vec![Message::user("and?")]), &answered).is_none());
+        assert!(chat_query(&chat(vec![Message::user("a"), Message::user("b")]), &[]).is_none());
+        assert!(chat_query(&chat(vec![Message::system("be brief")]), &[]).is_none());
+
+        let mut image = Message::user("what is this?");
+        image.images.push("aGVsbG8=".to_string());
+        assert!(chat_query(&chat(vec![image]), &[]).is_none());
+
+        let request = GenerateRequest::new("model", "why?");
+        assert!(generate_query(&request.clone().context(vec![1, 2])).is_none());
+        assert!(generate_query(&request.images(vec!["aGVsbG8=".to_string()])).is_none());
+    }
+
+    #[test]
+    fn chat_scope_covers_everything_but_the_question() {
+        let request = |system: &str, question: &str| {
+            chat(vec![Message::system(system), Message::user(question)])
+        };
+        let scope_of = |request: &ChatRequest| chat_query(request, &[]).unwrap().0;
+
+        let scope = scope_of(&request("be brief", "why?"));
+        assert_eq!(scope, scope_of(&request("be brief", "how?")));
+
+        let different = [
+            request("be verbose", "why?"),
+            chat(vec![Message::user("why?")]),
+            ChatRequest::new("other", request("be brief", "why?").messages),
+            request("be brief", "why?").format(json!("json")),
+            request("be brief", "why?").format(json!({ "type": "object" })),
+            request("be brief", "why?").options(ModelOptions::default().temperature(0.0)),
+            request("be brief", "why?").options(ModelOptions::default().seed(1)),
+            request("be brief", "why?").logprobs(true),
+        ];
+        for request in &different {
+            assert_ne!(scope, scope_of(request));
+        }
+    }
+
+    #[test]
+    fn generate_scope_covers_everything_but_the_prompt() {
+        let request = |prompt: &str| GenerateRequest::new("model", prompt);
+        let scope_of = |request: &GenerateRequest| generate_query(request).unwrap().0;
+
+        let (scope, prompt) = generate_query(&request("why?")).unwrap();
+        assert_eq!(prompt, "why?");
+        assert_eq!(scope, scope_of(&request("how?")));
+
+        let different = [
+            GenerateRequest::new("other", "why?"),
+            request("why?").system("be brief".to_string()),
+            request("why?").template("{{ .Prompt }}".to_string()),
+            request("why?").suffix("end".to_string()),
+            request("why?").raw(true),
+            GenerateRequest {
+                format: Some(json!("json")),
+                ..request("why?")
+            },
+            request("why?").options(ModelOptions::default().temperature(0.0)),
+            request("why?").top_logprobs(3),
+        ];
+        for request in &different {
+            assert_ne!(scope, scope_of(request));
+        }
+
+        // System prompt and template are separate parts
+        assert_ne!(
+            scope_of(&request("why?").system("a".to_string())),
+            scope_of(&request("why?").template("a".to_string()))
+        );
+    }
+
+    #[test]
+    fn streaming_replay_keeps_the_responses() {
+        let responses = vec![chat_response("a", false, 1), chat_response("b", true, 2)];
+
+        for stream in [None, Some(true)] {
+            let replayed = replay(responses.clone(), stream, merge_chat);
+            let contents = replayed
+                .iter()
+                .map(|r| r.message.content.as_str())
+                .collect::<Vec<_>>();
+            assert_eq!(contents, ["a", "b"]);
+        }
+    }
+
+    #[test]
+    fn chat_replay_without_stream_is_merged() {
+        let responses = vec![
+            chat_response("a", false, 1),
+            chat_response("b", false, 2),
+            chat_response("c", true, 3),
+        ];
+
+        let replayed = replay(responses, Some(false), merge_chat);
+        assert_eq!(replayed.len(), 1);
+
+        let merged = &replayed[0];
+        assert_eq!(merged.message.content, "abc");
+        assert!(merged.done);
+        assert_eq!(merged.eval_count, Some(3));
+        assert_eq!(merged.created_at, "3");
+
+        let tokens = merged
+            .logprobs
+            .iter()
+            .flatten()
+            .map(|l| l.token.token.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(tokens, ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn generate_replay_without_stream_is_merged() {
+        let responses = vec![
+            generate_response("a", false, vec![]),
+            generate_response("b", true, vec![1, 2]),
+        ];
+
+        let replayed = replay(responses, Some(false), merge_generate);
+        assert_eq!(replayed.len(), 1);
+
+        let merged = &replayed[0];
+        assert_eq!(merged.response, "ab");
+        assert_eq!(merged.thinking.as_deref(), Some("ab"));
+        assert_eq!(merged.context, Some(vec![1, 2]));
+        assert!(merged.done);
+        assert!(merged.logprobs.is_none());
+    }
+}
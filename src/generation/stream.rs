@@ -0,0 +1,166 @@
+use std::{pin::Pin, time::Duration};
+
+use async_stream::stream;
+use tokio_stream::{Stream, StreamExt};
+pub use tokio_util::sync::CancellationToken;
+
+use crate::OllamaError;
+
+type ResultStream<T> = Pin<Box<dyn Stream<Item = crate::Result<T>>>>;
+
+enum Next<T> {
+    Item(Option<T>),
+    Cancelled,
+    TimedOut(Duration),
+}
+
+/// Cancellation and stall detection for streaming endpoints.
+///
+/// When the stream is cancelled or times out, it yields one error and ends.
+/// The HTTP connection is closed, so Ollama stops generating.
+#[derive(Debug, Clone, Default)]
+pub struct StreamOptions {
+    pub cancellation_token: Option<CancellationToken>,
+    pub first_token_timeout: Option<Duration>,
+    pub idle_timeout: Option<Duration>,
+}
+
+impl StreamOptions {
+    /// Stop the stream with `OllamaError::Cancelled` once `token` is cancelled.
+    #[must_use]
+    pub fn cancellation_token(mut self, token: CancellationToken) -> Self {
+        self.cancellation_token = Some(token);
+        self
+    }
+
+    /// Maximum time until the first item arrives, including sending the request.
+    /// Fails with `OllamaError::FirstTokenTimeout`.
+    #[must_use]
+    pub fn first_token_timeout(mut self, timeout: Duration) -> Self {
+        self.first_token_timeout = Some(timeout);
+        self
+    }
+
+    /// Maximum time between two items after the first one.
+    /// Fails with `OllamaError::IdleTimeout`.
+    #[must_use]
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    fn is_noop(&self) -> bool {
+        self.cancellation_token.is_none()
+            && self.first_token_timeout.is_none()
+            && self.idle_timeout.is_none()
+    }
+
+    /// Wrap a stream so it honors these options.
+    pub(crate) fn guard<T: 'static>(self, mut stream: ResultStream<T>) -> ResultStream<T> {
+        if self.is_noop() {
+            return stream;
+        }
+
+        Box::pin(stream! {
+            let cancel = self.cancellation_token.unwrap_or_default();
+            let mut received_first = false;
+
+            loop {
+                let timeout = if received_first {
+                    self.idle_timeout
+                } else {
+                    self.first_token_timeout
+                };
+
+                let next = tokio::select! {
+                    biased;
+                    () = cancel.cancelled() => Next::Cancelled,
+                    item = async {
+                        match timeout {
+                            Some(timeout) => tokio::time::timeout(timeout, stream.next())
+                                .await
+                                .map_or(Next::TimedOut(timeout), Next::Item),
+                            None => Next::Item(stream.next().await),
+                        }
+                    } => item,
+                };
+
+                let error = match next {
+                    Next::Item(Some(item)) => {
+                        received_first = true;
+                        yield item;
+                        continue;
+                    }
+                    Next::Item(None) => break,
+                    Next::Cancelled => OllamaError::Cancelled,
+                    Next::TimedOut(timeout) if received_first => OllamaError::IdleTimeout(timeout),
+                    Next::TimedOut(timeout) => OllamaError::FirstTokenTimeout(timeout),
+                };
+
+                // Drop the response before yielding, so the connection is closed right away
+                drop(stream);
+                yield Err(error);
+                break;
+            }
+        })
+    }
+}
+
+/// Responses of a stream, kept to be cached once the stream ended.
+pub(crate) struct Recording<T> {
+    responses: Vec<T>,
+    failed: bool,
+}
+
+impl<T> Default for Recording<T> {
+    fn default() -> Self {
+        Self {
+            responses: Vec::new(),
+            failed: false,
+        }
+    }
+}
+
+impl<T: Clone> Recording<T> {
+    pub(crate) fn push(&mut self, response: &T) {
+        self.responses.push(response.clone());
+    }
+
+    /// Note an error, the recording is never complete after it.
+    pub(crate) fn fail(&mut self) {
+        self.failed = true;
+    }
+
+    /// The recorded responses, if there was no error and the last one is `done`.
+    pub(crate) fn finish(self, done: fn(&T) -> bool) -> Option<Vec<T>> {
+        (!self.failed && self.responses.last().is_some_and(done)).then_some(self.responses)
+    }
+}
+
+/// Pass `stream` through and hand its responses to `complete` once it ended,
+/// if they were recorded completely, see `Recording::finish`.
+pub(crate) fn record<T, F, Fut>(
+    mut stream: ResultStream<T>,
+    done: fn(&T) -> bool,
+    complete: F,
+) -> ResultStream<T>
+where
+    T: Clone + 'static,
+    F: FnOnce(Vec<T>) -> Fut + 'static,
+    Fut: Future<Output = ()>,
+{
+    Box::pin(stream! {
+        let mut recording = Recording::default();
+        while let Some(res) = stream.next().await {
+            match &res {
+                Ok(response) => recording.push(response),
+                Err(_) => recording.fail(),
+            }
+            yield res;
+        }
+
+        if let Some(responses) = recording.finish(done) {
+            complete(responses).await;
+        }
+    })
+}
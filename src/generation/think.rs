@@ -0,0 +1,294 @@
+use async_stream::stream;
+use tokio_stream::StreamExt;
+
+use crate::generation::{chat::ChatResponseStream, generate::GenerateResponseStream};
+
+/// Splits reasoning that a model emits inline, e.g. `<think>...</think>` inside `content`,
+/// into the separate `thinking` field.
+///
+/// Ollama only fills `thinking` when it separates the reasoning itself. Models run with `raw`
+/// or with older templates write the tags into the regular output instead. The splitter keeps
+/// partial tags buffered, so tags split across chunks are still recognized.
+#[derive(Debug, Clone)]
+pub struct ThinkSplitter {
+    open_tag: String,
+    close_tag: String,
+    start_in_thinking: bool,
+    in_thinking: bool,
+    buffer: String,
+}
+
+impl Default for ThinkSplitter {
+    /// Creates a splitter for `<think>` and `</think>` tags.
+    fn default() -> Self {
+        Self::new("<think>", "</think>")
+    }
+}
+
+impl ThinkSplitter {
+    /// Creates a splitter for custom tags, e.g. `<reasoning>` and `</reasoning>`.
+    ///
+    /// # Panics
+    /// If a tag is empty, it would match everywhere.
+    #[must_use]
+    pub fn new<S: Into<String>>(open_tag: S, close_tag: S) -> Self {
+        let (open_tag, close_tag) = (open_tag.into(), close_tag.into());
+        assert!(
+            !open_tag.is_empty() && !close_tag.is_empty(),
+            "ThinkSplitter tags must not be empty"
+        );
+
+        Self {
+            open_tag,
+            close_tag,
+            start_in_thinking: false,
+            in_thinking: false,
+            buffer: String::new(),
+        }
+    }
+
+    /// Treat the output as already being inside the thinking section.
+    /// Some templates put the opening tag into the prompt, so the model only emits the closing tag.
+    #[must_use]
+    pub fn start_in_thinking(mut self, start_in_thinking: bool) -> Self {
+        self.start_in_thinking = start_in_thinking;
+        self.in_thinking = start_in_thinking;
+        self
+    }
+
+    /// Feed the next piece of output. Returns `(content, thinking)` that can be emitted so far.
+    /// Text that could be the beginning of a tag is held back until the next call.
+    pub fn push(&mut self, text: &str) -> (String, String) {
+        self.buffer.push_str(text);
+
+        let mut content = String::new();
+        let mut thinking = String::new();
+
+        loop {
+            let tag = if self.in_thinking {
+                &self.close_tag
+            } else {
+                &self.open_tag
+            };
+
+            let sink = if self.in_thinking {
+                &mut thinking
+            } else {
+                &mut content
+            };
+
+            if let Some(pos) = self.buffer.find(tag.as_str()) {
+                sink.push_str(&self.buffer[..pos]);
+                self.buffer.drain(..pos + tag.len());
+                self.in_thinking = !self.in_thinking;
+                continue;
+            }
+
+            let keep = Self::partial_tag_len(&self.buffer, tag);
+            let emit = self.buffer.len() - keep;
+            sink.push_str(&self.buffer[..emit]);
+            self.buffer.drain(..emit);
+            break;
+        }
+
+        (content, thinking)
+    }
+
+    /// Flush text that was held back as a possible partial tag and reset the state,
+    /// so the splitter can be reused for the next response.
+    pub fn finish(&mut self) -> (String, String) {
+        let rest = std::mem::take(&mut self.buffer);
+        let in_thinking = self.in_thinking;
+        self.in_thinking = self.start_in_thinking;
+
+        if in_thinking {
+            (String::new(), rest)
+        } else {
+            (rest, String::new())
+        }
+    }
+
+    /// Length of the longest suffix of `text` that is a proper prefix of `tag`.
+    fn partial_tag_len(text: &str, tag: &str) -> usize {
+        (1..tag.len())
+            .rev()
+            .filter(|&len| tag.is_char_boundary(len))
+            .find(|&len| text.ends_with(&tag[..len]))
+            .unwrap_or(0)
+    }
+
+    /// Wrap a chat stream, moving tagged text from `message.content` into `message.thinking`.
+    ///
+    /// # Note
+    /// `Ollama::chat` records the history before this adapter runs,
+    /// so the `History` still contains the raw tagged content.
+    #[must_use]
+    pub fn chat_stream(mut self, mut stream: ChatResponseStream) -> ChatResponseStream {
+        Box::pin(stream! {
+            while let Some(res) = stream.next().await {
+                let Ok(mut response) = res else {
+                    yield res;
+                    continue;
+                };
+
+                let (mut content, mut thinking) = self.push(&response.message.content);
+                if response.done {
+                    let (rest_content, rest_thinking) = self.finish();
+                    content.push_str(&rest_content);
+                    thinking.push_str(&rest_thinking);
+                }
+
+                response.message.content = content;
+                if !thinking.is_empty() {
+                    response
+                        .message
+                        .thinking
+                        .get_or_insert_with(String::new)
+                        .push_str(&thinking);
+                }
+
+                yield Ok(response);
+            }
+        })
+    }
+
+    /// Wrap a generate stream, moving tagged text from `response` into `thinking`.
+    #[must_use]
+    pub fn generate_stream(mut self, mut stream: GenerateResponseStream) -> GenerateResponseStream {
+        Box::pin(stream! {
+            while let Some(res) = stream.next().await {
+                let Ok(mut response) = res else {
+                    yield res;
+                    continue;
+                };
+
+                let (mut content, mut thinking) = self.push(&response.response);
+                if response.done {
+                    let (rest_content, rest_thinking) = self.finish();
+                    content.push_str(&rest_content);
+                    thinking.push_str(&rest_thinking);
+                }
+
+                response.response = content;
+                if !thinking.is_empty() {
+                    response
+                        .thinking
+                        .get_or_insert_with(String::new)
+                        .push_str(&thinking);
+                }
+
+                yield Ok(response);
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Push every piece and finish, concatenating the output.
+    fn split(splitter: &mut ThinkSplitter, pieces: &[&str]) -> (String, String) {
+        let (mut content, mut thinking) = (String::new(), String::new());
+        for piece in pieces {
+            let (c, t) = splitter.push(piece);
+            content.push_str(&c);
+            thinking.push_str(&t);
+        }
+
+        let (c, t) = splitter.finish();
+        content.push_str(&c);
+        thinking.push_str(&t);
+        (content, thinking)
+    }
+
+    #[test]
+    fn tags_in_one_piece() {
+        let mut splitter = ThinkSplitter::default();
+
+        assert_eq!(
+            splitter.push("<think>hmm</think>answer"),
+            ("answer".to_string(), "hmm".to_string())
+        );
+        assert_eq!(splitter.finish(), (String::new(), String::new()));
+    }
+
+    #[test]
+    fn tags_split_across_pieces() {
+        let mut splitter = ThinkSplitter::default();
+
+        assert_eq!(
+            splitter.push("before <th"),
+            ("before ".to_string(), String::new())
+        );
+        assert_eq!(splitter.push("ink>hm"), (String::new(), "hm".to_string()));
+        assert_eq!(splitter.push("m</"), (String::new(), "m".to_string()));
+        assert_eq!(splitter.push("thi"), (String::new(), String::new()));
+        assert_eq!(
+            splitter.push("nk> after"),
+            (" after".to_string(), String::new())
+        );
+
+        let pieces = "<think>a</think>b"
+            .chars()
+            .map(String::from)
+            .collect::<Vec<_>>();
+        let pieces = pieces.iter().map(String::as_str).collect::<Vec<_>>();
+        assert_eq!(
+            split(&mut splitter, &pieces),
+            ("b".to_string(), "a".to_string())
+        );
+    }
+
+    #[test]
+    fn partial_tag_is_flushed_on_finish() {
+        let mut splitter = ThinkSplitter::default();
+
+        assert_eq!(
+            split(&mut splitter, &["a <thi"]),
+            ("a <thi".to_string(), String::new())
+        );
+        assert_eq!(
+            split(&mut splitter, &["<think>unfinished</th"]),
+            (String::new(), "unfinished</th".to_string())
+        );
+
+        // The state was reset by `finish`
+        assert_eq!(
+            split(&mut splitter, &["plain"]),
+            ("plain".to_string(), String::new())
+        );
+    }
+
+    #[test]
+    fn start_in_thinking() {
+        let mut splitter = ThinkSplitter::default().start_in_thinking(true);
+
+        assert_eq!(
+            split(&mut splitter, &["reasoning</", "think>answer"]),
+            ("answer".to_string(), "reasoning".to_string())
+        );
+
+        // `finish` goes back to thinking, not to content
+        assert_eq!(
+            split(&mut splitter, &["more</think>"]),
+            (String::new(), "more".to_string())
+        );
+    }
+
+    #[test]
+    fn custom_tags() {
+        let mut splitter = ThinkSplitter::new("[[", "]]");
+
+        assert_eq!(
+            split(&mut splitter, &["x[", "[y]", "]z"]),
+            ("xz".to_string(), "y".to_string())
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "ThinkSplitter tags must not be empty")]
+    fn empty_tags_are_rejected() {
+        let _ = ThinkSplitter::new("<think>", "");
+    }
+}
@@ -0,0 +1,22 @@
+use crate::{
+    generation::tokenize::{request::TokenizeRequest, response::TokenizeResponse},
+    llama::Llama,
+};
+
+pub mod request;
+pub mod response;
+
+impl Llama {
+    /// Llama.cpp `/tokenize` endpoint. Returns one `TokenizeResponse`.
+    ///
+    /// # Errors
+    ///
+    /// If Llama.cpp rejects the request.
+    /// If the response cannot be parsed.
+    pub async fn tokenize(&self, request: TokenizeRequest) -> crate::Result<TokenizeResponse> {
+        let url = self.url.join("tokenize")?;
+        let response = self.send(self.client.post(url).json(&request)).await?;
+
+        Ok(response.json::<TokenizeResponse>().await?)
+    }
+}
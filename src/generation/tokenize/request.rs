@@ -0,0 +1,34 @@
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct TokenizeRequest {
+    pub content: String,
+
+    /// Add special tokens like BOS
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub add_special: Option<bool>,
+
+    /// Return the text of every token next to its id
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub with_pieces: Option<bool>,
+}
+
+impl TokenizeRequest {
+    pub fn new<S: Into<String>>(content: S) -> Self {
+        Self {
+            content: content.into(),
+            add_special: None,
+            with_pieces: None,
+        }
+    }
+
+    #[must_use]
+    pub fn add_special(mut self, add_special: bool) -> Self {
+        self.add_special = Some(add_special);
+        self
+    }
+
+    #[must_use]
+    pub fn with_pieces(mut self, with_pieces: bool) -> Self {
+        self.with_pieces = Some(with_pieces);
+        self
+    }
+}
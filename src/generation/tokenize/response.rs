@@ -0,0 +1,49 @@
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct TokenizeResponse {
+    pub tokens: Vec<Token>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(untagged)]
+pub enum Token {
+    Id(u32),
+
+    /// Returned with `TokenizeRequest::with_pieces`
+    Piece {
+        id: u32,
+        piece: TokenPiece,
+    },
+}
+
+/// Text of a token. Tokens that are not valid UTF-8 on their own are returned as bytes.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(untagged)]
+pub enum TokenPiece {
+    Text(String),
+    Bytes(Vec<u8>),
+}
+
+impl Token {
+    #[must_use]
+    pub fn id(&self) -> u32 {
+        match self {
+            Token::Id(id) | Token::Piece { id, .. } => *id,
+        }
+    }
+}
+
+impl TokenPiece {
+    /// Length of the piece in bytes.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        match self {
+            TokenPiece::Text(text) => text.len(),
+            TokenPiece::Bytes(bytes) => bytes.len(),
+        }
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -2,11 +2,20 @@ use thiserror::Error;
 
 use crate::generation::chat::history::HistoryPoisonError;
 
+pub mod classify;
+pub mod client;
 pub mod generation;
+pub mod limiter;
 pub mod llama;
 pub mod misc;
 pub mod model;
 pub mod ollama;
+pub mod pool;
+pub mod rag;
+pub mod retry;
+pub mod router;
+pub mod splitter;
+pub mod store;
 
 pub type Result<T> = std::result::Result<T, OllamaError>;
 
@@ -19,6 +28,34 @@ pub enum OllamaError {
     #[error("URL Error")]
     UrlError(#[from] url::ParseError),
 
+    #[error("Error {status}:\n{body}")]
+    Status {
+        status: reqwest::StatusCode,
+        body: String,
+    },
+
+    #[error("IO Error")]
+    IoError(#[from] std::io::Error),
+
+    #[error("Cancelled")]
+    Cancelled,
+
+    #[error("No response within {0:?}")]
+    FirstTokenTimeout(std::time::Duration),
+
+    #[error("Stream stalled for {0:?}")]
+    IdleTimeout(std::time::Duration),
+
+    #[error("No free slot within {0:?}")]
+    QueueTimeout(std::time::Duration),
+
+    #[error("Embedding chunk at offset {offset} with {len} inputs failed: {source}")]
+    EmbedChunkFailed {
+        offset: usize,
+        len: usize,
+        source: Box<OllamaError>,
+    },
+
     #[error("{0}")]
     Other(String),
 }
@@ -0,0 +1,423 @@
+use std::{
+    collections::HashMap,
+    pin::Pin,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use async_stream::stream;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio_stream::{Stream, StreamExt};
+
+use crate::OllamaError;
+
+type ResultStream<T> = Pin<Box<dyn Stream<Item = crate::Result<T>>>>;
+
+/// Client-side limit on concurrent requests, globally and per model.
+///
+/// Calls over the limit wait in FIFO order. Streaming calls hold their slot until the
+/// stream is finished or dropped.
+///
+/// This struct uses Arc internally, clones share the same limits and queue.
+#[derive(Debug, Clone, Default)]
+pub struct ConcurrencyLimiter {
+    global: Option<Arc<Semaphore>>,
+    per_model_limit: Option<usize>,
+    queue_timeout: Option<Duration>,
+    models: Arc<Mutex<HashMap<String, Arc<ModelQueue>>>>,
+    counters: Arc<Counters>,
+}
+
+#[derive(Debug)]
+struct ModelQueue {
+    semaphore: Arc<Semaphore>,
+    queued: AtomicUsize,
+    in_flight: AtomicUsize,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    queued: AtomicUsize,
+    in_flight: AtomicUsize,
+    acquired: AtomicU64,
+    timed_out: AtomicU64,
+    total_wait_nanos: AtomicU64,
+    max_wait_nanos: AtomicU64,
+}
+
+/// Snapshot of a `ConcurrencyLimiter`, for monitoring.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LimiterStats {
+    /// Calls currently waiting for a slot
+    pub queued: usize,
+
+    /// Calls currently holding a slot
+    pub in_flight: usize,
+
+    /// Calls that got a slot since the limiter was created
+    pub acquired: u64,
+
+    /// Calls that gave up after the queue timeout
+    pub timed_out: u64,
+
+    /// Sum of the time calls spent waiting for a slot
+    pub total_wait: Duration,
+
+    /// Longest time a call spent waiting for a slot
+    pub max_wait: Duration,
+
+    /// Waiting and running calls per model
+    pub models: HashMap<String, ModelQueueStats>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ModelQueueStats {
+    pub queued: usize,
+    pub in_flight: usize,
+}
+
+impl LimiterStats {
+    /// Average time calls spent waiting for a slot.
+    #[must_use]
+    pub fn average_wait(&self) -> Option<Duration> {
+        let acquired = u32::try_from(self.acquired).ok()?;
+        self.total_wait.checked_div(acquired)
+    }
+}
+
+/// A slot of a `ConcurrencyLimiter`, released when dropped.
+#[derive(Debug)]
+pub struct LimiterPermit {
+    _global: Option<OwnedSemaphorePermit>,
+    _model: Option<OwnedSemaphorePermit>,
+    model_queue: Option<Arc<ModelQueue>>,
+    counters: Arc<Counters>,
+}
+
+impl Drop for LimiterPermit {
+    fn drop(&mut self) {
+        self.counters.in_flight.fetch_sub(1, Ordering::Relaxed);
+        if let Some(queue) = &self.model_queue {
+            queue.in_flight.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Decrements the counter when dropped, also if the waiting future is cancelled.
+struct QueuedGuard<'a>(&'a AtomicUsize);
+
+impl<'a> QueuedGuard<'a> {
+    fn new(counter: &'a AtomicUsize) -> Self {
+        counter.fetch_add(1, Ordering::Relaxed);
+        Self(counter)
+    }
+}
+
+impl Drop for QueuedGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl ConcurrencyLimiter {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Maximum number of concurrent calls over all models.
+    #[must_use]
+    pub fn global_limit(mut self, limit: usize) -> Self {
+        self.global = Some(Arc::new(Semaphore::new(limit)));
+        self
+    }
+
+    /// Maximum number of concurrent calls per model.
+    #[must_use]
+    pub fn per_model_limit(mut self, limit: usize) -> Self {
+        self.per_model_limit = Some(limit);
+        self
+    }
+
+    /// Maximum time a call waits for a slot before failing with `OllamaError::QueueTimeout`.
+    #[must_use]
+    pub fn queue_timeout(mut self, timeout: Duration) -> Self {
+        self.queue_timeout = Some(timeout);
+        self
+    }
+
+    fn model_queue(&self, model: &str) -> Option<Arc<ModelQueue>> {
+        let limit = self.per_model_limit?;
+        let mut models = self
+            .models
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+
+        Some(
+            models
+                .entry(model.to_string())
+                .or_insert_with(|| {
+                    Arc::new(ModelQueue {
+                        semaphore: Arc::new(Semaphore::new(limit)),
+                        queued: AtomicUsize::new(0),
+                        in_flight: AtomicUsize::new(0),
+                    })
+                })
+                .clone(),
+        )
+    }
+
+    /// Wait for a slot for `model`.
+    ///
+    /// # Errors
+    /// * `OllamaError::QueueTimeout` if no slot became free within the queue timeout.
+    pub async fn acquire(&self, model: &str) -> crate::Result<LimiterPermit> {
+        let started = Instant::now();
+        let model_queue = self.model_queue(model);
+
+        let (model_permit, global_permit) = {
+            let _queued = QueuedGuard::new(&self.counters.queued);
+            let _model_queued = model_queue.as_ref().map(|q| QueuedGuard::new(&q.queued));
+
+            // The model slot is taken first, so a busy model does not block the global slots
+            let acquire = async {
+                let model_permit = match &model_queue {
+                    Some(queue) => Some(queue.semaphore.clone().acquire_owned().await),
+                    None => None,
+                }
+                .transpose();
+
+                let global_permit = match &self.global {
+                    Some(global) => Some(global.clone().acquire_owned().await),
+                    None => None,
+                }
+                .transpose();
+
+                (model_permit, global_permit)
+            };
+
+            let (model_permit, global_permit) = match self.queue_timeout {
+                Some(timeout) => tokio::time::timeout(timeout, acquire).await.map_err(|_| {
+                    self.counters.timed_out.fetch_add(1, Ordering::Relaxed);
+                    OllamaError::QueueTimeout(timeout)
+                })?,
+                None => acquire.await,
+            };
+
+            let closed = |e| OllamaError::Other(format!("Concurrency limiter closed: {e}"));
+            (
+                model_permit.map_err(closed)?,
+                global_permit.map_err(closed)?,
+            )
+        };
+
+        let waited = u64::try_from(started.elapsed().as_nanos()).unwrap_or(u64::MAX);
+        self.counters.acquired.fetch_add(1, Ordering::Relaxed);
+        self.counters
+            .total_wait_nanos
+            .fetch_add(waited, Ordering::Relaxed);
+        self.counters
+            .max_wait_nanos
+            .fetch_max(waited, Ordering::Relaxed);
+
+        self.counters.in_flight.fetch_add(1, Ordering::Relaxed);
+        if let Some(queue) = &model_queue {
+            queue.in_flight.fetch_add(1, Ordering::Relaxed);
+        }
+
+        Ok(LimiterPermit {
+            _global: global_permit,
+            _model: model_permit,
+            model_queue,
+            counters: self.counters.clone(),
+        })
+    }
+
+    /// Current queue depth, load and wait times.
+    #[must_use]
+    pub fn stats(&self) -> LimiterStats {
+        let models = self
+            .models
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .iter()
+            .map(|(model, queue)| {
+                (
+                    model.clone(),
+                    ModelQueueStats {
+                        queued: queue.queued.load(Ordering::Relaxed),
+                        in_flight: queue.in_flight.load(Ordering::Relaxed),
+                    },
+                )
+            })
+            .collect();
+
+        LimiterStats {
+            queued: self.counters.queued.load(Ordering::Relaxed),
+            in_flight: self.counters.in_flight.load(Ordering::Relaxed),
+            acquired: self.counters.acquired.load(Ordering::Relaxed),
+            timed_out: self.counters.timed_out.load(Ordering::Relaxed),
+            total_wait: Duration::from_nanos(
+                self.counters.total_wait_nanos.load(Ordering::Relaxed),
+            ),
+            max_wait: Duration::from_nanos(self.counters.max_wait_nanos.load(Ordering::Relaxed)),
+            models,
+        }
+    }
+}
+
+/// Acquire a slot if a limiter is configured.
+pub(crate) async fn acquire(
+    limiter: Option<&ConcurrencyLimiter>,
+    model: &str,
+) -> crate::Result<Option<LimiterPermit>> {
+    match limiter {
+        Some(limiter) => Ok(Some(limiter.acquire(model).await?)),
+        None => Ok(None),
+    }
+}
+
+/// Keep `permit` until the stream is finished or dropped.
+pub(crate) fn hold<T: 'static>(
+    permit: Option<LimiterPermit>,
+    mut stream: ResultStream<T>,
+) -> ResultStream<T> {
+    let Some(permit) = permit else {
+        return stream;
+    };
+
+    Box::pin(stream! {
+        let _permit = permit;
+        while let Some(item) = stream.next().await {
+            yield item;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn model_stats(limiter: &ConcurrencyLimiter, model: &str) -> ModelQueueStats {
+        limiter.stats().models[model]
+    }
+
+    #[tokio::test]
+    async fn permits_are_counted_until_dropped() {
+        let limiter = ConcurrencyLimiter::new().per_model_limit(2);
+
+        let first = limiter.acquire("a").await.unwrap();
+        let second = limiter.acquire("a").await.unwrap();
+        let other = limiter.acquire("b").await.unwrap();
+
+        let stats = limiter.stats();
+        assert_eq!((stats.in_flight, stats.acquired, stats.queued), (3, 3, 0));
+        assert_eq!(model_stats(&limiter, "a").in_flight, 2);
+        assert_eq!(model_stats(&limiter, "b").in_flight, 1);
+
+        drop(first);
+        drop(other);
+        let stats = limiter.stats();
+        assert_eq!((stats.in_flight, stats.acquired), (1, 3));
+        assert_eq!(model_stats(&limiter, "a").in_flight, 1);
+        assert_eq!(model_stats(&limiter, "b").in_flight, 0);
+
+        drop(second);
+        assert_eq!(limiter.stats().in_flight, 0);
+    }
+
+    #[tokio::test]
+    async fn busy_model_does_not_block_others() {
+        let limiter = ConcurrencyLimiter::new()
+            .global_limit(2)
+            .per_model_limit(1)
+            .queue_timeout(Duration::from_millis(50));
+
+        let busy = limiter.acquire("a").await.unwrap();
+
+        // Waits for the model slot without taking the second global one
+        let waiting = tokio::spawn({
+            let limiter = limiter.clone();
+            async move { limiter.acquire("a").await.map(drop) }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(limiter.stats().queued, 1);
+        assert_eq!(model_stats(&limiter, "a").queued, 1);
+
+        let other = limiter.acquire("b").await.unwrap();
+        assert_eq!(limiter.stats().in_flight, 2);
+
+        let error = waiting.await.unwrap().unwrap_err();
+        assert!(matches!(error, OllamaError::QueueTimeout(_)));
+
+        let stats = limiter.stats();
+        assert_eq!((stats.queued, stats.timed_out, stats.acquired), (0, 1, 2));
+        assert_eq!(
+            model_stats(&limiter, "a"),
+            ModelQueueStats {
+                queued: 0,
+                in_flight: 1
+            }
+        );
+
+        drop((busy, other));
+        assert_eq!(limiter.stats().in_flight, 0);
+    }
+
+    #[tokio::test]
+    async fn released_slot_goes_to_the_waiting_call() {
+        let limiter = ConcurrencyLimiter::new().global_limit(1);
+
+        let permit = limiter.acquire("a").await.unwrap();
+        let waiting = tokio::spawn({
+            let limiter = limiter.clone();
+            async move { limiter.acquire("b").await.map(drop) }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(limiter.stats().queued, 1);
+
+        drop(permit);
+        waiting.await.unwrap().unwrap();
+
+        let stats = limiter.stats();
+        assert_eq!((stats.queued, stats.in_flight, stats.acquired), (0, 0, 2));
+        assert!(stats.max_wait > Duration::ZERO);
+        assert!(stats.average_wait().unwrap() <= stats.max_wait);
+    }
+
+    #[tokio::test]
+    async fn cancelled_wait_leaves_the_queue() {
+        let limiter = ConcurrencyLimiter::new().global_limit(1);
+        let _permit = limiter.acquire("a").await.unwrap();
+
+        let waited = tokio::time::timeout(Duration::from_millis(10), limiter.acquire("a")).await;
+        assert!(waited.is_err());
+
+        let stats = limiter.stats();
+        assert_eq!((stats.queued, stats.in_flight, stats.acquired), (0, 1, 1));
+        assert_eq!(stats.timed_out, 0);
+    }
+
+    #[tokio::test]
+    async fn stream_holds_the_permit() {
+        let limiter = ConcurrencyLimiter::new().global_limit(1);
+        let permit = limiter.acquire("a").await.unwrap();
+
+        let items: ResultStream<u32> = Box::pin(tokio_stream::iter([Ok(1), Ok(2)]));
+        let mut stream = hold(Some(permit), items);
+        assert_eq!(stream.next().await.unwrap().unwrap(), 1);
+        assert_eq!(limiter.stats().in_flight, 1);
+
+        assert_eq!(stream.next().await.unwrap().unwrap(), 2);
+        assert!(stream.next().await.is_none());
+        assert_eq!(limiter.stats().in_flight, 0);
+    }
+
+    #[test]
+    fn no_average_wait_without_calls() {
+        assert_eq!(LimiterStats::default().average_wait(), None);
+    }
+}
@@ -1,9 +1,17 @@
-use reqwest::{Client, IntoUrl, Url};
+use reqwest::{Client, IntoUrl, RequestBuilder, Response, Url};
+
+use crate::{
+    client,
+    limiter::{self, ConcurrencyLimiter, LimiterPermit},
+    retry::{self, RetryPolicy},
+};
 
 #[derive(Debug, Clone)]
 pub struct Llama {
     pub(crate) url: Url,
     pub(crate) client: Client,
+    pub(crate) retry: Option<RetryPolicy>,
+    pub(crate) limiter: Option<ConcurrencyLimiter>,
 }
 
 impl Default for Llama {
@@ -12,16 +20,20 @@ impl Default for Llama {
         Self {
             url: Url::parse("http://127.0.0.1:8012").unwrap(),
             client: Client::new(),
+            retry: None,
+            limiter: None,
         }
     }
 }
 
 impl Llama {
-    /// Set the base URL for the Llama.cpp API
+    /// Set the base URL of llama-server, e.g. `http://gpu-box:8080` for a server started
+    /// with `--host 0.0.0.0 --port 8080`. A path is kept, requests go to e.g.
+    /// `https://example.com/llama/rerank` behind a reverse proxy.
     /// # Errors
     /// * Returns an error if the provided URL is invalid.
     pub fn with_url<U: IntoUrl>(mut self, url: U) -> crate::Result<Self> {
-        self.url = url.into_url()?;
+        self.url = client::base_url(url.into_url()?);
         Ok(self)
     }
 
@@ -31,4 +43,31 @@ impl Llama {
         self.client = client;
         self
     }
+
+    /// Retry requests that fail for transient reasons according to `policy`.
+    /// Disabled by default.
+    #[must_use]
+    pub fn with_retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
+    }
+
+    /// Limit the number of concurrent requests, globally and per model.
+    /// Clones of the client share the limiter. Disabled by default.
+    #[must_use]
+    pub fn with_limiter(mut self, limiter: ConcurrencyLimiter) -> Self {
+        self.limiter = Some(limiter);
+        self
+    }
+
+    /// Wait for a slot of the configured limiter, if any.
+    pub(crate) async fn acquire(&self, model: &str) -> crate::Result<Option<LimiterPermit>> {
+        limiter::acquire(self.limiter.as_ref(), model).await
+    }
+
+    /// Send a request with the configured retry policy.
+    /// Unsuccessful status codes are turned into errors.
+    pub(crate) async fn send(&self, request: RequestBuilder) -> crate::Result<Response> {
+        retry::send(self.retry.as_ref(), request).await
+    }
 }
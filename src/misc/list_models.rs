@@ -83,16 +83,8 @@ impl Ollama {
     ///
     /// Ollama side errors
     pub async fn list_models(&self) -> crate::Result<ListModelsResponse> {
-        let url = self.url.join("/api/tags")?;
-        let response = self.client.get(url).send().await?;
-
-        if !response.status().is_success() {
-            return Err(crate::OllamaError::Other(format!(
-                "Error {}:\n{}",
-                response.status(),
-                response.text().await.unwrap_or_default()
-            )));
-        }
+        let url = self.url.join("api/tags")?;
+        let response = self.send(self.client.get(url)).await?;
 
         let models = response.json::<ListModelsResponse>().await?;
 
@@ -1 +1,4 @@
 pub mod list_models;
+pub mod pull_model;
+pub mod running_models;
+pub mod version;
@@ -0,0 +1,48 @@
+use crate::ollama::Ollama;
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct PullModelRequest {
+    pub model: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub insecure: Option<bool>,
+
+    stream: bool,
+}
+
+impl PullModelRequest {
+    pub fn new<S: Into<String>>(model: S) -> Self {
+        Self {
+            model: model.into(),
+            insecure: None,
+            stream: false,
+        }
+    }
+
+    /// Allow insecure connections to the library. Only use this if you are pulling from
+    /// your own library during development.
+    #[must_use]
+    pub fn insecure(mut self, insecure: bool) -> Self {
+        self.insecure = Some(insecure);
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct PullModelResponse {
+    pub status: String,
+}
+
+impl Ollama {
+    /// Ollama's `/api/pull` endpoint. Waits until the model is downloaded.
+    ///
+    /// # Errors
+    ///
+    /// Ollama side errors, e.g. the model does not exist.
+    pub async fn pull_model(&self, request: PullModelRequest) -> crate::Result<PullModelResponse> {
+        let url = self.url.join("api/pull")?;
+        let response = self.send(self.client.post(url).json(&request)).await?;
+
+        Ok(response.json::<PullModelResponse>().await?)
+    }
+}
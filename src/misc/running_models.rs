@@ -0,0 +1,71 @@
+use crate::{misc::list_models::ModelDetails, ollama::Ollama};
+
+#[derive(
+    Debug,
+    Clone,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+pub struct ListRunningModelsResponse {
+    pub models: Vec<RunningModel>,
+}
+
+#[derive(
+    Debug,
+    Clone,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+pub struct RunningModel {
+    pub name: String,
+
+    pub model: String,
+
+    #[serde(default)]
+    pub size: i64,
+
+    #[serde(default)]
+    pub digest: String,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<ModelDetails>,
+
+    #[serde(default)]
+    pub expires_at: String,
+
+    #[serde(default)]
+    pub size_vram: i64,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context_length: Option<i64>,
+}
+
+impl Ollama {
+    /// Ollama's `/api/ps` endpoint. Lists the models that are currently loaded into memory.
+    ///
+    /// # Errors
+    ///
+    /// Ollama side errors
+    pub async fn list_running_models(&self) -> crate::Result<ListRunningModelsResponse> {
+        let url = self.url.join("api/ps")?;
+        let response = self.send(self.client.get(url)).await?;
+
+        let models = response.json::<ListRunningModelsResponse>().await?;
+
+        Ok(models)
+    }
+}
@@ -0,0 +1,21 @@
+use crate::ollama::Ollama;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct VersionResponse {
+    pub version: String,
+}
+
+impl Ollama {
+    /// Ollama's `/api/version` endpoint.
+    /// Cheap to call, which makes it useful as a health check.
+    ///
+    /// # Errors
+    ///
+    /// Ollama side errors
+    pub async fn version(&self) -> crate::Result<VersionResponse> {
+        let url = self.url.join("api/version")?;
+        let response = self.send(self.client.get(url)).await?;
+
+        Ok(response.json::<VersionResponse>().await?)
+    }
+}
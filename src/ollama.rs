@@ -1,9 +1,23 @@
-use reqwest::{Client, IntoUrl, Url};
+use reqwest::{Client, IntoUrl, RequestBuilder, Response, Url};
+
+use crate::{
+    client,
+    generation::{
+        embed::cache::EmbeddingCache, response_cache::ResponseCache, semantic_cache::SemanticCache,
+    },
+    limiter::{self, ConcurrencyLimiter, LimiterPermit},
+    retry::{self, RetryPolicy},
+};
 
 #[derive(Debug, Clone)]
 pub struct Ollama {
     pub(crate) url: Url,
     pub(crate) client: Client,
+    pub(crate) retry: Option<RetryPolicy>,
+    pub(crate) limiter: Option<ConcurrencyLimiter>,
+    pub(crate) embedding_cache: Option<EmbeddingCache>,
+    pub(crate) semantic_cache: Option<SemanticCache>,
+    pub(crate) response_cache: Option<ResponseCache>,
 }
 
 impl Default for Ollama {
@@ -12,16 +26,22 @@ impl Default for Ollama {
         Self {
             url: Url::parse("http://127.0.0.1:11434").unwrap(),
             client: Client::new(),
+            retry: None,
+            limiter: None,
+            embedding_cache: None,
+            semantic_cache: None,
+            response_cache: None,
         }
     }
 }
 
 impl Ollama {
-    /// Set the base URL for the Ollama API
+    /// Set the base URL for the Ollama API.
+    /// A path is kept, e.g. `https://example.com/ollama` for a reverse proxy.
     /// # Errors
     /// * Returns an error if the provided URL is invalid.
     pub fn with_url<U: IntoUrl>(mut self, url: U) -> crate::Result<Self> {
-        self.url = url.into_url()?;
+        self.url = client::base_url(url.into_url()?);
         Ok(self)
     }
 
@@ -31,4 +51,56 @@ impl Ollama {
         self.client = client;
         self
     }
+
+    /// Retry requests that fail for transient reasons according to `policy`.
+    /// Disabled by default.
+    #[must_use]
+    pub fn with_retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
+    }
+
+    /// Limit the number of concurrent requests, globally and per model.
+    /// Clones of the client share the limiter. Disabled by default.
+    #[must_use]
+    pub fn with_limiter(mut self, limiter: ConcurrencyLimiter) -> Self {
+        self.limiter = Some(limiter);
+        self
+    }
+
+    /// Answer `generate_embeddings` from `cache` where possible.
+    /// Clones of the client share the cache. Disabled by default.
+    #[must_use]
+    pub fn with_embedding_cache(mut self, cache: EmbeddingCache) -> Self {
+        self.embedding_cache = Some(cache);
+        self
+    }
+
+    /// Answer `chat`, `generate` and `generate_without_stream` from `cache` when a similar
+    /// question was answered before.
+    /// Clones of the client share the cache. Disabled by default.
+    #[must_use]
+    pub fn with_semantic_cache(mut self, cache: SemanticCache) -> Self {
+        self.semantic_cache = Some(cache);
+        self
+    }
+
+    /// Replay the responses to requests already sent to `/api/chat` or `/api/generate`
+    /// from `cache`. Clones of the client share the cache. Disabled by default.
+    #[must_use]
+    pub fn with_response_cache(mut self, cache: ResponseCache) -> Self {
+        self.response_cache = Some(cache);
+        self
+    }
+
+    /// Wait for a slot of the configured limiter, if any.
+    pub(crate) async fn acquire(&self, model: &str) -> crate::Result<Option<LimiterPermit>> {
+        limiter::acquire(self.limiter.as_ref(), model).await
+    }
+
+    /// Send a request with the configured retry policy.
+    /// Unsuccessful status codes are turned into errors.
+    pub(crate) async fn send(&self, request: RequestBuilder) -> crate::Result<Response> {
+        retry::send(self.retry.as_ref(), request).await
+    }
 }
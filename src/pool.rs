@@ -0,0 +1,547 @@
+use std::{
+    pin::Pin,
+    sync::{
+        Arc, Weak,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use async_stream::stream;
+use futures_util::future;
+use reqwest::Url;
+use tokio::task::JoinHandle;
+use tokio_stream::{Stream, StreamExt};
+
+use crate::{
+    OllamaError,
+    generation::{
+        chat::{ChatResponseStream, history::History, request::ChatRequest},
+        embed::{EmbedResponseStream, request::EmbedRequest, response::EmbedResponse},
+        generate::{GenerateResponseStream, request::GenerateRequest, response::GenerateResponse},
+        stream::StreamOptions,
+    },
+    ollama::Ollama,
+};
+
+/// Time a health check waits for a host to answer.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+type ResultStream<T> = Pin<Box<dyn Stream<Item = crate::Result<T>>>>;
+
+/// How `OllamaPool` picks the host for a request.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Balancing {
+    /// Cycle through the healthy hosts.
+    #[default]
+    RoundRobin,
+
+    /// Pick the healthy host with the fewest requests in flight.
+    LeastInFlight,
+}
+
+/// Health and load of one host in an `OllamaPool`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostStatus {
+    pub url: Url,
+    pub healthy: bool,
+    pub in_flight: usize,
+}
+
+#[derive(Debug)]
+pub(crate) struct PoolHost {
+    pub(crate) ollama: Ollama,
+    healthy: AtomicBool,
+    in_flight: AtomicUsize,
+}
+
+impl PoolHost {
+    pub(crate) fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_healthy(&self, healthy: bool) {
+        self.healthy.store(healthy, Ordering::Relaxed);
+    }
+
+    fn begin(self: &Arc<Self>) -> InFlightGuard {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlightGuard(self.clone())
+    }
+}
+
+/// Counts a request as in flight until dropped.
+struct InFlightGuard(Arc<PoolHost>);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug)]
+struct PoolInner {
+    hosts: Vec<Arc<PoolHost>>,
+    balancing: Balancing,
+    next: AtomicUsize,
+}
+
+/// Spreads requests over several Ollama hosts.
+///
+/// Exposes the same chat, generate and embed API as `Ollama`. Hosts that fail with a
+/// connection error are ejected until a health check succeeds again, see `spawn_health_checks`.
+/// Requests fail over to the next host if the connection fails before streaming started.
+///
+/// This struct uses Arc internally and is cheap to clone.
+#[derive(Debug, Clone)]
+pub struct OllamaPool {
+    inner: Arc<PoolInner>,
+}
+
+impl OllamaPool {
+    #[must_use]
+    pub fn new(hosts: Vec<Ollama>) -> Self {
+        Self::with_balancing(hosts, Balancing::default())
+    }
+
+    #[must_use]
+    pub fn with_balancing(hosts: Vec<Ollama>, balancing: Balancing) -> Self {
+        let hosts = hosts
+            .into_iter()
+            .map(|ollama| {
+                Arc::new(PoolHost {
+                    ollama,
+                    healthy: AtomicBool::new(true),
+                    in_flight: AtomicUsize::new(0),
+                })
+            })
+            .collect();
+
+        Self {
+            inner: Arc::new(PoolInner {
+                hosts,
+                balancing,
+                next: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    /// Current health and load of every host.
+    #[must_use]
+    pub fn status(&self) -> Vec<HostStatus> {
+        self.inner
+            .hosts
+            .iter()
+            .map(|host| HostStatus {
+                url: host.ollama.url.clone(),
+                healthy: host.is_healthy(),
+                in_flight: host.in_flight.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    pub(crate) fn hosts(&self) -> &[Arc<PoolHost>] {
+        &self.inner.hosts
+    }
+
+    /// Probe every host with `/api/version` and update its health.
+    /// Hosts are probed concurrently, one that does not answer within 5s is unhealthy.
+    pub async fn check_health(&self) {
+        Self::probe(&self.inner).await;
+    }
+
+    async fn probe(inner: &PoolInner) {
+        future::join_all(inner.hosts.iter().map(|host| async move {
+            let version = tokio::time::timeout(PROBE_TIMEOUT, host.ollama.version()).await;
+            host.set_healthy(matches!(version, Ok(Ok(_))));
+        }))
+        .await;
+    }
+
+    /// Probe all hosts every `interval` in the background, so ejected hosts are added back
+    /// once they recover. The task stops when the last clone of the pool is dropped.
+    ///
+    /// # Panics
+    /// If called outside of a Tokio runtime.
+    #[must_use = "dropping the handle does not stop the task, use `abort` to stop it early"]
+    pub fn spawn_health_checks(&self, interval: Duration) -> JoinHandle<()> {
+        let inner: Weak<PoolInner> = Arc::downgrade(&self.inner);
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+            loop {
+                ticker.tick().await;
+
+                let Some(inner) = inner.upgrade() else {
+                    break;
+                };
+
+                Self::probe(&inner).await;
+            }
+        })
+    }
+
+    /// Hosts in the order they should be tried: healthy hosts by the balancing strategy,
+    /// followed by ejected hosts as a last resort.
+    pub(crate) fn candidates(&self) -> Vec<Arc<PoolHost>> {
+        let hosts = &self.inner.hosts;
+        if hosts.is_empty() {
+            return vec![];
+        }
+
+        let start = self.inner.next.fetch_add(1, Ordering::Relaxed) % hosts.len();
+        let mut rotated = hosts[start..]
+            .iter()
+            .chain(&hosts[..start])
+            .cloned()
+            .collect::<Vec<_>>();
+
+        if self.inner.balancing == Balancing::LeastInFlight {
+            // Stable sort keeps the rotation as tie breaker
+            rotated.sort_by_key(|host| host.in_flight.load(Ordering::Relaxed));
+        }
+
+        let (mut healthy, ejected): (Vec<_>, Vec<_>) =
+            rotated.into_iter().partition(|host| host.is_healthy());
+        healthy.extend(ejected);
+
+        healthy
+    }
+
+    /// Run `f` on the candidates in order until one does not fail with a connection error.
+    pub(crate) async fn with_failover<T, F, Fut>(
+        candidates: Vec<Arc<PoolHost>>,
+        f: F,
+    ) -> crate::Result<T>
+    where
+        F: Fn(Ollama) -> Fut,
+        Fut: Future<Output = crate::Result<T>>,
+    {
+        let mut last_error = None;
+
+        for host in candidates {
+            let _guard = host.begin();
+
+            match f(host.ollama.clone()).await {
+                Err(e) if is_connection_error(&e) => {
+                    host.set_healthy(false);
+                    last_error = Some(e);
+                }
+                res => return res,
+            }
+        }
+
+        Err(last_error.unwrap_or_else(no_hosts))
+    }
+
+    /// Open a stream on the candidates in order until its first item is not a connection error.
+    /// Once the first item was yielded, the stream stays on that host.
+    pub(crate) fn stream_with_failover<T, F>(
+        candidates: Vec<Arc<PoolHost>>,
+        f: F,
+    ) -> ResultStream<T>
+    where
+        T: 'static,
+        F: Fn(Ollama) -> crate::Result<ResultStream<T>> + 'static,
+    {
+        Box::pin(stream! {
+            let mut last_error = None;
+
+            for host in candidates {
+                let _guard = host.begin();
+
+                let mut stream = match f(host.ollama.clone()) {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        yield Err(e);
+                        return;
+                    }
+                };
+
+                match stream.next().await {
+                    Some(Err(e)) if is_connection_error(&e) => {
+                        host.set_healthy(false);
+                        last_error = Some(e);
+                    }
+                    Some(first) => {
+                        yield first;
+                        while let Some(item) = stream.next().await {
+                            yield item;
+                        }
+                        return;
+                    }
+                    None => return,
+                }
+            }
+
+            yield Err(last_error.unwrap_or_else(no_hosts));
+        })
+    }
+
+    /// `Ollama::chat` on the next host.
+    ///
+    /// # Errors
+    ///
+    /// Same as `Ollama::chat`.
+    /// If all hosts fail with a connection error, the last one is yielded.
+    pub fn chat(
+        &self,
+        request: ChatRequest,
+        history: History,
+    ) -> crate::Result<ChatResponseStream> {
+        self.chat_with_options(request, history, StreamOptions::default())
+    }
+
+    /// `Ollama::chat_with_options` on the next host.
+    ///
+    /// # Errors
+    ///
+    /// Same as `Ollama::chat_with_options`.
+    /// If all hosts fail with a connection error, the last one is yielded.
+    pub fn chat_with_options(
+        &self,
+        request: ChatRequest,
+        history: History,
+        options: StreamOptions,
+    ) -> crate::Result<ChatResponseStream> {
+        Self::chat_on(self.candidates(), request, history, options)
+    }
+
+    pub(crate) fn chat_on(
+        candidates: Vec<Arc<PoolHost>>,
+        request: ChatRequest,
+        history: History,
+        options: StreamOptions,
+    ) -> crate::Result<ChatResponseStream> {
+        let history_len = history.messages()?.len();
+
+        Ok(Self::stream_with_failover(candidates, move |ollama| {
+            // Undo the messages a failed attempt added to the history
+            history.messages_mut()?.truncate(history_len);
+            ollama.chat_with_options(request.clone(), history.clone(), options.clone())
+        }))
+    }
+
+    /// `Ollama::generate` on the next host.
+    ///
+    /// # Errors
+    ///
+    /// Same as `Ollama::generate`.
+    /// If all hosts fail with a connection error, the last one is returned.
+    pub async fn generate(
+        &self,
+        request: GenerateRequest,
+    ) -> crate::Result<GenerateResponseStream> {
+        Self::generate_on(self.candidates(), request).await
+    }
+
+    pub(crate) async fn generate_on(
+        candidates: Vec<Arc<PoolHost>>,
+        request: GenerateRequest,
+    ) -> crate::Result<GenerateResponseStream> {
+        let mut last_error = None;
+
+        for host in candidates {
+            let guard = host.begin();
+
+            match host.ollama.generate(request.clone()).await {
+                Ok(mut stream) => {
+                    return Ok(Box::pin(stream! {
+                        let _guard = guard;
+                        while let Some(item) = stream.next().await {
+                            yield item;
+                        }
+                    }));
+                }
+                Err(e) if is_connection_error(&e) => {
+                    host.set_healthy(false);
+                    last_error = Some(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(last_error.unwrap_or_else(no_hosts))
+    }
+
+    /// `Ollama::generate_with_options` on the next host.
+    ///
+    /// # Errors
+    ///
+    /// Same as `Ollama::generate_with_options`.
+    /// If all hosts fail with a connection error, the last one is yielded.
+    pub fn generate_with_options(
+        &self,
+        request: GenerateRequest,
+        options: StreamOptions,
+    ) -> crate::Result<GenerateResponseStream> {
+        Ok(Self::generate_with_options_on(
+            self.candidates(),
+            request,
+            options,
+        ))
+    }
+
+    pub(crate) fn generate_with_options_on(
+        candidates: Vec<Arc<PoolHost>>,
+        request: GenerateRequest,
+        options: StreamOptions,
+    ) -> GenerateResponseStream {
+        Self::stream_with_failover(candidates, move |ollama| {
+            ollama.generate_with_options(request.clone(), options.clone())
+        })
+    }
+
+    /// `Ollama::generate_without_stream` on the next host.
+    ///
+    /// # Errors
+    ///
+    /// Same as `Ollama::generate_without_stream`.
+    /// If all hosts fail with a connection error, the last one is returned.
+    pub async fn generate_without_stream(
+        &self,
+        request: GenerateRequest,
+    ) -> crate::Result<GenerateResponse> {
+        Self::generate_without_stream_on(self.candidates(), request).await
+    }
+
+    pub(crate) async fn generate_without_stream_on(
+        candidates: Vec<Arc<PoolHost>>,
+        request: GenerateRequest,
+    ) -> crate::Result<GenerateResponse> {
+        Self::with_failover(candidates, |ollama| {
+            let request = request.clone();
+            async move { ollama.generate_without_stream(request).await }
+        })
+        .await
+    }
+
+    /// `Ollama::generate_embeddings` on the next host.
+    ///
+    /// # Errors
+    ///
+    /// Same as `Ollama::generate_embeddings`.
+    /// If all hosts fail with a connection error, the last one is returned.
+    pub async fn generate_embeddings(&self, request: EmbedRequest) -> crate::Result<EmbedResponse> {
+        Self::generate_embeddings_on(self.candidates(), request).await
+    }
+
+    pub(crate) async fn generate_embeddings_on(
+        candidates: Vec<Arc<PoolHost>>,
+        request: EmbedRequest,
+    ) -> crate::Result<EmbedResponse> {
+        Self::with_failover(candidates, |ollama| {
+            let request = request.clone();
+            async move { ollama.generate_embeddings(request).await }
+        })
+        .await
+    }
+
+    /// `Ollama::generate_embeddings_chunked` on the next host.
+    /// All chunks are sent to the same host.
+    ///
+    /// # Errors
+    ///
+    /// Same as `Ollama::generate_embeddings_chunked`.
+    /// If all hosts fail with a connection error, the last one is yielded.
+    pub fn generate_embeddings_chunked(
+        &self,
+        request: EmbedRequest,
+        chunk_size: usize,
+    ) -> crate::Result<EmbedResponseStream> {
+        Ok(Self::generate_embeddings_chunked_on(
+            self.candidates(),
+            request,
+            chunk_size,
+        ))
+    }
+
+    pub(crate) fn generate_embeddings_chunked_on(
+        candidates: Vec<Arc<PoolHost>>,
+        request: EmbedRequest,
+        chunk_size: usize,
+    ) -> EmbedResponseStream {
+        Self::stream_with_failover(candidates, move |ollama| {
+            ollama.generate_embeddings_chunked(request.clone(), chunk_size)
+        })
+    }
+}
+
+/// True if the request never reached Ollama, so it is safe to send it to another host.
+pub(crate) fn is_connection_error(error: &OllamaError) -> bool {
+    matches!(error, OllamaError::NetworkError(e) if e.is_connect())
+}
+
+fn no_hosts() -> OllamaError {
+    OllamaError::Other("No hosts available in pool".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(balancing: Balancing) -> OllamaPool {
+        let hosts = ["a", "b", "c"].map(|host| {
+            Ollama::default()
+                .with_url(format!("http://{host}:11434"))
+                .unwrap()
+        });
+        OllamaPool::with_balancing(hosts.to_vec(), balancing)
+    }
+
+    /// Host names in candidate order.
+    fn order(pool: &OllamaPool) -> Vec<String> {
+        pool.candidates()
+            .iter()
+            .map(|host| host.ollama.url.host_str().unwrap_or_default().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn round_robin_rotates_and_tries_ejected_hosts_last() {
+        let pool = pool(Balancing::RoundRobin);
+
+        assert_eq!(order(&pool), ["a", "b", "c"]);
+        assert_eq!(order(&pool), ["b", "c", "a"]);
+
+        pool.hosts()[0].set_healthy(false);
+        assert_eq!(order(&pool), ["c", "b", "a"]);
+        assert_eq!(order(&pool), ["b", "c", "a"]);
+
+        pool.hosts()[0].set_healthy(true);
+        assert_eq!(order(&pool), ["b", "c", "a"]);
+    }
+
+    #[test]
+    fn least_in_flight_prefers_idle_hosts() {
+        let pool = pool(Balancing::LeastInFlight);
+        let hosts = pool.hosts();
+        let _guards = [hosts[0].begin(), hosts[0].begin(), hosts[2].begin()];
+
+        assert_eq!(order(&pool), ["b", "c", "a"]);
+
+        hosts[1].set_healthy(false);
+        assert_eq!(order(&pool), ["c", "a", "b"]);
+    }
+
+    #[test]
+    fn in_flight_guard_releases_on_drop() {
+        let pool = pool(Balancing::LeastInFlight);
+        drop(pool.hosts()[1].begin());
+
+        assert!(pool.status().iter().all(|status| status.in_flight == 0));
+    }
+
+    #[tokio::test]
+    async fn unreachable_hosts_are_marked_unhealthy() {
+        let pool = OllamaPool::new(vec![
+            Ollama::default().with_url("http://127.0.0.1:1").unwrap(),
+        ]);
+
+        pool.check_health().await;
+
+        assert!(!pool.status()[0].healthy);
+    }
+}
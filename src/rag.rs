@@ -0,0 +1,425 @@
+use std::{pin::Pin, sync::Arc};
+
+use serde_json::Value;
+use tokio::sync::RwLock;
+
+use crate::{
+    OllamaError,
+    generation::{
+        chat::{
+            ChatResponseStream,
+            history::History,
+            message::{Message, Role},
+            request::ChatRequest,
+        },
+        rerank::{ranked::RankOptions, reranker::Reranker},
+        tools::{Tool, ToolCallArguments, ToolFunction},
+    },
+    ollama::Ollama,
+    splitter::{CharEstimate, TokenCounter},
+    store::{Filter, HybridOptions, VectorStore},
+};
+
+const DEFAULT_INSTRUCTIONS: &str = "Answer using the numbered sources below. \
+Cite the sources you use like [1]. \
+If the sources do not contain the answer, say so.";
+
+/// A retrieved document, numbered for citation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Passage {
+    /// Number used to cite this passage, starting at 1
+    pub citation: usize,
+
+    /// Id of the document in the `VectorStore`
+    pub id: u64,
+    pub text: String,
+    pub metadata: Value,
+
+    /// Rerank relevance score, or the retrieval score without a reranker
+    pub score: f32,
+}
+
+/// Passages packed into a message for the model.
+#[derive(Debug, Clone)]
+pub struct RagContext {
+    pub passages: Vec<Passage>,
+
+    /// System message with the instructions and the numbered passages
+    pub message: Message,
+}
+
+impl RagContext {
+    /// Passages cited in `answer` with `[n]`, in order of first citation.
+    #[must_use]
+    pub fn cited(&self, answer: &str) -> Vec<&Passage> {
+        let mut cited: Vec<&Passage> = Vec::new();
+
+        for citation in citations(answer) {
+            if let Some(passage) = self.passages.iter().find(|p| p.citation == citation)
+                && !cited.iter().any(|p| p.citation == citation)
+            {
+                cited.push(passage);
+            }
+        }
+
+        cited
+    }
+}
+
+/// Result of `RagPipeline::chat`.
+pub struct RagChat {
+    pub stream: ChatResponseStream,
+    pub context: RagContext,
+}
+
+/// Retrieval-augmented generation over a `VectorStore`.
+///
+/// For a query, the `retrieve` best documents are fetched from the store, optionally
+/// reranked down to `top_k`, and packed into a system message of at most
+/// `max_context_tokens`. Every passage is numbered, so answers can cite their sources.
+///
+/// The pipeline is also a `Tool`, so the model can search on its own.
+///
+/// This struct uses Arc internally, clones share the same store.
+#[derive(Debug, Clone)]
+pub struct RagPipeline {
+    store: Arc<RwLock<VectorStore>>,
+    reranker: Option<Arc<dyn Reranker>>,
+    retrieve: usize,
+    top_k: usize,
+    min_score: Option<f32>,
+    filter: Option<Filter>,
+    hybrid: Option<HybridOptions>,
+    max_context_tokens: usize,
+    estimate: CharEstimate,
+    instructions: String,
+    tool_name: String,
+    tool_description: String,
+}
+
+impl RagPipeline {
+    /// Retrieves 20 candidates, keeps 5 and packs at most 2048 tokens by default.
+    #[must_use]
+    pub fn new(store: VectorStore) -> Self {
+        Self::from_shared(Arc::new(RwLock::new(store)))
+    }
+
+    /// Use a store that is shared with other code, e.g. to keep adding documents.
+    #[must_use]
+    pub fn from_shared(store: Arc<RwLock<VectorStore>>) -> Self {
+        Self {
+            store,
+            reranker: None,
+            retrieve: 20,
+            top_k: 5,
+            min_score: None,
+            filter: None,
+            hybrid: None,
+            max_context_tokens: 2048,
+            estimate: CharEstimate::default(),
+            instructions: DEFAULT_INSTRUCTIONS.to_string(),
+            tool_name: "search_documents".to_string(),
+            tool_description: "Search the document collection. Returns numbered passages."
+                .to_string(),
+        }
+    }
+
+    /// Rerank the candidates, e.g. with `LlamaReranker` or `OllamaReranker`.
+    #[must_use]
+    pub fn reranker<R: Reranker + 'static>(mut self, reranker: R) -> Self {
+        self.reranker = Some(Arc::new(reranker));
+        self
+    }
+
+    /// Candidates fetched from the store.
+    #[must_use]
+    pub fn retrieve(mut self, n: usize) -> Self {
+        self.retrieve = n.max(1);
+        self
+    }
+
+    /// Passages kept after reranking.
+    #[must_use]
+    pub fn top_k(mut self, k: usize) -> Self {
+        self.top_k = k.max(1);
+        self
+    }
+
+    /// Drop passages scoring below `score`.
+    #[must_use]
+    pub fn min_score(mut self, score: f32) -> Self {
+        self.min_score = Some(score);
+        self
+    }
+
+    /// Only retrieve documents whose metadata matches `filter`.
+    #[must_use]
+    pub fn filter(mut self, filter: Filter) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Retrieve candidates with `VectorStore::hybrid_search`, which needs a BM25 index.
+    #[must_use]
+    pub fn hybrid(mut self, options: HybridOptions) -> Self {
+        self.hybrid = Some(options);
+        self
+    }
+
+    /// Token budget for the packed passages, estimated from their length.
+    #[must_use]
+    pub fn max_context_tokens(mut self, tokens: usize) -> Self {
+        self.max_context_tokens = tokens;
+        self
+    }
+
+    /// Text before the passages in the context message.
+    #[must_use]
+    pub fn instructions<S: Into<String>>(mut self, instructions: S) -> Self {
+        self.instructions = instructions.into();
+        self
+    }
+
+    /// Name and description the model sees when the pipeline is used as a tool.
+    #[must_use]
+    pub fn tool_info<S: Into<String>>(mut self, name: S, description: S) -> Self {
+        self.tool_name = name.into();
+        self.tool_description = description.into();
+        self
+    }
+
+    #[must_use]
+    pub fn store(&self) -> Arc<RwLock<VectorStore>> {
+        self.store.clone()
+    }
+
+    /// The best passages for `query`, best first.
+    ///
+    /// # Errors
+    ///
+    /// If the query cannot be embedded.
+    /// If hybrid retrieval is enabled, but the store has no BM25 index.
+    /// If reranking fails.
+    pub async fn retrieve_passages(&self, query: &str) -> crate::Result<Vec<Passage>> {
+        // Not holding the lock while embedding, so writers are not blocked on Ollama
+        let embedding = self.store.read().await.embed_query_detached(query);
+        let embedding = embedding.await?;
+
+        let mut passages = {
+            let store = self.store.read().await;
+            let hits = match &self.hybrid {
+                Some(options) => store.hybrid_search_by_embedding(
+                    query,
+                    &embedding,
+                    self.retrieve,
+                    self.filter.as_ref(),
+                    options,
+                )?,
+                None => store.search_by_embedding(&embedding, self.retrieve, self.filter.as_ref()),
+            };
+
+            hits.into_iter()
+                .map(|hit| Passage {
+                    citation: 0,
+                    id: hit.document.id,
+                    text: hit.document.text.clone(),
+                    metadata: hit.document.metadata.clone(),
+                    score: hit.score,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        if let Some(reranker) = &self.reranker
+            && !passages.is_empty()
+        {
+            let documents = passages.iter().map(|p| p.text.clone()).collect::<Vec<_>>();
+            let options = RankOptions::default().top_n(self.top_k);
+            passages = reranker
+                .rerank(query, &documents)
+                .await?
+                .rank_owned(passages, &options)
+                .into_iter()
+                .map(|ranked| Passage {
+                    score: ranked.score,
+                    ..ranked.document
+                })
+                .collect();
+        }
+
+        if let Some(min_score) = self.min_score {
+            passages.retain(|p| p.score >= min_score);
+        }
+
+        passages.truncate(self.top_k);
+        for (i, passage) in passages.iter_mut().enumerate() {
+            passage.citation = i + 1;
+        }
+
+        Ok(passages)
+    }
+
+    /// Retrieve passages for `query` and pack them into a context message.
+    ///
+    /// # Errors
+    ///
+    /// Same as `retrieve_passages`.
+    pub async fn context(&self, query: &str) -> crate::Result<RagContext> {
+        let passages = self.pack(self.retrieve_passages(query).await?);
+        let message = Message::system(format!(
+            "{}\n\n{}",
+            self.instructions,
+            format_passages(&passages)
+        ));
+
+        Ok(RagContext { passages, message })
+    }
+
+    /// Keep passages in order while they fit into the token budget, renumbered.
+    fn pack(&self, passages: Vec<Passage>) -> Vec<Passage> {
+        let mut used = 0;
+        let mut packed = Vec::new();
+
+        for mut passage in passages {
+            passage.citation = packed.len() + 1;
+            let formatted = format_passage(&passage);
+            let tokens = self.estimate.count(&formatted, 0..formatted.len());
+
+            if used + tokens <= self.max_context_tokens {
+                used += tokens;
+                packed.push(passage);
+            }
+        }
+
+        packed
+    }
+
+    /// Whether `message` is a context message of this pipeline.
+    fn is_context(&self, message: &Message) -> bool {
+        message.role == Role::System
+            && message
+                .content
+                .strip_prefix(self.instructions.as_str())
+                .is_some_and(|rest| rest.starts_with("\n\n"))
+    }
+
+    /// Add a context message for the last user message of `request`, right before it.
+    /// Context messages of earlier turns are removed from the request.
+    ///
+    /// # Errors
+    ///
+    /// If the request has no user message.
+    /// Same as `context`.
+    pub async fn augment(
+        &self,
+        mut request: ChatRequest,
+    ) -> crate::Result<(ChatRequest, RagContext)> {
+        request.messages.retain(|m| !self.is_context(m));
+
+        let position = request
+            .messages
+            .iter()
+            .rposition(|m| m.role == Role::User)
+            .ok_or_else(|| OllamaError::Other("Request has no user message".to_string()))?;
+
+        let context = self.context(&request.messages[position].content).await?;
+        request.messages.insert(position, context.message.clone());
+
+        Ok((request, context))
+    }
+
+    /// `Ollama::chat` with a context message for the last user message.
+    /// The context message of the previous turn is removed from `history`,
+    /// so it only keeps the one of the current turn.
+    ///
+    /// # Errors
+    ///
+    /// Same as `augment` and `Ollama::chat`.
+    pub async fn chat(
+        &self,
+        ollama: &Ollama,
+        request: ChatRequest,
+        history: History,
+    ) -> crate::Result<RagChat> {
+        let (request, context) = self.augment(request).await?;
+        history.messages_mut()?.retain(|m| !self.is_context(m));
+        let stream = ollama.chat(request, history)?;
+
+        Ok(RagChat { stream, context })
+    }
+}
+
+impl Tool for RagPipeline {
+    fn tool_function(&self) -> ToolFunction {
+        ToolFunction::new(self.tool_name.as_str(), self.tool_description.as_str()).parameter(
+            "query",
+            "What to search for, as a question or keywords",
+            true,
+        )
+    }
+
+    fn execute(
+        &self,
+        arguments: ToolCallArguments,
+    ) -> Pin<Box<dyn Future<Output = Result<String, String>> + Send + Sync + 'static>> {
+        let pipeline = self.clone();
+
+        // Spawned, because the request futures are not `Sync`
+        let task = tokio::spawn(async move {
+            let query = arguments
+                .get("query")
+                .ok_or_else(|| "Missing argument: query".to_string())?;
+
+            let passages = pipeline
+                .retrieve_passages(query)
+                .await
+                .map_err(|e| e.to_string())?;
+            let passages = pipeline.pack(passages);
+
+            if passages.is_empty() {
+                return Ok("No matching documents found.".to_string());
+            }
+
+            Ok(format_passages(&passages))
+        });
+
+        Box::pin(async move { task.await.map_err(|e| e.to_string())? })
+    }
+}
+
+fn format_passage(passage: &Passage) -> String {
+    format!("[{}] {}", passage.citation, passage.text)
+}
+
+fn format_passages(passages: &[Passage]) -> String {
+    passages
+        .iter()
+        .map(format_passage)
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// Numbers in `[n]` and `[n, m]` markers.
+fn citations(answer: &str) -> Vec<usize> {
+    let mut numbers = Vec::new();
+    let mut rest = answer;
+
+    while let Some(open) = rest.find('[') {
+        rest = &rest[open + 1..];
+        let Some(close) = rest.find(']') else {
+            break;
+        };
+
+        let inner = &rest[..close];
+        let parsed = inner
+            .split(',')
+            .map(|n| n.trim().parse::<usize>())
+            .collect::<Result<Vec<_>, _>>();
+
+        if let Ok(parsed) = parsed {
+            numbers.extend(parsed);
+        }
+        rest = &rest[close + 1..];
+    }
+
+    numbers
+}
@@ -0,0 +1,260 @@
+use std::time::Duration;
+
+use reqwest::{RequestBuilder, Response, StatusCode, header::RETRY_AFTER};
+
+/// Opt-in policy for retrying requests that failed for transient reasons,
+/// e.g. Ollama returning 503 while loading a model or a reset connection.
+///
+/// Only the initial request is retried. Once a streaming response was received,
+/// the stream is never replayed.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Maximum number of attempts, including the first one.
+    pub max_attempts: u32,
+
+    /// Delay before the first retry.
+    pub initial_backoff: Duration,
+
+    /// Upper bound for the delay between two attempts.
+    pub max_backoff: Duration,
+
+    /// Factor the delay grows by after every attempt.
+    pub multiplier: f64,
+
+    /// Fraction of the delay that is randomized, between 0.0 and 1.0.
+    pub jitter: f64,
+
+    /// HTTP status codes that are retried.
+    pub retry_statuses: Vec<StatusCode>,
+
+    /// Retry if the connection could not be established.
+    pub retry_connect: bool,
+
+    /// Retry if the request timed out.
+    pub retry_timeout: bool,
+
+    /// Retry if sending the request failed, e.g. the connection was reset.
+    pub retry_request: bool,
+}
+
+impl Default for RetryPolicy {
+    /// 3 attempts, starting at 500ms and doubling up to 10s with 50% jitter.
+    /// Retries 408, 429, 502, 503 and 504 as well as connection errors and timeouts.
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(10),
+            multiplier: 2.0,
+            jitter: 0.5,
+            retry_statuses: vec![
+                StatusCode::REQUEST_TIMEOUT,
+                StatusCode::TOO_MANY_REQUESTS,
+                StatusCode::BAD_GATEWAY,
+                StatusCode::SERVICE_UNAVAILABLE,
+                StatusCode::GATEWAY_TIMEOUT,
+            ],
+            retry_connect: true,
+            retry_timeout: true,
+            retry_request: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Maximum number of attempts, including the first one.
+    #[must_use]
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    /// Delay before the first retry and upper bound for all delays.
+    #[must_use]
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Factor the delay grows by after every attempt.
+    #[must_use]
+    pub fn multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    /// Fraction of the delay that is randomized, between 0.0 and 1.0.
+    #[must_use]
+    pub fn jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    /// HTTP status codes that are retried.
+    #[must_use]
+    pub fn retry_statuses(mut self, statuses: Vec<StatusCode>) -> Self {
+        self.retry_statuses = statuses;
+        self
+    }
+
+    /// Which kinds of network errors are retried.
+    #[must_use]
+    pub fn retry_errors(mut self, connect: bool, timeout: bool, request: bool) -> Self {
+        self.retry_connect = connect;
+        self.retry_timeout = timeout;
+        self.retry_request = request;
+        self
+    }
+
+    /// True if a response with this status should be retried.
+    #[must_use]
+    pub fn is_retryable_status(&self, status: StatusCode) -> bool {
+        self.retry_statuses.contains(&status)
+    }
+
+    /// True if this error should be retried.
+    #[must_use]
+    pub fn is_retryable_error(&self, error: &reqwest::Error) -> bool {
+        (self.retry_connect && error.is_connect())
+            || (self.retry_timeout && error.is_timeout())
+            || (self.retry_request && error.is_request())
+    }
+
+    /// Delay after the given failed attempt, starting at 1.
+    #[must_use]
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exponent = i32::try_from(attempt.saturating_sub(1)).unwrap_or(i32::MAX);
+        let backoff = self.initial_backoff.as_secs_f64() * self.multiplier.powi(exponent);
+        let backoff = backoff.min(self.max_backoff.as_secs_f64());
+        let jitter = 1.0 - self.jitter * fastrand::f64();
+
+        Duration::try_from_secs_f64(backoff * jitter).unwrap_or(self.max_backoff)
+    }
+}
+
+/// Send the request, retrying according to `policy`.
+/// Unsuccessful status codes are turned into errors.
+pub(crate) async fn send(
+    policy: Option<&RetryPolicy>,
+    request: RequestBuilder,
+) -> crate::Result<Response> {
+    let Some(policy) = policy else {
+        return check_status(request.send().await?).await;
+    };
+
+    let mut attempt = 1;
+    loop {
+        // Requests with streaming bodies cannot be cloned and are sent only once
+        let Some(current) = request.try_clone() else {
+            return check_status(request.send().await?).await;
+        };
+
+        let delay = match current.send().await {
+            Ok(response) if response.status().is_success() => return Ok(response),
+            Ok(response)
+                if attempt < policy.max_attempts
+                    && policy.is_retryable_status(response.status()) =>
+            {
+                let delay = policy.delay(attempt);
+                retry_after(&response)
+                    .map_or(delay, |after| delay.max(after.min(policy.max_backoff)))
+            }
+            Ok(response) => return check_status(response).await,
+            Err(e) if attempt < policy.max_attempts && policy.is_retryable_error(&e) => {
+                policy.delay(attempt)
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+/// Turn an unsuccessful response into an error containing the response body.
+pub(crate) async fn check_status(response: Response) -> crate::Result<Response> {
+    let status = response.status();
+    if !status.is_success() {
+        return Err(crate::OllamaError::Status {
+            status,
+            body: response.text().await.unwrap_or_default(),
+        });
+    }
+
+    Ok(response)
+}
+
+fn retry_after(response: &Response) -> Option<Duration> {
+    let seconds = response
+        .headers()
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+
+    Some(Duration::from_secs(seconds))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(jitter: f64) -> RetryPolicy {
+        RetryPolicy::default()
+            .backoff(Duration::from_millis(100), Duration::from_secs(1))
+            .multiplier(2.0)
+            .jitter(jitter)
+    }
+
+    #[test]
+    fn backoff_grows_up_to_the_maximum() {
+        let policy = policy(0.0);
+        let delays = (1..=6).map(|a| policy.delay(a)).collect::<Vec<_>>();
+
+        assert_eq!(
+            delays,
+            [100, 200, 400, 800, 1000, 1000].map(Duration::from_millis)
+        );
+        assert_eq!(policy.delay(0), Duration::from_millis(100));
+        assert_eq!(policy.delay(u32::MAX), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn jitter_only_shortens_the_delay() {
+        let (exact, jittered) = (policy(0.0), policy(0.5));
+
+        for attempt in 1..=6 {
+            let backoff = exact.delay(attempt);
+            for _ in 0..100 {
+                let delay = jittered.delay(attempt);
+                assert!(delay <= backoff, "{delay:?} > {backoff:?}");
+                assert!(delay >= backoff / 2, "{delay:?} < {backoff:?} / 2");
+            }
+        }
+    }
+
+    #[test]
+    fn jitter_is_clamped() {
+        assert!((policy(2.0).jitter - 1.0).abs() < f64::EPSILON);
+        assert!(policy(-1.0).jitter.abs() < f64::EPSILON);
+
+        // Full jitter never goes negative
+        let full = policy(2.0);
+        for _ in 0..100 {
+            assert!(full.delay(3) <= Duration::from_millis(400));
+        }
+    }
+
+    #[test]
+    fn default_statuses() {
+        let policy = RetryPolicy::default();
+
+        assert!(policy.is_retryable_status(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(policy.is_retryable_status(StatusCode::TOO_MANY_REQUESTS));
+        assert!(!policy.is_retryable_status(StatusCode::NOT_FOUND));
+        assert!(!policy.is_retryable_status(StatusCode::INTERNAL_SERVER_ERROR));
+    }
+}
@@ -0,0 +1,366 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex, RwLock, Weak},
+    time::{Duration, Instant},
+};
+
+use async_stream::stream;
+use futures_util::future;
+use reqwest::Url;
+use tokio::task::JoinHandle;
+use tokio_stream::StreamExt;
+
+use crate::{
+    generation::{
+        chat::{ChatResponseStream, history::History, request::ChatRequest},
+        embed::{EmbedResponseStream, request::EmbedRequest, response::EmbedResponse},
+        generate::{GenerateResponseStream, request::GenerateRequest, response::GenerateResponse},
+        stream::StreamOptions,
+    },
+    misc::pull_model::PullModelRequest,
+    pool::{OllamaPool, PoolHost},
+};
+
+/// Models known to be on a host.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HostInventory {
+    /// Models pulled on the host, from `/api/tags`
+    pub available: HashSet<String>,
+
+    /// Models loaded into memory, from `/api/ps`
+    pub loaded: HashSet<String>,
+}
+
+#[derive(Debug, Default)]
+struct RouterState {
+    inventory: RwLock<HashMap<Url, HostInventory>>,
+    refreshed_at: Mutex<Option<Instant>>,
+    refreshing: tokio::sync::Mutex<()>,
+}
+
+/// Routes requests of an `OllamaPool` to the hosts that have `request.model`.
+///
+/// Hosts where the model is already loaded are preferred, followed by hosts where it is
+/// pulled. Within each group, the pool's balancing and failover apply.
+/// The inventory of every host is cached and refreshed once it is older than `max_age`,
+/// or in the background with `spawn_refresh`.
+///
+/// This struct uses Arc internally and is cheap to clone.
+#[derive(Debug, Clone)]
+pub struct ModelRouter {
+    pool: OllamaPool,
+    max_age: Duration,
+    pull_missing: bool,
+    state: Arc<RouterState>,
+}
+
+impl ModelRouter {
+    /// Creates a router with an inventory max age of 30s, which does not pull missing models.
+    #[must_use]
+    pub fn new(pool: OllamaPool) -> Self {
+        Self {
+            pool,
+            max_age: Duration::from_secs(30),
+            pull_missing: false,
+            state: Arc::default(),
+        }
+    }
+
+    /// How long the cached inventory is used before it is refreshed on the next request.
+    #[must_use]
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    /// Pull the model on the next host if no host has it.
+    /// Nothing is pulled while no host could be queried.
+    #[must_use]
+    pub fn pull_missing(mut self, pull_missing: bool) -> Self {
+        self.pull_missing = pull_missing;
+        self
+    }
+
+    #[must_use]
+    pub fn pool(&self) -> &OllamaPool {
+        &self.pool
+    }
+
+    /// Cached inventory of every host that could be queried.
+    #[must_use]
+    pub fn inventory(&self) -> HashMap<Url, HostInventory> {
+        self.state
+            .inventory
+            .read()
+            .map(|inventory| inventory.clone())
+            .unwrap_or_default()
+    }
+
+    /// Query `/api/tags` and `/api/ps` on every host and replace the cached inventory.
+    /// Hosts that cannot be queried are removed from the inventory.
+    pub async fn refresh(&self) {
+        Self::refresh_state(&self.pool, &self.state).await;
+    }
+
+    async fn refresh_state(pool: &OllamaPool, state: &RouterState) {
+        let _refreshing = state.refreshing.lock().await;
+        Self::update(pool, state).await;
+    }
+
+    /// Query all hosts concurrently, the caller holds `state.refreshing`.
+    async fn update(pool: &OllamaPool, state: &RouterState) {
+        let inventory = future::join_all(pool.hosts().iter().map(|host| async move {
+            let (available, loaded) =
+                tokio::join!(host.ollama.list_models(), host.ollama.list_running_models());
+
+            let (Ok(available), Ok(loaded)) = (available, loaded) else {
+                return None;
+            };
+
+            let inventory = HostInventory {
+                available: available
+                    .models
+                    .iter()
+                    .map(|m| normalize_model_name(&m.name))
+                    .collect(),
+                loaded: loaded
+                    .models
+                    .iter()
+                    .map(|m| normalize_model_name(&m.name))
+                    .collect(),
+            };
+
+            Some((host.ollama.url.clone(), inventory))
+        }))
+        .await;
+
+        if let Ok(mut cached) = state.inventory.write() {
+            *cached = inventory.into_iter().flatten().collect();
+        }
+
+        if let Ok(mut refreshed_at) = state.refreshed_at.lock() {
+            *refreshed_at = Some(Instant::now());
+        }
+    }
+
+    /// Refresh the inventory every `interval` in the background.
+    /// The task stops when the last clone of the router is dropped.
+    ///
+    /// # Panics
+    /// If called outside of a Tokio runtime.
+    #[must_use = "dropping the handle does not stop the task, use `abort` to stop it early"]
+    pub fn spawn_refresh(&self, interval: Duration) -> JoinHandle<()> {
+        let pool = self.pool.clone();
+        let state: Weak<RouterState> = Arc::downgrade(&self.state);
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+            loop {
+                ticker.tick().await;
+
+                let Some(state) = state.upgrade() else {
+                    break;
+                };
+
+                Self::refresh_state(&pool, &state).await;
+            }
+        })
+    }
+
+    fn is_stale(&self) -> bool {
+        self.state
+            .refreshed_at
+            .lock()
+            .map_or(true, |at| at.is_none_or(|at| at.elapsed() >= self.max_age))
+    }
+
+    /// Pool candidates ordered by where `model` is loaded, then where it is available.
+    async fn route(&self, model: &str) -> crate::Result<Vec<Arc<PoolHost>>> {
+        if self.is_stale() {
+            let _refreshing = self.state.refreshing.lock().await;
+
+            // Another request may have refreshed while this one waited
+            if self.is_stale() {
+                Self::update(&self.pool, &self.state).await;
+            }
+        }
+
+        let model = normalize_model_name(model);
+        let mut candidates = self.pool.candidates();
+
+        let rank = |host: &Arc<PoolHost>| {
+            let inventory = self.state.inventory.read().ok();
+            match inventory.as_ref().and_then(|i| i.get(&host.ollama.url)) {
+                Some(host) if host.loaded.contains(&model) => 0,
+                Some(host) if host.available.contains(&model) => 1,
+                _ => 2,
+            }
+        };
+
+        // Without an answer from any host, the model may well be there
+        let answered = self
+            .state
+            .inventory
+            .read()
+            .is_ok_and(|inventory| !inventory.is_empty());
+        let missing = candidates.iter().all(|host| rank(host) == 2);
+        if answered
+            && missing
+            && self.pull_missing
+            && let Some(host) = candidates.first()
+        {
+            host.ollama
+                .pull_model(PullModelRequest::new(model.clone()))
+                .await?;
+
+            if let Ok(mut inventory) = self.state.inventory.write() {
+                inventory
+                    .entry(host.ollama.url.clone())
+                    .or_default()
+                    .available
+                    .insert(model.clone());
+            }
+        }
+
+        // Stable sort keeps the pool's order within each group
+        candidates.sort_by_key(|host| rank(host));
+
+        Ok(candidates)
+    }
+
+    /// `Ollama::chat` on the best host for `request.model`.
+    ///
+    /// # Errors
+    ///
+    /// Same as `OllamaPool::chat`.
+    /// If pulling a missing model fails, the error is yielded.
+    pub fn chat(
+        &self,
+        request: ChatRequest,
+        history: History,
+    ) -> crate::Result<ChatResponseStream> {
+        self.chat_with_options(request, history, StreamOptions::default())
+    }
+
+    /// `Ollama::chat_with_options` on the best host for `request.model`.
+    ///
+    /// # Errors
+    ///
+    /// Same as `OllamaPool::chat_with_options`.
+    /// If pulling a missing model fails, the error is yielded.
+    pub fn chat_with_options(
+        &self,
+        request: ChatRequest,
+        history: History,
+        options: StreamOptions,
+    ) -> crate::Result<ChatResponseStream> {
+        let router = self.clone();
+
+        Ok(Box::pin(stream! {
+            let candidates = router.route(&request.model).await?;
+            let mut stream = OllamaPool::chat_on(candidates, request, history, options)?;
+
+            while let Some(item) = stream.next().await {
+                yield item;
+            }
+        }))
+    }
+
+    /// `Ollama::generate` on the best host for `request.model`.
+    ///
+    /// # Errors
+    ///
+    /// Same as `OllamaPool::generate`.
+    /// If pulling a missing model fails.
+    pub async fn generate(
+        &self,
+        request: GenerateRequest,
+    ) -> crate::Result<GenerateResponseStream> {
+        let candidates = self.route(&request.model).await?;
+        OllamaPool::generate_on(candidates, request).await
+    }
+
+    /// `Ollama::generate_with_options` on the best host for `request.model`.
+    ///
+    /// # Errors
+    ///
+    /// Same as `OllamaPool::generate_with_options`.
+    /// If pulling a missing model fails, the error is yielded.
+    pub fn generate_with_options(
+        &self,
+        request: GenerateRequest,
+        options: StreamOptions,
+    ) -> crate::Result<GenerateResponseStream> {
+        let router = self.clone();
+
+        Ok(Box::pin(stream! {
+            let candidates = router.route(&request.model).await?;
+            let mut stream = OllamaPool::generate_with_options_on(candidates, request, options);
+
+            while let Some(item) = stream.next().await {
+                yield item;
+            }
+        }))
+    }
+
+    /// `Ollama::generate_without_stream` on the best host for `request.model`.
+    ///
+    /// # Errors
+    ///
+    /// Same as `OllamaPool::generate_without_stream`.
+    /// If pulling a missing model fails.
+    pub async fn generate_without_stream(
+        &self,
+        request: GenerateRequest,
+    ) -> crate::Result<GenerateResponse> {
+        let candidates = self.route(&request.model).await?;
+        OllamaPool::generate_without_stream_on(candidates, request).await
+    }
+
+    /// `Ollama::generate_embeddings` on the best host for `request.model`.
+    ///
+    /// # Errors
+    ///
+    /// Same as `OllamaPool::generate_embeddings`.
+    /// If pulling a missing model fails.
+    pub async fn generate_embeddings(&self, request: EmbedRequest) -> crate::Result<EmbedResponse> {
+        let candidates = self.route(&request.model).await?;
+        OllamaPool::generate_embeddings_on(candidates, request).await
+    }
+
+    /// `Ollama::generate_embeddings_chunked` on the best host for `request.model`.
+    ///
+    /// # Errors
+    ///
+    /// Same as `OllamaPool::generate_embeddings_chunked`.
+    /// If pulling a missing model fails, the error is yielded.
+    pub fn generate_embeddings_chunked(
+        &self,
+        request: EmbedRequest,
+        chunk_size: usize,
+    ) -> crate::Result<EmbedResponseStream> {
+        let router = self.clone();
+
+        Ok(Box::pin(stream! {
+            let candidates = router.route(&request.model).await?;
+            let mut stream = OllamaPool::generate_embeddings_chunked_on(candidates, request, chunk_size);
+
+            while let Some(item) = stream.next().await {
+                yield item;
+            }
+        }))
+    }
+}
+
+/// Ollama treats `model` and `model:latest` as the same model.
+fn normalize_model_name(name: &str) -> String {
+    let tag_part = name.rsplit('/').next().unwrap_or(name);
+
+    if tag_part.contains(':') {
+        name.to_string()
+    } else {
+        format!("{name}:latest")
+    }
+}
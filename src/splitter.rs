@@ -0,0 +1,773 @@
+//! Splitting documents into chunks that fit a token budget, for embedding and retrieval.
+//!
+//! A document is split at the coarsest separator of the strategy, e.g. paragraphs, and parts
+//! that are still over budget are split again at the next finer separator. Adjacent parts
+//! are then merged into chunks of up to `max_tokens`, optionally overlapping.
+//!
+//! Every `TextChunk` keeps its byte offsets into the source, so rows of an `EmbedResponse`
+//! for `EmbedInput::from(&chunks[..])` map back to spans of the document.
+
+use std::ops::Range;
+
+use crate::{
+    generation::{
+        embed::request::EmbedInput,
+        tokenize::{request::TokenizeRequest, response::Token},
+    },
+    llama::Llama,
+};
+
+/// Counts the tokens of a span of a document.
+pub trait TokenCounter {
+    fn count(&self, text: &str, span: Range<usize>) -> usize;
+}
+
+/// Estimate tokens from the number of characters, without a tokenizer.
+///
+/// About 4 characters per token for English prose, 3 for code and 2 for languages
+/// without spaces between words.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CharEstimate {
+    pub chars_per_token: f32,
+}
+
+impl Default for CharEstimate {
+    fn default() -> Self {
+        Self {
+            chars_per_token: 4.0,
+        }
+    }
+}
+
+impl TokenCounter for CharEstimate {
+    fn count(&self, text: &str, span: Range<usize>) -> usize {
+        let chars = text[span].chars().count();
+
+        // Realistic documents are far below f32's exact integer range
+        #[allow(
+            clippy::cast_precision_loss,
+            clippy::cast_possible_truncation,
+            clippy::cast_sign_loss
+        )]
+        let tokens = (chars as f32 / self.chars_per_token).ceil() as usize;
+        tokens
+    }
+}
+
+/// Exact token positions of one document, from llama.cpp's tokenizer.
+#[derive(Debug, Clone)]
+struct TokenOffsets {
+    /// Byte offset of the start of every token
+    starts: Vec<usize>,
+}
+
+impl TokenCounter for TokenOffsets {
+    fn count(&self, _text: &str, span: Range<usize>) -> usize {
+        let first = self.starts.partition_point(|&start| start < span.start);
+        let last = self.starts.partition_point(|&start| start < span.end);
+        last - first
+    }
+}
+
+/// Programming languages with dedicated separators for `SplitStrategy::Code`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Language {
+    Rust,
+    Python,
+    JavaScript,
+    TypeScript,
+    Go,
+    Java,
+    C,
+    Cpp,
+}
+
+impl Language {
+    /// Lines starting a top level item, most important first.
+    fn separators(self) -> &'static [&'static str] {
+        match self {
+            Language::Rust => &[
+                "\nimpl ",
+                "\npub fn ",
+                "\nfn ",
+                "\npub struct ",
+                "\nstruct ",
+                "\npub enum ",
+                "\nenum ",
+                "\npub trait ",
+                "\ntrait ",
+                "\nmod ",
+                "\n    pub fn ",
+                "\n    fn ",
+            ],
+            Language::Python => &[
+                "\nclass ",
+                "\ndef ",
+                "\nasync def ",
+                "\n    def ",
+                "\n    async def ",
+            ],
+            Language::JavaScript => &[
+                "\nexport ",
+                "\nclass ",
+                "\nfunction ",
+                "\nasync function ",
+                "\nconst ",
+                "\nlet ",
+            ],
+            Language::TypeScript => &[
+                "\nexport ",
+                "\ninterface ",
+                "\ntype ",
+                "\nclass ",
+                "\nfunction ",
+                "\nasync function ",
+                "\nconst ",
+                "\nlet ",
+            ],
+            Language::Go => &["\nfunc ", "\ntype ", "\nvar ", "\nconst "],
+            Language::Java => &[
+                "\npublic class ",
+                "\nclass ",
+                "\ninterface ",
+                "\n    public ",
+                "\n    private ",
+                "\n    protected ",
+            ],
+            Language::C | Language::Cpp => &[
+                "\nnamespace ",
+                "\nclass ",
+                "\nstruct ",
+                "\nvoid ",
+                "\nint ",
+                "\nstatic ",
+            ],
+        }
+    }
+}
+
+/// Where documents are split, coarsest first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SplitStrategy {
+    /// Split at the separators in order, e.g. paragraphs, lines, words
+    Recursive(Vec<String>),
+
+    /// Split at paragraphs, then sentences, then words
+    Sentence,
+
+    /// Split before headings, then at paragraphs, lines and sentences.
+    /// Headings inside code fences are ignored.
+    Markdown,
+
+    /// Split before top level items of the language, then at blank lines and lines
+    Code(Language),
+}
+
+impl Default for SplitStrategy {
+    fn default() -> Self {
+        Self::Recursive(
+            ["\n\n", "\n", ". ", " "]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Separator {
+    Literal(String),
+    Sentence,
+    Heading,
+}
+
+impl SplitStrategy {
+    fn separators(&self) -> Vec<Separator> {
+        let literal = |s: &str| Separator::Literal(s.to_string());
+
+        match self {
+            SplitStrategy::Recursive(separators) => separators.iter().map(|s| literal(s)).collect(),
+            SplitStrategy::Sentence => vec![
+                literal("\n\n"),
+                literal("\n"),
+                Separator::Sentence,
+                literal(" "),
+            ],
+            SplitStrategy::Markdown => vec![
+                Separator::Heading,
+                literal("\n\n"),
+                literal("\n"),
+                Separator::Sentence,
+                literal(" "),
+            ],
+            SplitStrategy::Code(language) => language
+                .separators()
+                .iter()
+                .map(|s| literal(s))
+                .chain([literal("\n\n"), literal("\n"), literal(" ")])
+                .collect(),
+        }
+    }
+}
+
+/// A piece of a document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextChunk {
+    pub text: String,
+
+    /// Byte offset of the first character in the source
+    pub start: usize,
+
+    /// Byte offset after the last character in the source
+    pub end: usize,
+
+    /// Tokens in `text`, as counted while splitting
+    pub tokens: usize,
+}
+
+impl TextChunk {
+    #[must_use]
+    pub fn span(&self) -> Range<usize> {
+        self.start..self.end
+    }
+}
+
+impl From<&[TextChunk]> for EmbedInput {
+    fn from(chunks: &[TextChunk]) -> Self {
+        EmbedInput::Multiple(chunks.iter().map(|c| c.text.clone()).collect())
+    }
+}
+
+/// Splits documents into chunks of at most `max_tokens`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextSplitter {
+    strategy: SplitStrategy,
+    max_tokens: usize,
+    overlap: usize,
+    estimate: CharEstimate,
+}
+
+impl Default for TextSplitter {
+    /// Recursive strategy, 512 tokens, no overlap.
+    fn default() -> Self {
+        Self {
+            strategy: SplitStrategy::default(),
+            max_tokens: 512,
+            overlap: 0,
+            estimate: CharEstimate::default(),
+        }
+    }
+}
+
+impl TextSplitter {
+    #[must_use]
+    pub fn new(strategy: SplitStrategy) -> Self {
+        Self {
+            strategy,
+            ..Self::default()
+        }
+    }
+
+    /// Maximum tokens per chunk.
+    #[must_use]
+    pub fn max_tokens(mut self, max_tokens: usize) -> Self {
+        self.max_tokens = max_tokens.max(1);
+        self
+    }
+
+    /// Tokens at the end of a chunk that are repeated at the start of the next one.
+    /// Overlap happens at the finest separator, so it can be smaller.
+    #[must_use]
+    pub fn overlap(mut self, overlap: usize) -> Self {
+        self.overlap = overlap;
+        self
+    }
+
+    /// Characters per token used by `split`.
+    #[must_use]
+    pub fn chars_per_token(mut self, chars_per_token: f32) -> Self {
+        self.estimate = CharEstimate { chars_per_token };
+        self
+    }
+
+    /// Split `text` with token counts estimated from its length.
+    #[must_use]
+    pub fn split(&self, text: &str) -> Vec<TextChunk> {
+        self.split_with(text, &self.estimate)
+    }
+
+    /// Split `text` with exact token counts from llama.cpp's tokenizer.
+    /// The document is tokenized once.
+    ///
+    /// # Errors
+    ///
+    /// Same as `Llama::tokenize`.
+    pub async fn split_with_llama(
+        &self,
+        llama: &Llama,
+        text: &str,
+    ) -> crate::Result<Vec<TextChunk>> {
+        let response = llama
+            .tokenize(
+                TokenizeRequest::new(text)
+                    .add_special(false)
+                    .with_pieces(true),
+            )
+            .await?;
+
+        let mut starts = Vec::with_capacity(response.tokens.len());
+        let mut offset = 0;
+        for token in &response.tokens {
+            starts.push(offset.min(text.len()));
+            if let Token::Piece { piece, .. } = token {
+                offset += piece.len();
+            }
+        }
+
+        // Without pieces, assume tokens are spread evenly
+        if offset == 0 && !starts.is_empty() {
+            let len = starts.len();
+            for (i, start) in starts.iter_mut().enumerate() {
+                *start = text.len() * i / len;
+            }
+        }
+
+        Ok(self.split_with(text, &TokenOffsets { starts }))
+    }
+
+    /// Split `text` with a custom token counter.
+    pub fn split_with<C: TokenCounter + ?Sized>(&self, text: &str, counter: &C) -> Vec<TextChunk> {
+        let mut chunks = Vec::new();
+        self.split_span(
+            text,
+            counter,
+            0..text.len(),
+            &self.strategy.separators(),
+            &mut chunks,
+        );
+
+        chunks
+    }
+
+    /// Split `span` at the first separator. Parts within budget are merged with their
+    /// neighbors, parts over budget are split at the finer separators on their own, so
+    /// chunks never cross a coarser boundary than necessary.
+    fn split_span<C: TokenCounter + ?Sized>(
+        &self,
+        text: &str,
+        counter: &C,
+        span: Range<usize>,
+        separators: &[Separator],
+        out: &mut Vec<TextChunk>,
+    ) {
+        if span.is_empty() {
+            return;
+        }
+
+        if counter.count(text, span.clone()) <= self.max_tokens {
+            out.extend(chunk(text, counter, span));
+            return;
+        }
+
+        let Some((separator, finer)) = separators.split_first() else {
+            let parts = self.hard_split(text, counter, span);
+            self.merge(text, counter, &parts, out);
+            return;
+        };
+
+        let parts = split_points(text, span.clone(), separator);
+        if parts.len() <= 1 {
+            self.split_span(text, counter, span, finer, out);
+            return;
+        }
+
+        let mut siblings = Vec::new();
+        for part in parts {
+            if counter.count(text, part.clone()) <= self.max_tokens {
+                siblings.push(part);
+                continue;
+            }
+
+            self.merge(text, counter, &siblings, out);
+            siblings.clear();
+            self.split_span(text, counter, part, finer, out);
+        }
+
+        self.merge(text, counter, &siblings, out);
+    }
+
+    /// Split at the longest prefixes within budget, for text without any separator.
+    fn hard_split<C: TokenCounter + ?Sized>(
+        &self,
+        text: &str,
+        counter: &C,
+        span: Range<usize>,
+    ) -> Vec<Range<usize>> {
+        // Characters looked at ahead of `start`, about one chunk's worth
+        #[allow(
+            clippy::cast_precision_loss,
+            clippy::cast_possible_truncation,
+            clippy::cast_sign_loss
+        )]
+        let window = ((self.max_tokens as f32 * self.estimate.chars_per_token).ceil() as usize)
+            .saturating_add(1);
+
+        let mut parts = Vec::new();
+        let mut start = span.start;
+
+        while start < span.end {
+            let mut window = window.max(2);
+
+            let end = loop {
+                let mut boundaries = text[start..span.end]
+                    .char_indices()
+                    .map(|(i, _)| start + i)
+                    .skip(1)
+                    .take(window)
+                    .collect::<Vec<_>>();
+                let complete = boundaries.len() < window;
+                if complete {
+                    boundaries.push(span.end);
+                }
+
+                // At least one character, also if it is over budget on its own
+                let fits = boundaries
+                    .partition_point(|&end| counter.count(text, start..end) <= self.max_tokens);
+
+                // Look further if the whole window fits, e.g. for long tokens
+                if complete || fits < boundaries.len() {
+                    break boundaries[fits.saturating_sub(1)];
+                }
+                window = window.saturating_mul(2);
+            };
+
+            parts.push(start..end);
+            start = end;
+        }
+
+        parts
+    }
+
+    /// Merge consecutive `parts` into chunks within budget, with overlap.
+    fn merge<C: TokenCounter + ?Sized>(
+        &self,
+        text: &str,
+        counter: &C,
+        parts: &[Range<usize>],
+        out: &mut Vec<TextChunk>,
+    ) {
+        let mut first = 0;
+
+        while first < parts.len() {
+            let start = parts[first].start;
+
+            let mut last = first;
+            while last + 1 < parts.len()
+                && counter.count(text, start..parts[last + 1].end) <= self.max_tokens
+            {
+                last += 1;
+            }
+
+            out.extend(chunk(text, counter, start..parts[last].end));
+
+            if last + 1 >= parts.len() {
+                break;
+            }
+
+            // Start the next chunk with the trailing parts that fit into the overlap,
+            // as long as the next new part still fits
+            let next = last + 1;
+            let mut overlap_from = next;
+            while overlap_from > first + 1
+                && counter.count(text, parts[overlap_from - 1].start..parts[last].end)
+                    <= self.overlap
+                && counter.count(text, parts[overlap_from - 1].start..parts[next].end)
+                    <= self.max_tokens
+            {
+                overlap_from -= 1;
+            }
+
+            first = overlap_from;
+        }
+    }
+}
+
+/// A chunk of `span` without surrounding whitespace, or `None` if it is blank.
+fn chunk<C: TokenCounter + ?Sized>(
+    text: &str,
+    counter: &C,
+    span: Range<usize>,
+) -> Option<TextChunk> {
+    let slice = &text[span.clone()];
+    let trimmed = slice.trim();
+    if trimmed.is_empty() {
+        return None;
+    }
+
+    let start = span.start + (slice.len() - slice.trim_start().len());
+    let end = start + trimmed.len();
+
+    Some(TextChunk {
+        text: trimmed.to_string(),
+        start,
+        end,
+        tokens: counter.count(text, start..end),
+    })
+}
+
+/// Split `span` into consecutive parts at `separator`.
+fn split_points(text: &str, span: Range<usize>, separator: &Separator) -> Vec<Range<usize>> {
+    let slice = &text[span.clone()];
+    let mut points = match separator {
+        Separator::Literal(separator) if separator.is_empty() => Vec::new(),
+        Separator::Literal(separator) => {
+            // Split after leading whitespace and punctuation of the separator, so `"\nfn "`
+            // starts the next part with `fn` and `". "` ends the previous one with the period
+            let keep = separator
+                .find(char::is_alphanumeric)
+                .unwrap_or(separator.len());
+
+            slice
+                .match_indices(separator.as_str())
+                .map(|(i, _)| i + keep)
+                .collect()
+        }
+        Separator::Sentence => sentence_ends(slice),
+        Separator::Heading => heading_starts(slice),
+    };
+
+    points.retain(|&p| p > 0 && p < slice.len());
+    points.dedup();
+
+    let mut parts = Vec::with_capacity(points.len() + 1);
+    let mut start = 0;
+    for point in points {
+        parts.push(span.start + start..span.start + point);
+        start = point;
+    }
+    parts.push(span.start + start..span.end);
+
+    parts
+}
+
+/// Offsets after sentence ending punctuation followed by whitespace.
+fn sentence_ends(text: &str) -> Vec<usize> {
+    let mut ends = Vec::new();
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((_, c)) = chars.next() {
+        if !matches!(c, '.' | '!' | '?' | '。' | '！' | '？') {
+            continue;
+        }
+
+        // Closing quotes and brackets belong to the sentence
+        while let Some(&(_, next)) = chars.peek() {
+            if matches!(next, '"' | '\'' | ')' | ']' | '”' | '’') {
+                chars.next();
+            } else {
+                break;
+            }
+        }
+
+        let Some(&(i, next)) = chars.peek() else {
+            continue;
+        };
+
+        // CJK punctuation is not followed by a space
+        if next.is_whitespace() {
+            ends.push(i + next.len_utf8());
+        } else if matches!(c, '。' | '！' | '？') {
+            ends.push(i);
+        }
+    }
+
+    ends
+}
+
+/// Offsets of markdown heading lines outside of code fences.
+fn heading_starts(text: &str) -> Vec<usize> {
+    let mut starts = Vec::new();
+    let mut in_fence = false;
+    let mut offset = 0;
+
+    for line in text.split_inclusive('\n') {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+        } else if !in_fence && is_heading(trimmed) {
+            starts.push(offset);
+        }
+
+        offset += line.len();
+    }
+
+    starts
+}
+
+fn is_heading(line: &str) -> bool {
+    let hashes = line.chars().take_while(|&c| c == '#').count();
+    (1..=6).contains(&hashes) && line[hashes..].starts_with([' ', '\t', '\n'])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One token per character, so budgets are easy to follow.
+    fn splitter(max_tokens: usize) -> TextSplitter {
+        TextSplitter::default()
+            .chars_per_token(1.0)
+            .max_tokens(max_tokens)
+    }
+
+    fn texts(chunks: &[TextChunk]) -> Vec<&str> {
+        chunks.iter().map(|c| c.text.as_str()).collect()
+    }
+
+    /// Every chunk is within budget, unless it is a single character, and its offsets point
+    /// at its text.
+    fn assert_consistent(text: &str, chunks: &[TextChunk], max_tokens: usize) {
+        for chunk in chunks {
+            assert_eq!(&text[chunk.span()], chunk.text);
+            assert!(
+                chunk.tokens <= max_tokens || chunk.text.chars().count() == 1,
+                "{chunk:?} over {max_tokens}"
+            );
+        }
+    }
+
+    #[test]
+    fn char_estimate_counts_characters() {
+        let estimate = CharEstimate::default();
+
+        assert_eq!(estimate.count("abcd", 0..4), 1);
+        assert_eq!(estimate.count("abcde", 0..5), 2);
+        assert_eq!(estimate.count("éééé", 0.."éééé".len()), 1);
+        assert_eq!(estimate.count("", 0..0), 0);
+    }
+
+    #[test]
+    fn words_are_merged_within_budget() {
+        let text = "one two three four five six seven";
+        let chunks = splitter(10).split(text);
+
+        assert_eq!(
+            texts(&chunks),
+            ["one two", "three", "four five", "six seven"]
+        );
+        assert_consistent(text, &chunks, 10);
+        assert_eq!(splitter(100).split(text)[0].text, text);
+    }
+
+    #[test]
+    fn overlap_repeats_trailing_parts() {
+        let text = "one two three four five six seven";
+        let chunks = splitter(10).overlap(5).split(text);
+
+        assert_eq!(
+            texts(&chunks),
+            ["one two", "two three", "four five", "five six", "six seven"]
+        );
+        assert_consistent(text, &chunks, 10);
+    }
+
+    #[test]
+    fn overlap_as_large_as_the_budget_still_advances() {
+        let text = "one two three four five six seven";
+
+        for overlap in [10, 11, 1000] {
+            let chunks = splitter(10).overlap(overlap).split(text);
+
+            assert_consistent(text, &chunks, 10);
+            assert_eq!(chunks.last().map(|c| c.end), Some(text.len()));
+            assert!(chunks.windows(2).all(|w| w[0].start < w[1].start));
+        }
+    }
+
+    #[test]
+    fn zero_budget_means_one_token() {
+        let chunks = TextSplitter::default()
+            .chars_per_token(1.0)
+            .max_tokens(0)
+            .overlap(3)
+            .split("ab c");
+
+        assert_eq!(texts(&chunks), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn long_word_is_split_hard() {
+        let chunks = splitter(3).split("abcdefghij");
+
+        assert_eq!(texts(&chunks), ["abc", "def", "ghi", "j"]);
+    }
+
+    #[test]
+    fn hard_split_keeps_multi_byte_characters_whole() {
+        let text = "éü漢字😀".repeat(20);
+
+        // Fewer, equal and more characters per token than the window assumes
+        for chars_per_token in [0.5, 1.0, 3.0] {
+            for max_tokens in [1, 2, 3, 7] {
+                let chunks = TextSplitter::default()
+                    .chars_per_token(chars_per_token)
+                    .max_tokens(max_tokens)
+                    .split(&text);
+
+                assert_consistent(&text, &chunks, max_tokens);
+                assert_eq!(texts(&chunks).concat(), text);
+            }
+        }
+    }
+
+    #[test]
+    fn hard_split_with_a_huge_budget() {
+        /// A quarter of `usize::MAX` per character.
+        struct Expensive;
+
+        impl TokenCounter for Expensive {
+            fn count(&self, text: &str, span: Range<usize>) -> usize {
+                (usize::MAX / 4).saturating_mul(text[span].chars().count())
+            }
+        }
+
+        let chunks = TextSplitter::default()
+            .max_tokens(usize::MAX / 2)
+            .split_with("abcdefg", &Expensive);
+
+        assert_eq!(texts(&chunks), ["ab", "cd", "ef", "g"]);
+    }
+
+    #[test]
+    fn sentences_keep_their_punctuation() {
+        let text = "First one. Second one! Third? Fourth.";
+        let chunks = TextSplitter::new(SplitStrategy::Sentence)
+            .chars_per_token(1.0)
+            .max_tokens(12)
+            .split(text);
+
+        assert_eq!(
+            texts(&chunks),
+            ["First one.", "Second one!", "Third?", "Fourth."]
+        );
+        assert_consistent(text, &chunks, 12);
+    }
+
+    #[test]
+    fn headings_in_code_fences_are_ignored() {
+        let text = "# A\n\n```\n# not a heading\n```\n# B\ntext";
+
+        assert_eq!(heading_starts(text), [0, text.find("# B").unwrap()]);
+    }
+
+    #[test]
+    fn code_is_split_before_items() {
+        let text = "fn a() {\n    1\n}\n\nfn b() {\n    2\n}";
+        let chunks = TextSplitter::new(SplitStrategy::Code(Language::Rust))
+            .chars_per_token(1.0)
+            .max_tokens(20)
+            .split(text);
+
+        assert_eq!(texts(&chunks), ["fn a() {\n    1\n}", "fn b() {\n    2\n}"]);
+    }
+}
@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+
+/// BM25 parameters of a `VectorStore`'s lexical index.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bm25Config {
+    /// Term frequency saturation, higher values let repeated terms count more
+    pub k1: f32,
+
+    /// Document length normalization, from 0 (none) to 1 (full)
+    pub b: f32,
+}
+
+impl Default for Bm25Config {
+    /// `k1` 1.2 and `b` 0.75, the usual defaults.
+    fn default() -> Self {
+        Self { k1: 1.2, b: 0.75 }
+    }
+}
+
+impl Bm25Config {
+    #[must_use]
+    pub fn k1(mut self, k1: f32) -> Self {
+        self.k1 = k1;
+        self
+    }
+
+    #[must_use]
+    pub fn b(mut self, b: f32) -> Self {
+        self.b = b;
+        self
+    }
+}
+
+/// Inverted index over the documents' text, scored with BM25.
+///
+/// Text is split into lowercase alphanumeric terms, so identifiers like `KJFK` or `A320`
+/// match exactly, regardless of case and punctuation around them.
+#[derive(Debug, Clone)]
+pub(crate) struct Bm25Index {
+    config: Bm25Config,
+
+    /// Term to `(slot, term frequency)`
+    postings: HashMap<String, Vec<(u32, u32)>>,
+
+    /// Terms per slot, 0 for empty slots
+    lengths: Vec<u32>,
+    total_length: u64,
+    documents: usize,
+}
+
+impl Bm25Index {
+    pub(crate) fn new(config: Bm25Config) -> Self {
+        Self {
+            config,
+            postings: HashMap::new(),
+            lengths: Vec::new(),
+            total_length: 0,
+            documents: 0,
+        }
+    }
+
+    pub(crate) fn config(&self) -> Bm25Config {
+        self.config
+    }
+
+    pub(crate) fn insert(&mut self, slot: u32, text: &str) {
+        let frequencies = frequencies(text);
+        let length = frequencies.values().sum::<u32>();
+
+        for (term, frequency) in frequencies {
+            self.postings
+                .entry(term)
+                .or_default()
+                .push((slot, frequency));
+        }
+
+        let slot = slot as usize;
+        if self.lengths.len() <= slot {
+            self.lengths.resize(slot + 1, 0);
+        }
+        self.lengths[slot] = length;
+        self.total_length += u64::from(length);
+        self.documents += 1;
+    }
+
+    /// `text` must be the text `slot` was inserted with.
+    pub(crate) fn remove(&mut self, slot: u32, text: &str) {
+        for term in frequencies(text).into_keys() {
+            if let Some(postings) = self.postings.get_mut(&term) {
+                postings.retain(|(s, _)| *s != slot);
+                if postings.is_empty() {
+                    self.postings.remove(&term);
+                }
+            }
+        }
+
+        if let Some(length) = self.lengths.get_mut(slot as usize) {
+            self.total_length -= u64::from(*length);
+            *length = 0;
+        }
+        self.documents -= 1;
+    }
+
+    /// The `k` best matching slots accepted by `filter`, best first.
+    pub(crate) fn search(
+        &self,
+        query: &str,
+        k: usize,
+        filter: &dyn Fn(u32) -> bool,
+    ) -> Vec<(u32, f32)> {
+        if self.documents == 0 {
+            return Vec::new();
+        }
+
+        // Document counts and lengths are far below f32's exact integer range
+        #[allow(clippy::cast_precision_loss)]
+        let (documents, average_length) = (
+            self.documents as f32,
+            self.total_length as f32 / self.documents as f32,
+        );
+        let Bm25Config { k1, b } = self.config;
+
+        let mut scores = HashMap::<u32, f32>::new();
+        for term in frequencies(query).into_keys() {
+            let Some(postings) = self.postings.get(&term) else {
+                continue;
+            };
+
+            #[allow(clippy::cast_precision_loss)]
+            let frequency = postings.len() as f32;
+            let idf = (1.0 + (documents - frequency + 0.5) / (frequency + 0.5)).ln();
+
+            for &(slot, tf) in postings {
+                #[allow(clippy::cast_precision_loss)]
+                let (tf, length) = (tf as f32, self.lengths[slot as usize] as f32);
+                let norm = k1 * (1.0 - b + b * length / average_length.max(1.0));
+
+                *scores.entry(slot).or_default() += idf * tf * (k1 + 1.0) / (tf + norm);
+            }
+        }
+
+        let mut hits = scores
+            .into_iter()
+            .filter(|(slot, _)| filter(*slot))
+            .collect::<Vec<_>>();
+
+        hits.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        hits.truncate(k);
+        hits
+    }
+}
+
+fn frequencies(text: &str) -> HashMap<String, u32> {
+    let mut frequencies = HashMap::new();
+    for term in text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|t| !t.is_empty())
+    {
+        *frequencies.entry(term.to_lowercase()).or_default() += 1;
+    }
+
+    frequencies
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index(texts: &[&str]) -> Bm25Index {
+        let mut index = Bm25Index::new(Bm25Config::default());
+        for (slot, text) in (0..).zip(texts) {
+            index.insert(slot, text);
+        }
+        index
+    }
+
+    fn slots(hits: &[(u32, f32)]) -> Vec<u32> {
+        hits.iter().map(|(slot, _)| *slot).collect()
+    }
+
+    #[test]
+    fn score_is_bm25() {
+        let index = index(&["apple banana", "apple apple cherry date", "cherry"]);
+        let hits = index.search("banana", 10, &|_| true);
+
+        // 3 documents with 7 terms, "banana" is in one of them, which has 2 terms
+        let idf = (1.0f32 + 2.5 / 1.5).ln();
+        let norm = 1.2 * (0.25 + 0.75 * 2.0 / (7.0 / 3.0));
+        assert_eq!(slots(&hits), [0]);
+        assert!((hits[0].1 - idf * 2.2 / (1.0 + norm)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn terms_ignore_case_and_punctuation() {
+        let index = index(&["Departs from KJFK, gate A3.", "Arrives at EGLL"]);
+
+        let hits = index.search("kjfk a3?", 10, &|_| true);
+        assert_eq!(slots(&hits), [0]);
+        assert!(index.search("KJF", 10, &|_| true).is_empty());
+    }
+
+    #[test]
+    fn rare_terms_and_short_documents_score_higher() {
+        let index = index(&["common rare", "common", "common filler filler filler"]);
+
+        let hits = index.search("common rare", 10, &|_| true);
+        assert_eq!(slots(&hits), [0, 1, 2]);
+
+        // Without length normalization only the term frequency counts, ties go by slot
+        let mut index = Bm25Index::new(Bm25Config::default().b(0.0));
+        index.insert(0, "common filler filler filler");
+        index.insert(1, "common");
+        let hits = index.search("common", 10, &|_| true);
+        assert_eq!(slots(&hits), [0, 1]);
+        assert!((hits[0].1 - hits[1].1).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn repeated_terms_saturate() {
+        let index = index(&[
+            "word",
+            "word word",
+            "word word word word word word word word",
+        ]);
+        let scores = index
+            .search("word", 10, &|_| true)
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+
+        // More occurrences count less, and long documents are penalized
+        assert!(scores[&1] > scores[&0]);
+        assert!(scores[&2] - scores[&1] < scores[&1] - scores[&0]);
+    }
+
+    #[test]
+    fn filter_and_k() {
+        let index = index(&["sky", "blue sky", "sky sky blue", "grass"]);
+
+        let hits = index.search("sky", 2, &|_| true);
+        assert_eq!(hits.len(), 2);
+
+        let hits = index.search("sky", 10, &|slot| slot != 0);
+        assert_eq!(slots(&hits).len(), 2);
+        assert!(!slots(&hits).contains(&0));
+        assert!(index.search("sky", 0, &|_| true).is_empty());
+    }
+
+    #[test]
+    fn removed_documents_are_not_found() {
+        let mut index = index(&["blue sky", "grey sky"]);
+        index.remove(0, "blue sky");
+
+        assert!(index.search("blue", 10, &|_| true).is_empty());
+        assert_eq!(slots(&index.search("sky", 10, &|_| true)), [1]);
+        assert_eq!((index.documents, index.total_length), (1, 2));
+        assert!(!index.postings.contains_key("blue"));
+
+        index.remove(1, "grey sky");
+        assert!(index.search("sky", 10, &|_| true).is_empty());
+    }
+}
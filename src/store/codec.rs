@@ -0,0 +1,131 @@
+//! Little endian encoding for the binary store files.
+
+use crate::OllamaError;
+
+#[derive(Debug, Default)]
+pub(crate) struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    pub(crate) fn new(magic: [u8; 4], version: u8) -> Self {
+        let mut writer = Self::default();
+        writer.bytes.extend_from_slice(&magic);
+        writer.u8(version);
+        writer
+    }
+
+    pub(crate) fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    pub(crate) fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub(crate) fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn len(&mut self, len: usize) {
+        self.u64(len as u64);
+    }
+
+    pub(crate) fn bytes(&mut self, bytes: &[u8]) {
+        self.len(bytes.len());
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    pub(crate) fn str(&mut self, value: &str) {
+        self.bytes(value.as_bytes());
+    }
+
+    /// Without length prefix, the reader has to know the length.
+    pub(crate) fn f32s(&mut self, values: &[f32]) {
+        for value in values {
+            self.bytes.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    /// Checks the magic bytes and returns the version.
+    pub(crate) fn new(bytes: &'a [u8], magic: [u8; 4]) -> crate::Result<(Self, u8)> {
+        let mut reader = Self { bytes };
+        if reader.take(4)? != magic.as_slice() {
+            return Err(invalid("wrong file type"));
+        }
+
+        let version = reader.u8()?;
+        Ok((reader, version))
+    }
+
+    fn take(&mut self, n: usize) -> crate::Result<&'a [u8]> {
+        if self.bytes.len() < n {
+            return Err(invalid("unexpected end of file"));
+        }
+
+        let (head, tail) = self.bytes.split_at(n);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> crate::Result<[u8; N]> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    pub(crate) fn u8(&mut self) -> crate::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn u32(&mut self) -> crate::Result<u32> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    pub(crate) fn u64(&mut self) -> crate::Result<u64> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    pub(crate) fn len(&mut self) -> crate::Result<usize> {
+        usize::try_from(self.u64()?).map_err(|_| invalid("length out of range"))
+    }
+
+    pub(crate) fn bytes(&mut self) -> crate::Result<&'a [u8]> {
+        let len = self.len()?;
+        self.take(len)
+    }
+
+    pub(crate) fn str(&mut self) -> crate::Result<&'a str> {
+        std::str::from_utf8(self.bytes()?).map_err(|e| invalid(&e.to_string()))
+    }
+
+    pub(crate) fn f32s(&mut self, len: usize) -> crate::Result<Vec<f32>> {
+        let bytes = self.take(
+            len.checked_mul(4)
+                .ok_or_else(|| invalid("length out of range"))?,
+        )?;
+        Ok(bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect())
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+}
+
+pub(crate) fn invalid(reason: &str) -> OllamaError {
+    OllamaError::Other(format!("Invalid store file: {reason}"))
+}
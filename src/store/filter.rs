@@ -0,0 +1,254 @@
+use serde_json::Value;
+
+/// Condition on the JSON metadata of a document.
+///
+/// Keys starting with `/` are JSON pointers into nested objects, e.g. `/source/path`,
+/// all other keys are looked up in the top level object.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    /// The value equals
+    Eq(String, Value),
+
+    /// The value is missing or not equal
+    Ne(String, Value),
+
+    /// The value equals one of the values
+    In(String, Vec<Value>),
+
+    /// The value is a number greater than
+    Gt(String, f64),
+
+    /// The value is a number greater than or equal
+    Gte(String, f64),
+
+    /// The value is a number less than
+    Lt(String, f64),
+
+    /// The value is a number less than or equal
+    Lte(String, f64),
+
+    /// The value is a string or an array containing the value
+    Contains(String, Value),
+
+    /// The key is present, also if its value is null
+    Exists(String),
+
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+    Not(Box<Filter>),
+}
+
+impl Filter {
+    pub fn eq<K: Into<String>, V: Into<Value>>(key: K, value: V) -> Self {
+        Self::Eq(key.into(), value.into())
+    }
+
+    pub fn ne<K: Into<String>, V: Into<Value>>(key: K, value: V) -> Self {
+        Self::Ne(key.into(), value.into())
+    }
+
+    pub fn is_in<K: Into<String>, V: Into<Value>, I: IntoIterator<Item = V>>(
+        key: K,
+        values: I,
+    ) -> Self {
+        Self::In(key.into(), values.into_iter().map(Into::into).collect())
+    }
+
+    pub fn gt<K: Into<String>>(key: K, value: f64) -> Self {
+        Self::Gt(key.into(), value)
+    }
+
+    pub fn gte<K: Into<String>>(key: K, value: f64) -> Self {
+        Self::Gte(key.into(), value)
+    }
+
+    pub fn lt<K: Into<String>>(key: K, value: f64) -> Self {
+        Self::Lt(key.into(), value)
+    }
+
+    pub fn lte<K: Into<String>>(key: K, value: f64) -> Self {
+        Self::Lte(key.into(), value)
+    }
+
+    pub fn contains<K: Into<String>, V: Into<Value>>(key: K, value: V) -> Self {
+        Self::Contains(key.into(), value.into())
+    }
+
+    pub fn exists<K: Into<String>>(key: K) -> Self {
+        Self::Exists(key.into())
+    }
+
+    /// Both this and `other` have to match.
+    #[must_use]
+    pub fn and(self, other: Filter) -> Self {
+        match self {
+            Self::And(mut filters) => {
+                filters.push(other);
+                Self::And(filters)
+            }
+            filter => Self::And(vec![filter, other]),
+        }
+    }
+
+    /// This or `other` has to match.
+    #[must_use]
+    pub fn or(self, other: Filter) -> Self {
+        match self {
+            Self::Or(mut filters) => {
+                filters.push(other);
+                Self::Or(filters)
+            }
+            filter => Self::Or(vec![filter, other]),
+        }
+    }
+
+    /// Whether `metadata` satisfies this filter.
+    #[must_use]
+    pub fn matches(&self, metadata: &Value) -> bool {
+        let number = |key: &str| lookup(metadata, key).and_then(Value::as_f64);
+
+        match self {
+            Self::Eq(key, value) => lookup(metadata, key) == Some(value),
+            Self::Ne(key, value) => lookup(metadata, key) != Some(value),
+            Self::In(key, values) => lookup(metadata, key).is_some_and(|v| values.contains(v)),
+            Self::Gt(key, value) => number(key).is_some_and(|n| n > *value),
+            Self::Gte(key, value) => number(key).is_some_and(|n| n >= *value),
+            Self::Lt(key, value) => number(key).is_some_and(|n| n < *value),
+            Self::Lte(key, value) => number(key).is_some_and(|n| n <= *value),
+            Self::Contains(key, value) => match (lookup(metadata, key), value) {
+                (Some(Value::Array(items)), value) => items.contains(value),
+                (Some(Value::String(s)), Value::String(needle)) => s.contains(needle.as_str()),
+                _ => false,
+            },
+            Self::Exists(key) => lookup(metadata, key).is_some(),
+            Self::And(filters) => filters.iter().all(|f| f.matches(metadata)),
+            Self::Or(filters) => filters.iter().any(|f| f.matches(metadata)),
+            Self::Not(filter) => !filter.matches(metadata),
+        }
+    }
+}
+
+impl std::ops::Not for Filter {
+    type Output = Filter;
+
+    fn not(self) -> Self::Output {
+        Filter::Not(Box::new(self))
+    }
+}
+
+fn lookup<'a>(metadata: &'a Value, key: &str) -> Option<&'a Value> {
+    if key.starts_with('/') {
+        metadata.pointer(key)
+    } else {
+        metadata.get(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn metadata() -> Value {
+        json!({
+            "airport": "KJFK",
+            "runways": 4,
+            "elevation": 13.2,
+            "tags": ["international", "hub"],
+            "closed": null,
+            "source": { "path": "airports/kjfk.md", "page": 2 },
+        })
+    }
+
+    #[test]
+    fn comparisons() {
+        let metadata = metadata();
+        let cases = [
+            (Filter::eq("airport", "KJFK"), true),
+            (Filter::eq("airport", "kjfk"), false),
+            (Filter::eq("runways", 4), true),
+            (Filter::eq("runways", "4"), false),
+            (Filter::ne("airport", "EGLL"), true),
+            (Filter::ne("missing", "EGLL"), true),
+            (Filter::ne("airport", "KJFK"), false),
+            (Filter::is_in("airport", ["EGLL", "KJFK"]), true),
+            (Filter::is_in("airport", ["EGLL"]), false),
+            (Filter::is_in("missing", [Value::Null]), false),
+            (Filter::gt("runways", 3.0), true),
+            (Filter::gt("runways", 4.0), false),
+            (Filter::gte("runways", 4.0), true),
+            (Filter::lt("elevation", 13.3), true),
+            (Filter::lte("elevation", 13.2), true),
+            (Filter::lte("elevation", 13.0), false),
+            (Filter::gt("airport", 0.0), false),
+            (Filter::lt("missing", 0.0), false),
+        ];
+
+        for (filter, expected) in cases {
+            assert_eq!(filter.matches(&metadata), expected, "{filter:?}");
+        }
+    }
+
+    #[test]
+    fn contains_and_exists() {
+        let metadata = metadata();
+        let cases = [
+            (Filter::contains("tags", "hub"), true),
+            (Filter::contains("tags", "regional"), false),
+            (Filter::contains("airport", "JF"), true),
+            (Filter::contains("airport", "jf"), false),
+            (Filter::contains("runways", 4), false),
+            (Filter::contains("airport", json!(["KJFK"])), false),
+            (Filter::exists("closed"), true),
+            (Filter::exists("missing"), false),
+        ];
+
+        for (filter, expected) in cases {
+            assert_eq!(filter.matches(&metadata), expected, "{filter:?}");
+        }
+    }
+
+    #[test]
+    fn json_pointers() {
+        let metadata = metadata();
+
+        assert!(Filter::eq("/source/path", "airports/kjfk.md").matches(&metadata));
+        assert!(Filter::gte("/source/page", 2.0).matches(&metadata));
+        assert!(Filter::eq("/tags/1", "hub").matches(&metadata));
+        assert!(!Filter::exists("/source/missing").matches(&metadata));
+
+        // Without the slash the key is looked up literally
+        assert!(!Filter::exists("source/path").matches(&metadata));
+    }
+
+    #[test]
+    fn combinations() {
+        let metadata = metadata();
+        let jfk = Filter::eq("airport", "KJFK");
+        let heathrow = Filter::eq("airport", "EGLL");
+
+        assert!(
+            jfk.clone()
+                .and(Filter::gt("runways", 2.0))
+                .matches(&metadata)
+        );
+        assert!(!jfk.clone().and(heathrow.clone()).matches(&metadata));
+        assert!(heathrow.clone().or(jfk.clone()).matches(&metadata));
+        assert!(
+            !heathrow
+                .clone()
+                .or(Filter::exists("missing"))
+                .matches(&metadata)
+        );
+        assert!((!heathrow.clone()).matches(&metadata));
+        assert!(!(!jfk.clone()).matches(&metadata));
+
+        // Chained calls extend the same list
+        let chained = jfk.clone().and(heathrow.clone()).and(jfk.clone());
+        assert_eq!(chained, Filter::And(vec![jfk.clone(), heathrow, jfk]));
+
+        assert!(Filter::And(vec![]).matches(&metadata));
+        assert!(!Filter::Or(vec![]).matches(&metadata));
+    }
+}
@@ -0,0 +1,714 @@
+//! Hierarchical Navigable Small World graph for approximate nearest neighbour search.
+//!
+//! See Malkov & Yashunin, <https://arxiv.org/abs/1603.09320>.
+//! The graph only stores neighbor lists, vectors are looked up by slot through `Vectors`.
+//! All vectors are expected to be normalized, similarity is the dot product.
+
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BinaryHeap, HashSet},
+};
+
+use crate::{
+    generation::embed::vector,
+    store::codec::{Reader, Writer, invalid},
+};
+
+/// Parameters of an HNSW index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HnswConfig {
+    /// Neighbors per node and layer, twice as many on the bottom layer
+    pub m: usize,
+
+    /// Candidates considered when inserting. Higher builds a better graph, slower
+    pub ef_construction: usize,
+
+    /// Candidates considered when searching. Higher gives better recall, slower
+    pub ef_search: usize,
+
+    /// Seed for the random layer assignment
+    pub seed: u64,
+}
+
+impl Default for HnswConfig {
+    /// M 16, ef construction 200, ef search 64.
+    fn default() -> Self {
+        Self {
+            m: 16,
+            ef_construction: 200,
+            ef_search: 64,
+            seed: 0x5eed,
+        }
+    }
+}
+
+impl HnswConfig {
+    /// Neighbors per node and layer, twice as many on the bottom layer.
+    #[must_use]
+    pub fn m(mut self, m: usize) -> Self {
+        self.m = m.max(2);
+        self
+    }
+
+    /// Candidates considered when inserting.
+    #[must_use]
+    pub fn ef_construction(mut self, ef: usize) -> Self {
+        self.ef_construction = ef.max(1);
+        self
+    }
+
+    /// Candidates considered when searching, at least `k` are used.
+    #[must_use]
+    pub fn ef_search(mut self, ef: usize) -> Self {
+        self.ef_search = ef.max(1);
+        self
+    }
+
+    #[must_use]
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+}
+
+/// Vector lookup by slot.
+pub(crate) trait Vectors {
+    fn vector(&self, slot: u32) -> &[f32];
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Scored {
+    score: f32,
+    slot: u32,
+}
+
+impl Eq for Scored {}
+
+impl PartialOrd for Scored {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scored {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.score
+            .total_cmp(&other.score)
+            .then(other.slot.cmp(&self.slot))
+    }
+}
+
+/// Bitset of visited slots, much cheaper than a `HashSet` for dense slots.
+struct Visited(Vec<u64>);
+
+impl Visited {
+    fn new(len: usize) -> Self {
+        Self(vec![0; len.div_ceil(64)])
+    }
+
+    /// Returns whether `slot` was not visited before.
+    fn insert(&mut self, slot: u32) -> bool {
+        let (word, bit) = (slot as usize / 64, 1 << (slot % 64));
+        let Some(word) = self.0.get_mut(word) else {
+            return false;
+        };
+
+        let new = *word & bit == 0;
+        *word |= bit;
+        new
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    /// Neighbor slots per layer, `neighbors.len()` is the level of the node + 1
+    neighbors: Vec<Vec<u32>>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct HnswIndex {
+    config: HnswConfig,
+    nodes: Vec<Option<Node>>,
+    entry: Option<u32>,
+    rng: fastrand::Rng,
+}
+
+impl HnswIndex {
+    pub(crate) fn new(config: HnswConfig) -> Self {
+        Self {
+            config,
+            nodes: Vec::new(),
+            entry: None,
+            rng: fastrand::Rng::with_seed(config.seed),
+        }
+    }
+
+    pub(crate) fn config(&self) -> HnswConfig {
+        self.config
+    }
+
+    pub(crate) fn set_ef_search(&mut self, ef: usize) {
+        self.config.ef_search = ef.max(1);
+    }
+
+    fn max_neighbors(&self, layer: usize) -> usize {
+        if layer == 0 {
+            self.config.m * 2
+        } else {
+            self.config.m
+        }
+    }
+
+    fn random_level(&mut self) -> usize {
+        let m = u32::try_from(self.config.m).unwrap_or(u32::MAX);
+        let ml = 1.0 / f64::from(m).ln();
+        let uniform = 1.0 - self.rng.f64();
+
+        // Truncation is intended, the level is the floor of an exponential sample
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let level = (-uniform.ln() * ml).floor() as usize;
+        level.min(32)
+    }
+
+    fn level(&self, slot: u32) -> Option<usize> {
+        self.nodes
+            .get(slot as usize)?
+            .as_ref()
+            .map(|node| node.neighbors.len() - 1)
+    }
+
+    fn neighbors(&self, slot: u32, layer: usize) -> &[u32] {
+        self.nodes
+            .get(slot as usize)
+            .and_then(Option::as_ref)
+            .and_then(|node| node.neighbors.get(layer))
+            .map_or(&[], Vec::as_slice)
+    }
+
+    fn is_live(&self, slot: u32) -> bool {
+        self.nodes.get(slot as usize).is_some_and(Option::is_some)
+    }
+
+    /// Best first search on one layer. Only slots accepted by `filter` are returned,
+    /// but all slots are traversed. Returns up to `ef` results, best first.
+    fn search_layer<V: Vectors + ?Sized>(
+        &self,
+        vectors: &V,
+        query: &[f32],
+        entries: &[Scored],
+        ef: usize,
+        layer: usize,
+        filter: &dyn Fn(u32) -> bool,
+    ) -> Vec<Scored> {
+        let mut visited = Visited::new(self.nodes.len());
+        for entry in entries {
+            visited.insert(entry.slot);
+        }
+        let mut candidates = entries.iter().copied().collect::<BinaryHeap<_>>();
+        let mut results = entries
+            .iter()
+            .filter(|e| filter(e.slot))
+            .map(|e| Reverse(*e))
+            .collect::<BinaryHeap<_>>();
+
+        while let Some(candidate) = candidates.pop() {
+            if results.len() >= ef
+                && results
+                    .peek()
+                    .is_some_and(|Reverse(worst)| candidate.score < worst.score)
+            {
+                break;
+            }
+
+            for &neighbor in self.neighbors(candidate.slot, layer) {
+                if !self.is_live(neighbor) || !visited.insert(neighbor) {
+                    continue;
+                }
+
+                let scored = Scored {
+                    score: vector::dot(query, vectors.vector(neighbor)),
+                    slot: neighbor,
+                };
+
+                let worse_than_all = results.len() >= ef
+                    && results
+                        .peek()
+                        .is_some_and(|Reverse(worst)| scored.score <= worst.score);
+                if worse_than_all {
+                    continue;
+                }
+
+                candidates.push(scored);
+                if filter(neighbor) {
+                    results.push(Reverse(scored));
+                    if results.len() > ef {
+                        results.pop();
+                    }
+                }
+            }
+        }
+
+        let mut results = results.into_iter().map(|Reverse(s)| s).collect::<Vec<_>>();
+        results.sort_by(|a, b| b.cmp(a));
+        results
+    }
+
+    /// Greedy descent from the entry point to `layer`.
+    fn descend<V: Vectors + ?Sized>(
+        &self,
+        vectors: &V,
+        query: &[f32],
+        layer: usize,
+    ) -> Option<Vec<Scored>> {
+        let entry = self.entry?;
+        let top = self.level(entry)?;
+
+        let mut entries = vec![Scored {
+            score: vector::dot(query, vectors.vector(entry)),
+            slot: entry,
+        }];
+
+        for current in (layer + 1..=top).rev() {
+            entries = self.search_layer(vectors, query, &entries, 1, current, &|_| true);
+        }
+
+        Some(entries)
+    }
+
+    /// Heuristic neighbor selection: a candidate is skipped if it is closer to an already
+    /// selected neighbor than to the base, which keeps links in different directions.
+    /// Skipped candidates fill the remaining places.
+    fn select_neighbors<V: Vectors + ?Sized>(
+        vectors: &V,
+        candidates: &[Scored],
+        max: usize,
+    ) -> Vec<u32> {
+        let mut selected: Vec<Scored> = Vec::with_capacity(max);
+        let mut skipped = Vec::new();
+
+        for candidate in candidates {
+            if selected.len() >= max {
+                break;
+            }
+
+            let diverse = selected.iter().all(|s| {
+                vector::dot(vectors.vector(candidate.slot), vectors.vector(s.slot))
+                    < candidate.score
+            });
+
+            if diverse {
+                selected.push(*candidate);
+            } else {
+                skipped.push(*candidate);
+            }
+        }
+
+        selected
+            .into_iter()
+            .chain(skipped)
+            .take(max)
+            .map(|s| s.slot)
+            .collect()
+    }
+
+    /// Neighbor list of `base` on `layer` chosen from `slots`, best first.
+    fn prune<V: Vectors + ?Sized>(
+        &self,
+        vectors: &V,
+        base: u32,
+        slots: impl IntoIterator<Item = u32>,
+        layer: usize,
+    ) -> Vec<u32> {
+        let base_vector = vectors.vector(base);
+        let mut candidates = slots
+            .into_iter()
+            .filter(|&slot| slot != base && self.is_live(slot))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .map(|slot| Scored {
+                score: vector::dot(base_vector, vectors.vector(slot)),
+                slot,
+            })
+            .collect::<Vec<_>>();
+        candidates.sort_by(|a, b| b.cmp(a));
+
+        Self::select_neighbors(vectors, &candidates, self.max_neighbors(layer))
+    }
+
+    /// Add the vector at `slot`. The slot must not be in the index.
+    pub(crate) fn insert<V: Vectors + ?Sized>(&mut self, vectors: &V, slot: u32) {
+        let level = self.random_level();
+        let index = slot as usize;
+        if self.nodes.len() <= index {
+            self.nodes.resize(index + 1, None);
+        }
+
+        let query = vectors.vector(slot);
+        let top = self.entry.and_then(|entry| self.level(entry));
+        let Some(mut entries) = self.descend(vectors, query, level) else {
+            self.nodes[index] = Some(Node {
+                neighbors: vec![Vec::new(); level + 1],
+            });
+            self.entry = Some(slot);
+            return;
+        };
+
+        let mut neighbors = vec![Vec::new(); level + 1];
+        for layer in (0..=level.min(top.unwrap_or_default())).rev() {
+            entries = self.search_layer(
+                vectors,
+                query,
+                &entries,
+                self.config.ef_construction,
+                layer,
+                &|_| true,
+            );
+            neighbors[layer] = Self::select_neighbors(vectors, &entries, self.max_neighbors(layer));
+        }
+
+        self.nodes[index] = Some(Node {
+            neighbors: neighbors.clone(),
+        });
+
+        // Link back, pruning neighbors that got too many links
+        for (layer, layer_neighbors) in neighbors.iter().enumerate() {
+            for &neighbor in layer_neighbors {
+                let mut links = self.neighbors(neighbor, layer).to_vec();
+                links.push(slot);
+                if links.len() > self.max_neighbors(layer) {
+                    links = self.prune(vectors, neighbor, links, layer);
+                }
+
+                if let Some(Some(node)) = self.nodes.get_mut(neighbor as usize)
+                    && let Some(list) = node.neighbors.get_mut(layer)
+                {
+                    *list = links;
+                }
+            }
+        }
+
+        if top.is_none_or(|top| level > top) {
+            self.entry = Some(slot);
+        }
+    }
+
+    /// Remove `slot` and reconnect its neighbors among each other.
+    /// The vector at `slot` is not used, it may already be gone.
+    ///
+    /// Links are directed, so every node is checked for links to `slot`, which is `O(n)`.
+    pub(crate) fn remove<V: Vectors + ?Sized>(&mut self, vectors: &V, slot: u32) {
+        let Some(node) = self.nodes.get_mut(slot as usize).and_then(Option::take) else {
+            return;
+        };
+
+        for (layer, removed_links) in node.neighbors.iter().enumerate() {
+            // Its neighbors, and the nodes linking to it, which are not always the same
+            let mut affected = self
+                .nodes
+                .iter()
+                .enumerate()
+                .filter(|(_, node)| {
+                    node.as_ref()
+                        .and_then(|node| node.neighbors.get(layer))
+                        .is_some_and(|links| links.contains(&slot))
+                })
+                .filter_map(|(i, _)| u32::try_from(i).ok())
+                .chain(removed_links.iter().copied())
+                .collect::<Vec<_>>();
+            affected.sort_unstable();
+            affected.dedup();
+
+            for neighbor in affected {
+                if !self.is_live(neighbor) {
+                    continue;
+                }
+
+                let links = self
+                    .neighbors(neighbor, layer)
+                    .iter()
+                    .copied()
+                    .filter(|&s| s != slot)
+                    .chain(removed_links.iter().copied())
+                    .collect::<Vec<_>>();
+                let links = self.prune(vectors, neighbor, links, layer);
+
+                if let Some(Some(node)) = self.nodes.get_mut(neighbor as usize)
+                    && let Some(list) = node.neighbors.get_mut(layer)
+                {
+                    *list = links;
+                }
+            }
+        }
+
+        if self.entry == Some(slot) {
+            self.entry = self
+                .nodes
+                .iter()
+                .enumerate()
+                .filter_map(|(i, node)| Some((u32::try_from(i).ok()?, node.as_ref()?)))
+                .max_by_key(|(_, node)| node.neighbors.len())
+                .map(|(i, _)| i);
+        }
+    }
+
+    /// Up to `k` slots accepted by `filter` that are most similar to `query`, best first.
+    pub(crate) fn search<V: Vectors + ?Sized>(
+        &self,
+        vectors: &V,
+        query: &[f32],
+        k: usize,
+        filter: &dyn Fn(u32) -> bool,
+    ) -> Vec<(u32, f32)> {
+        if k == 0 {
+            return Vec::new();
+        }
+
+        let Some(entries) = self.descend(vectors, query, 0) else {
+            return Vec::new();
+        };
+
+        let ef = self.config.ef_search.max(k);
+        let mut results = self.search_layer(vectors, query, &entries, ef, 0, filter);
+        results.truncate(k);
+
+        results.into_iter().map(|s| (s.slot, s.score)).collect()
+    }
+
+    pub(crate) fn write(&self, writer: &mut Writer) {
+        writer.len(self.config.m);
+        writer.len(self.config.ef_construction);
+        writer.len(self.config.ef_search);
+        writer.u64(self.config.seed);
+        writer.u64(self.entry.map_or(u64::MAX, u64::from));
+        writer.len(self.nodes.len());
+
+        for node in &self.nodes {
+            let Some(node) = node else {
+                writer.u8(0);
+                continue;
+            };
+
+            writer.u8(1);
+            writer.len(node.neighbors.len());
+            for layer in &node.neighbors {
+                writer.len(layer.len());
+                for &neighbor in layer {
+                    writer.u32(neighbor);
+                }
+            }
+        }
+    }
+
+    /// Check that the index holds exactly the live ones of `slots` slots.
+    pub(crate) fn check(&self, slots: usize, is_live: impl Fn(u32) -> bool) -> crate::Result<()> {
+        if self.nodes.len() > slots {
+            return Err(invalid("index has more nodes than the store has slots"));
+        }
+
+        for slot in 0..slots {
+            let slot = u32::try_from(slot).map_err(|_| invalid("too many slots"))?;
+            if self.is_live(slot) != is_live(slot) {
+                return Err(invalid(&format!("index does not match slot {slot}")));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read an index written by `write`. Links and the entry point are checked to be
+    /// nodes of the index, see `check` for the vectors.
+    pub(crate) fn read(reader: &mut Reader<'_>) -> crate::Result<Self> {
+        let config = HnswConfig {
+            m: reader.len()?,
+            ef_construction: reader.len()?,
+            ef_search: reader.len()?,
+            seed: reader.u64()?,
+        };
+        let entry = match reader.u64()? {
+            u64::MAX => None,
+            entry => Some(u32::try_from(entry).map_err(|_| invalid("bad entry point"))?),
+        };
+
+        let count = reader.len()?;
+        let mut nodes = Vec::with_capacity(count.min(1 << 20));
+        for _ in 0..count {
+            if reader.u8()? == 0 {
+                nodes.push(None);
+                continue;
+            }
+
+            let layers = reader.len()?;
+            let mut neighbors = Vec::with_capacity(layers.min(64));
+            for _ in 0..layers {
+                let len = reader.len()?;
+                let mut layer = Vec::with_capacity(len.min(1024));
+                for _ in 0..len {
+                    layer.push(reader.u32()?);
+                }
+                neighbors.push(layer);
+            }
+
+            if neighbors.is_empty() {
+                return Err(invalid("node without layers"));
+            }
+            nodes.push(Some(Node { neighbors }));
+        }
+
+        if config.m < 2 || config.ef_construction == 0 || config.ef_search == 0 {
+            return Err(invalid("bad index config"));
+        }
+
+        let is_live = |slot: u32| nodes.get(slot as usize).is_some_and(Option::is_some);
+        for node in nodes.iter().flatten() {
+            let links = node.neighbors.iter().flatten();
+            if links.copied().any(|neighbor| !is_live(neighbor)) {
+                return Err(invalid("link to a node that is not in the index"));
+            }
+        }
+
+        match entry {
+            Some(entry) if !is_live(entry) => {
+                return Err(invalid("entry point is not in the index"));
+            }
+            None if nodes.iter().any(Option::is_some) => {
+                return Err(invalid("index without entry point"));
+            }
+            _ => {}
+        }
+
+        Ok(Self {
+            config,
+            nodes,
+            entry,
+            rng: fastrand::Rng::with_seed(config.seed ^ count as u64),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Points(Vec<Vec<f32>>);
+
+    impl Vectors for Points {
+        fn vector(&self, slot: u32) -> &[f32] {
+            &self.0[slot as usize]
+        }
+    }
+
+    fn points(n: usize) -> Points {
+        let mut rng = fastrand::Rng::with_seed(1);
+        Points(
+            (0..n)
+                .map(|_| vector::normalized(&(0..8).map(|_| rng.f32() - 0.5).collect::<Vec<_>>()))
+                .collect(),
+        )
+    }
+
+    fn build(points: &Points) -> HnswIndex {
+        let mut index = HnswIndex::new(HnswConfig::default().m(4));
+        for slot in 0..points.0.len() {
+            index.insert(points, u32::try_from(slot).unwrap());
+        }
+        index
+    }
+
+    fn links(index: &HnswIndex) -> impl Iterator<Item = u32> + '_ {
+        index
+            .nodes
+            .iter()
+            .flatten()
+            .flat_map(|node| node.neighbors.iter().flatten().copied())
+    }
+
+    fn round_trip(index: &HnswIndex) -> crate::Result<HnswIndex> {
+        let mut writer = Writer::new(*b"TEST", 1);
+        index.write(&mut writer);
+        let bytes = writer.into_bytes();
+
+        let (mut reader, _) = Reader::new(&bytes, *b"TEST")?;
+        HnswIndex::read(&mut reader)
+    }
+
+    fn top(index: &HnswIndex, points: &Points, slot: u32) -> Option<u32> {
+        let results = index.search(points, points.vector(slot), 1, &|_| true);
+        results.first().map(|(slot, _)| *slot)
+    }
+
+    #[test]
+    fn finds_inserted_vectors() {
+        let points = points(200);
+        let index = build(&points);
+
+        for slot in 0..200 {
+            assert_eq!(top(&index, &points, slot), Some(slot));
+        }
+
+        let even = index.search(&points, points.vector(3), 5, &|slot| slot % 2 == 0);
+        assert_eq!(even.len(), 5);
+        assert!(even.iter().all(|(slot, _)| slot % 2 == 0));
+    }
+
+    #[test]
+    fn remove_drops_all_links() {
+        let points = points(200);
+        let mut index = build(&points);
+
+        for slot in (0..200).step_by(3) {
+            index.remove(&points, slot);
+        }
+
+        assert!(links(&index).all(|slot| index.is_live(slot)));
+        assert!(index.entry.is_some_and(|entry| index.is_live(entry)));
+        for slot in (0..200).filter(|slot| slot % 3 != 0) {
+            assert_eq!(top(&index, &points, slot), Some(slot));
+        }
+
+        for slot in 0..200 {
+            index.remove(&points, slot);
+        }
+        assert_eq!(index.entry, None);
+        assert!(
+            index
+                .search(&points, points.vector(0), 3, &|_| true)
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn survives_serialization() {
+        let points = points(100);
+        let mut index = build(&points);
+        index.remove(&points, 7);
+
+        let read = round_trip(&index).unwrap();
+
+        assert_eq!(read.config, index.config);
+        assert_eq!(read.entry, index.entry);
+        assert!(read.check(100, |slot| slot != 7).is_ok());
+        for slot in (0..100).filter(|&slot| slot != 7) {
+            assert_eq!(top(&read, &points, slot), Some(slot));
+        }
+    }
+
+    #[test]
+    fn rejects_inconsistent_graphs() {
+        let points = points(20);
+        let index = build(&points);
+
+        let mut out_of_range = index.clone();
+        out_of_range.nodes[0].as_mut().unwrap().neighbors[0].push(99);
+        assert!(round_trip(&out_of_range).is_err());
+
+        let mut removed_entry = index.clone();
+        let entry = removed_entry.entry.unwrap();
+        removed_entry.nodes[entry as usize] = None;
+        assert!(round_trip(&removed_entry).is_err());
+
+        assert!(index.check(20, |_| true).is_ok());
+        assert!(index.check(19, |_| true).is_err());
+        assert!(index.check(20, |slot| slot != 4).is_err());
+        assert!(index.check(21, |slot| slot < 20).is_ok());
+    }
+}
@@ -0,0 +1,267 @@
+use std::collections::HashMap;
+
+use crate::store::{Document, Filter, SearchHit, VectorStore};
+
+/// How `VectorStore::hybrid_search` combines the semantic and the lexical ranking.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fusion {
+    /// Reciprocal rank fusion, `sum(1 / (k + rank))` over both rankings.
+    /// Only ranks count, so the different score scales do not matter.
+    Rrf { k: f32 },
+
+    /// `semantic * cosine + (1 - semantic) * bm25`, with both scores min-max normalized
+    /// over their candidates. A document missing from one ranking scores 0 there.
+    Weighted { semantic: f32 },
+}
+
+impl Default for Fusion {
+    /// Reciprocal rank fusion with `k` 60.
+    fn default() -> Self {
+        Self::Rrf { k: 60.0 }
+    }
+}
+
+/// Options for `VectorStore::hybrid_search`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HybridOptions {
+    pub fusion: Fusion,
+
+    /// Candidates taken from each ranking before fusion
+    pub candidates: usize,
+}
+
+impl Default for HybridOptions {
+    /// Reciprocal rank fusion over the best 50 of each ranking.
+    fn default() -> Self {
+        Self {
+            fusion: Fusion::default(),
+            candidates: 50,
+        }
+    }
+}
+
+impl HybridOptions {
+    #[must_use]
+    pub fn fusion(mut self, fusion: Fusion) -> Self {
+        self.fusion = fusion;
+        self
+    }
+
+    /// Candidates taken from each ranking before fusion, at least the requested `k`.
+    #[must_use]
+    pub fn candidates(mut self, candidates: usize) -> Self {
+        self.candidates = candidates;
+        self
+    }
+}
+
+impl VectorStore {
+    /// The `k` documents matching `filter` that are best for `query` by both meaning and
+    /// exact terms, best first. Scores are the fused scores, see `Fusion`.
+    ///
+    /// # Errors
+    ///
+    /// If the store has no BM25 index, see `bm25`.
+    /// Same as `Ollama::generate_embeddings`.
+    pub async fn hybrid_search(
+        &self,
+        query: &str,
+        k: usize,
+        filter: Option<&Filter>,
+        options: &HybridOptions,
+    ) -> crate::Result<Vec<SearchHit<'_>>> {
+        let embedding = self.embed_query(query).await?;
+        self.hybrid_search_by_embedding(query, &embedding, k, filter, options)
+    }
+
+    /// Like `hybrid_search`, with the embedding of `query` computed elsewhere.
+    ///
+    /// # Errors
+    ///
+    /// If the store has no BM25 index, see `bm25`.
+    pub fn hybrid_search_by_embedding(
+        &self,
+        query: &str,
+        embedding: &[f32],
+        k: usize,
+        filter: Option<&Filter>,
+        options: &HybridOptions,
+    ) -> crate::Result<Vec<SearchHit<'_>>> {
+        let candidates = options.candidates.max(k);
+        let lexical = self.search_text(query, candidates, filter)?;
+        let semantic = self.search_by_embedding(embedding, candidates, filter);
+
+        let mut fused = HashMap::<u64, (&Document, f32)>::new();
+        match options.fusion {
+            Fusion::Rrf { k } => {
+                for hits in [&semantic, &lexical] {
+                    for (rank, hit) in hits.iter().enumerate() {
+                        // Ranks are far below f32's exact integer range
+                        #[allow(clippy::cast_precision_loss)]
+                        let score = 1.0 / (k + rank as f32 + 1.0);
+                        fused
+                            .entry(hit.document.id)
+                            .or_insert((hit.document, 0.0))
+                            .1 += score;
+                    }
+                }
+            }
+            Fusion::Weighted { semantic: weight } => {
+                for (hits, weight) in [(&semantic, weight), (&lexical, 1.0 - weight)] {
+                    let (min, max) = hits.iter().fold((f32::MAX, f32::MIN), |(min, max), hit| {
+                        (min.min(hit.score), max.max(hit.score))
+                    });
+
+                    for hit in hits {
+                        let normalized = if max > min {
+                            (hit.score - min) / (max - min)
+                        } else {
+                            1.0
+                        };
+                        fused
+                            .entry(hit.document.id)
+                            .or_insert((hit.document, 0.0))
+                            .1 += weight * normalized;
+                    }
+                }
+            }
+        }
+
+        let mut hits = fused
+            .into_values()
+            .map(|(document, score)| SearchHit { document, score })
+            .collect::<Vec<_>>();
+
+        hits.sort_by(|a, b| {
+            b.score
+                .total_cmp(&a.score)
+                .then(a.document.id.cmp(&b.document.id))
+        });
+        hits.truncate(k);
+
+        Ok(hits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        ollama::Ollama,
+        store::{Bm25Config, HnswConfig},
+    };
+
+    /// Semantic ranking for `[1, 0]` is a, b, c, lexical ranking for "sky" is c, b.
+    fn store() -> VectorStore {
+        let mut store = VectorStore::new(Ollama::default(), "model").bm25(Bm25Config::default());
+        for (name, text, embedding) in [
+            ("a", "red", [1.0, 0.0]),
+            ("b", "blue sky", [0.8, 0.6]),
+            ("c", "sky sky blue", [0.0, 1.0]),
+        ] {
+            store
+                .insert_embedded(text, json!({ "name": name }), &embedding)
+                .unwrap();
+        }
+        store
+    }
+
+    fn search<'a>(
+        store: &'a VectorStore,
+        filter: Option<&Filter>,
+        fusion: Fusion,
+    ) -> Vec<(&'a str, f32)> {
+        let options = HybridOptions::default().fusion(fusion);
+        store
+            .hybrid_search_by_embedding("sky", &[1.0, 0.0], 10, filter, &options)
+            .unwrap()
+            .into_iter()
+            .map(|hit| (hit.document.metadata["name"].as_str().unwrap(), hit.score))
+            .collect()
+    }
+
+    fn assert_ranking(actual: &[(&str, f32)], expected: &[(&str, f32)]) {
+        assert_eq!(actual.len(), expected.len(), "{actual:?}");
+        for ((name, score), (expected_name, expected_score)) in actual.iter().zip(expected) {
+            assert_eq!(name, expected_name, "{actual:?}");
+            assert!((score - expected_score).abs() < 1e-5, "{actual:?}");
+        }
+    }
+
+    #[test]
+    fn reciprocal_rank_fusion() {
+        let store = store();
+        let hits = search(&store, None, Fusion::Rrf { k: 1.0 });
+
+        assert_ranking(
+            &hits,
+            &[
+                ("c", 1.0 / 4.0 + 1.0 / 2.0),
+                ("b", 1.0 / 3.0 + 1.0 / 3.0),
+                ("a", 1.0 / 2.0),
+            ],
+        );
+    }
+
+    #[test]
+    fn weighted_fusion() {
+        let store = store();
+
+        // Semantic 1, 0.8, 0 and lexical 0, 0, 1 after normalization, ties go by id
+        let hits = search(&store, None, Fusion::Weighted { semantic: 0.5 });
+        assert_ranking(&hits, &[("a", 0.5), ("c", 0.5), ("b", 0.4)]);
+
+        let hits = search(&store, None, Fusion::Weighted { semantic: 1.0 });
+        assert_ranking(&hits, &[("a", 1.0), ("b", 0.8), ("c", 0.0)]);
+
+        let hits = search(&store, None, Fusion::Weighted { semantic: 0.0 });
+        assert_ranking(&hits, &[("c", 1.0), ("a", 0.0), ("b", 0.0)]);
+    }
+
+    #[test]
+    fn filter_applies_to_both_rankings() {
+        let store = store();
+        let filter = Filter::ne("name", "c");
+
+        let hits = search(&store, Some(&filter), Fusion::Rrf { k: 1.0 });
+        assert_ranking(&hits, &[("b", 1.0 / 3.0 + 1.0 / 2.0), ("a", 1.0 / 2.0)]);
+
+        // Normalized over the remaining candidates, a single one scores 1
+        let hits = search(&store, Some(&filter), Fusion::Weighted { semantic: 0.5 });
+        assert_ranking(&hits, &[("a", 0.5), ("b", 0.5)]);
+    }
+
+    #[test]
+    fn k_and_candidates() {
+        let store = store().hnsw(HnswConfig::default());
+        let options = HybridOptions::default()
+            .fusion(Fusion::Rrf { k: 1.0 })
+            .candidates(1);
+
+        // Only the best of each ranking is fused
+        let hits = store
+            .hybrid_search_by_embedding("sky", &[1.0, 0.0], 1, None, &options)
+            .unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].document.text, "red");
+
+        let hits = store
+            .hybrid_search_by_embedding("sky", &[1.0, 0.0], 3, None, &options)
+            .unwrap();
+        assert_eq!(hits.len(), 3);
+    }
+
+    #[test]
+    fn lexical_index_is_required() {
+        let store = VectorStore::new(Ollama::default(), "model");
+        let options = HybridOptions::default();
+
+        assert!(
+            store
+                .hybrid_search_by_embedding("sky", &[1.0, 0.0], 1, None, &options)
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,710 @@
+use std::{collections::HashMap, path::Path};
+
+use serde_json::Value;
+use tokio_stream::StreamExt;
+
+use crate::{
+    OllamaError,
+    generation::embed::{
+        concurrent::ConcurrentEmbedOptions,
+        request::{EmbedInput, EmbedRequest},
+        vector::{self, Similarity},
+    },
+    ollama::Ollama,
+    store::{
+        bm25::Bm25Index,
+        codec::{Reader, Writer, invalid},
+        hnsw::{HnswIndex, Vectors},
+    },
+};
+
+pub mod bm25;
+pub(crate) mod codec;
+pub mod filter;
+pub mod hnsw;
+pub mod hybrid;
+
+pub use bm25::Bm25Config;
+pub use filter::Filter;
+pub use hnsw::HnswConfig;
+pub use hybrid::{Fusion, HybridOptions};
+
+const MAGIC: [u8; 4] = *b"OLVS";
+const VERSION: u8 = 1;
+
+/// A document in a `VectorStore`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Document {
+    pub id: u64,
+    pub text: String,
+    pub metadata: Value,
+
+    /// Normalized embedding of `text`
+    pub embedding: Vec<f32>,
+}
+
+/// A result of `VectorStore::search`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SearchHit<'a> {
+    pub document: &'a Document,
+
+    /// Cosine similarity to the query, the BM25 score for `search_text`,
+    /// or the fused score for `hybrid_search`
+    pub score: f32,
+}
+
+/// In-memory semantic search over documents with JSON metadata.
+///
+/// Documents and queries are embedded with `Ollama::generate_embeddings` using `model`.
+/// Embeddings are stored normalized, so similarity is the cosine similarity.
+///
+/// Search is exact by default. With `hnsw`, an HNSW graph is maintained next to the
+/// documents for approximate search over large sets.
+///
+/// With `bm25`, a lexical index is maintained as well, for exact terms like identifiers
+/// that embeddings miss. `hybrid_search` fuses both rankings.
+#[derive(Debug, Clone)]
+pub struct VectorStore {
+    ollama: Ollama,
+    model: String,
+    dimensions: Option<i32>,
+    embed_options: ConcurrentEmbedOptions,
+    slots: Vec<Option<Document>>,
+    free: Vec<u32>,
+    positions: HashMap<u64, u32>,
+    index: Option<HnswIndex>,
+    lexical: Option<Bm25Index>,
+    next_id: u64,
+}
+
+impl Vectors for [Option<Document>] {
+    fn vector(&self, slot: u32) -> &[f32] {
+        self.get(slot as usize)
+            .and_then(Option::as_ref)
+            .map_or(&[], |d| d.embedding.as_slice())
+    }
+}
+
+impl VectorStore {
+    #[must_use]
+    pub fn new<S: Into<String>>(ollama: Ollama, model: S) -> Self {
+        Self {
+            ollama,
+            model: model.into(),
+            dimensions: None,
+            embed_options: ConcurrentEmbedOptions::default(),
+            slots: Vec::new(),
+            free: Vec::new(),
+            positions: HashMap::new(),
+            index: None,
+            lexical: None,
+            next_id: 0,
+        }
+    }
+
+    /// Requested embedding length, see `EmbedRequest::dimensions`.
+    #[must_use]
+    pub fn dimensions(mut self, dimensions: i32) -> Self {
+        self.dimensions = Some(dimensions);
+        self
+    }
+
+    /// Batch size, concurrency and retries used when adding documents.
+    #[must_use]
+    pub fn embed_options(mut self, options: ConcurrentEmbedOptions) -> Self {
+        self.embed_options = options;
+        self
+    }
+
+    /// Search with an HNSW index instead of comparing the query with every document.
+    /// Documents already in the store are indexed right away.
+    #[must_use]
+    pub fn hnsw(mut self, config: HnswConfig) -> Self {
+        let mut index = HnswIndex::new(config);
+        for (slot, document) in self.slots.iter().enumerate() {
+            if document.is_some() {
+                index.insert(self.slots.as_slice(), slot_u32(slot));
+            }
+        }
+
+        self.index = Some(index);
+        self
+    }
+
+    /// Maintain a BM25 index for `search_text` and `hybrid_search`.
+    /// Documents already in the store are indexed right away.
+    #[must_use]
+    pub fn bm25(mut self, config: Bm25Config) -> Self {
+        let mut lexical = Bm25Index::new(config);
+        for (slot, document) in self.slots.iter().enumerate() {
+            if let Some(document) = document {
+                lexical.insert(slot_u32(slot), &document.text);
+            }
+        }
+
+        self.lexical = Some(lexical);
+        self
+    }
+
+    /// The BM25 parameters, if the store has a lexical index.
+    #[must_use]
+    pub fn bm25_config(&self) -> Option<Bm25Config> {
+        self.lexical.as_ref().map(Bm25Index::config)
+    }
+
+    /// The HNSW parameters, if the store has an index.
+    #[must_use]
+    pub fn hnsw_config(&self) -> Option<HnswConfig> {
+        self.index.as_ref().map(HnswIndex::config)
+    }
+
+    /// Change the candidates considered per search, trading speed for recall.
+    /// Has no effect without an HNSW index.
+    pub fn set_ef_search(&mut self, ef: usize) {
+        if let Some(index) = &mut self.index {
+            index.set_ef_search(ef);
+        }
+    }
+
+    #[must_use]
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    #[must_use]
+    pub fn get(&self, id: u64) -> Option<&Document> {
+        let slot = *self.positions.get(&id)?;
+        self.slots.get(slot as usize)?.as_ref()
+    }
+
+    pub fn documents(&self) -> impl Iterator<Item = &Document> {
+        self.slots.iter().flatten()
+    }
+
+    fn request<I: Into<EmbedInput>>(&self, input: I) -> EmbedRequest {
+        let request = EmbedRequest::new(self.model.clone(), input);
+        match self.dimensions {
+            Some(dimensions) => request.dimensions(dimensions),
+            None => request,
+        }
+    }
+
+    /// Embed `query` with the store's model.
+    ///
+    /// # Errors
+    ///
+    /// Same as `Ollama::generate_embeddings`.
+    pub async fn embed_query(&self, query: &str) -> crate::Result<Vec<f32>> {
+        self.embed_query_detached(query).await
+    }
+
+    /// Like `embed_query`, but the future does not borrow the store,
+    /// so a lock on it can be released while Ollama embeds.
+    pub(crate) fn embed_query_detached(
+        &self,
+        query: &str,
+    ) -> impl Future<Output = crate::Result<Vec<f32>>> + use<> {
+        let ollama = self.ollama.clone();
+        let request = self.request(query);
+
+        async move {
+            let response = ollama.generate_embeddings(request).await?;
+            response
+                .embeddings
+                .into_iter()
+                .next()
+                .ok_or_else(|| OllamaError::Other("Ollama returned no embedding".to_string()))
+        }
+    }
+
+    /// Embed and add one document. Returns its id.
+    ///
+    /// # Errors
+    ///
+    /// Same as `add_documents`.
+    pub async fn add<S: Into<String>>(&mut self, text: S, metadata: Value) -> crate::Result<u64> {
+        let ids = self.add_documents(vec![(text.into(), metadata)]).await?;
+        Ok(ids[0])
+    }
+
+    /// Embed and add documents in batches of `embed_options.chunk_size`. Returns their ids.
+    /// Either all documents are added, or none.
+    ///
+    /// # Errors
+    ///
+    /// `OllamaError::EmbedChunkFailed` if a batch failed after all retries.
+    /// If an embedding has a different length than the ones already in the store.
+    pub async fn add_documents(
+        &mut self,
+        documents: Vec<(String, Value)>,
+    ) -> crate::Result<Vec<u64>> {
+        if documents.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let texts = documents
+            .iter()
+            .map(|(text, _)| text.clone())
+            .collect::<Vec<_>>();
+        let mut embeddings = vec![Vec::new(); texts.len()];
+
+        let mut chunks = self
+            .ollama
+            .generate_embeddings_concurrent(self.request(texts), self.embed_options.clone())?;
+
+        while let Some(chunk) = chunks.next().await {
+            let chunk = chunk?;
+            for (i, embedding) in chunk.response.embeddings.into_iter().enumerate() {
+                if let Some(slot) = embeddings.get_mut(chunk.offset + i) {
+                    *slot = embedding;
+                }
+            }
+        }
+
+        if embeddings.iter().any(Vec::is_empty) {
+            return Err(OllamaError::Other(
+                "Ollama returned fewer embeddings than inputs".to_string(),
+            ));
+        }
+
+        let expected = self.embedding_len().unwrap_or(embeddings[0].len());
+        for embedding in &embeddings {
+            check_len(expected, embedding)?;
+        }
+
+        Ok(documents
+            .into_iter()
+            .zip(embeddings)
+            .map(|((text, metadata), embedding)| self.push(text, metadata, &embedding))
+            .collect())
+    }
+
+    /// Add a document with an embedding computed elsewhere, e.g. by a cache.
+    /// The embedding has to come from the same model. Returns its id.
+    ///
+    /// # Errors
+    ///
+    /// If the embedding has a different length than the ones already in the store.
+    pub fn insert_embedded<S: Into<String>>(
+        &mut self,
+        text: S,
+        metadata: Value,
+        embedding: &[f32],
+    ) -> crate::Result<u64> {
+        if let Some(expected) = self.embedding_len() {
+            check_len(expected, embedding)?;
+        }
+
+        Ok(self.push(text.into(), metadata, embedding))
+    }
+
+    fn embedding_len(&self) -> Option<usize> {
+        self.documents().next().map(|d| d.embedding.len())
+    }
+
+    fn push(&mut self, text: String, metadata: Value, embedding: &[f32]) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let document = Document {
+            id,
+            text,
+            metadata,
+            embedding: vector::normalized(embedding),
+        };
+        let lexical_text = self.lexical.is_some().then(|| document.text.clone());
+
+        let slot = if let Some(slot) = self.free.pop() {
+            self.slots[slot as usize] = Some(document);
+            slot
+        } else {
+            self.slots.push(Some(document));
+            slot_u32(self.slots.len() - 1)
+        };
+
+        self.positions.insert(id, slot);
+        if let Some(index) = &mut self.index {
+            index.insert(self.slots.as_slice(), slot);
+        }
+        if let (Some(lexical), Some(text)) = (&mut self.lexical, lexical_text) {
+            lexical.insert(slot, &text);
+        }
+
+        id
+    }
+
+    /// Remove a document. Returns it if it was in the store.
+    pub fn remove(&mut self, id: u64) -> Option<Document> {
+        let slot = self.positions.remove(&id)?;
+        let document = self.slots.get_mut(slot as usize)?.take();
+
+        if let Some(index) = &mut self.index {
+            index.remove(self.slots.as_slice(), slot);
+        }
+        if let (Some(lexical), Some(document)) = (&mut self.lexical, &document) {
+            lexical.remove(slot, &document.text);
+        }
+        self.free.push(slot);
+
+        document
+    }
+
+    /// Remove all documents matching `filter`. Returns how many were removed.
+    pub fn remove_where(&mut self, filter: &Filter) -> usize {
+        let ids = self
+            .documents()
+            .filter(|d| filter.matches(&d.metadata))
+            .map(|d| d.id)
+            .collect::<Vec<_>>();
+
+        for id in &ids {
+            self.remove(*id);
+        }
+
+        ids.len()
+    }
+
+    /// The `k` documents most similar to `query`, best first.
+    ///
+    /// # Errors
+    ///
+    /// Same as `Ollama::generate_embeddings`.
+    pub async fn search(&self, query: &str, k: usize) -> crate::Result<Vec<SearchHit<'_>>> {
+        let embedding = self.embed_query(query).await?;
+        Ok(self.search_by_embedding(&embedding, k, None))
+    }
+
+    /// The `k` documents matching `filter` that are most similar to `query`, best first.
+    ///
+    /// # Errors
+    ///
+    /// Same as `Ollama::generate_embeddings`.
+    pub async fn search_with_filter(
+        &self,
+        query: &str,
+        k: usize,
+        filter: &Filter,
+    ) -> crate::Result<Vec<SearchHit<'_>>> {
+        let embedding = self.embed_query(query).await?;
+        Ok(self.search_by_embedding(&embedding, k, Some(filter)))
+    }
+
+    /// The `k` documents most similar to `embedding`, best first.
+    /// Only documents matching `filter` are considered.
+    ///
+    /// With an HNSW index, the result is approximate.
+    #[must_use]
+    pub fn search_by_embedding(
+        &self,
+        embedding: &[f32],
+        k: usize,
+        filter: Option<&Filter>,
+    ) -> Vec<SearchHit<'_>> {
+        let query = vector::normalized(embedding);
+        let accepts = |d: &Document| filter.is_none_or(|f| f.matches(&d.metadata));
+
+        if let Some(index) = &self.index {
+            let accepts_slot = |slot: u32| {
+                self.slots
+                    .get(slot as usize)
+                    .and_then(Option::as_ref)
+                    .is_some_and(accepts)
+            };
+
+            return index
+                .search(self.slots.as_slice(), &query, k, &accepts_slot)
+                .into_iter()
+                .filter_map(|(slot, score)| {
+                    let document = self.slots.get(slot as usize)?.as_ref()?;
+                    Some(SearchHit { document, score })
+                })
+                .collect();
+        }
+
+        let candidates = self.documents().filter(|d| accepts(d)).collect::<Vec<_>>();
+        let embeddings = candidates
+            .iter()
+            .map(|d| d.embedding.as_slice())
+            .collect::<Vec<_>>();
+
+        vector::top_k(&query, &embeddings, k, Similarity::Dot)
+            .into_iter()
+            .map(|neighbor| SearchHit {
+                document: candidates[neighbor.index],
+                score: neighbor.score,
+            })
+            .collect()
+    }
+
+    /// The `k` documents matching `filter` with the best BM25 score for `query`, best first.
+    ///
+    /// # Errors
+    ///
+    /// If the store has no BM25 index, see `bm25`.
+    pub fn search_text(
+        &self,
+        query: &str,
+        k: usize,
+        filter: Option<&Filter>,
+    ) -> crate::Result<Vec<SearchHit<'_>>> {
+        let lexical = self
+            .lexical
+            .as_ref()
+            .ok_or_else(|| OllamaError::Other("VectorStore has no BM25 index".to_string()))?;
+
+        let accepts_slot = |slot: u32| {
+            self.slots
+                .get(slot as usize)
+                .and_then(Option::as_ref)
+                .is_some_and(|d| filter.is_none_or(|f| f.matches(&d.metadata)))
+        };
+
+        Ok(lexical
+            .search(query, k, &accepts_slot)
+            .into_iter()
+            .filter_map(|(slot, score)| {
+                let document = self.slots.get(slot as usize)?.as_ref()?;
+                Some(SearchHit { document, score })
+            })
+            .collect())
+    }
+
+    /// Write the store, including the HNSW graph, to `path` in a compact binary format.
+    ///
+    /// # Errors
+    ///
+    /// If the file cannot be written.
+    pub async fn save<P: AsRef<Path>>(&self, path: P) -> crate::Result<()> {
+        tokio::fs::write(path, self.to_bytes()).await?;
+        Ok(())
+    }
+
+    /// Read a store written by `save`. Queries and new documents are embedded with `ollama`
+    /// using the model the store was created with.
+    ///
+    /// # Errors
+    ///
+    /// If the file cannot be read or is not a valid store file.
+    pub async fn load<P: AsRef<Path>>(ollama: Ollama, path: P) -> crate::Result<Self> {
+        let bytes = tokio::fs::read(path).await?;
+        Self::from_bytes(ollama, &bytes)
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer::new(MAGIC, VERSION);
+
+        writer.str(&self.model);
+        writer.u8(u8::from(self.dimensions.is_some()));
+        writer.u32(self.dimensions.unwrap_or_default().cast_unsigned());
+        writer.len(self.embedding_len().unwrap_or_default());
+        writer.u64(self.next_id);
+        writer.len(self.slots.len());
+
+        for slot in &self.slots {
+            let Some(document) = slot else {
+                writer.u8(0);
+                continue;
+            };
+
+            writer.u8(1);
+            writer.u64(document.id);
+            writer.str(&document.text);
+            writer.str(&document.metadata.to_string());
+            writer.f32s(&document.embedding);
+        }
+
+        match &self.index {
+            Some(index) => {
+                writer.u8(1);
+                index.write(&mut writer);
+            }
+            None => writer.u8(0),
+        }
+
+        // The lexical index is rebuilt from the texts on load
+        match &self.lexical {
+            Some(lexical) => {
+                let config = lexical.config();
+                writer.u8(1);
+                writer.f32s(&[config.k1, config.b]);
+            }
+            None => writer.u8(0),
+        }
+
+        writer.into_bytes()
+    }
+
+    fn from_bytes(ollama: Ollama, bytes: &[u8]) -> crate::Result<Self> {
+        let (mut reader, version) = Reader::new(bytes, MAGIC)?;
+        if version != VERSION {
+            return Err(invalid(&format!("unsupported version {version}")));
+        }
+
+        let model = reader.str()?.to_string();
+        let has_dimensions = reader.u8()? == 1;
+        let requested = reader.u32()?.cast_signed();
+        let dimensions = reader.len()?;
+        let next_id = reader.u64()?;
+        let count = reader.len()?;
+
+        let mut store = Self::new(ollama, model);
+        store.dimensions = has_dimensions.then_some(requested);
+        store.next_id = next_id;
+        store.slots.reserve(count.min(bytes.len()));
+
+        for slot in 0..count {
+            if reader.u8()? == 0 {
+                store.slots.push(None);
+                store.free.push(slot_u32(slot));
+                continue;
+            }
+
+            let id = reader.u64()?;
+            let text = reader.str()?.to_string();
+            let metadata = serde_json::from_str(reader.str()?)
+                .map_err(|e| invalid(&format!("bad metadata: {e}")))?;
+            let embedding = reader.f32s(dimensions)?;
+
+            // Either would let a later insert overwrite this document
+            if id >= next_id {
+                return Err(invalid(&format!(
+                    "document id {id} is not below the next id"
+                )));
+            }
+            if store.positions.insert(id, slot_u32(slot)).is_some() {
+                return Err(invalid(&format!("duplicate document id {id}")));
+            }
+
+            store.slots.push(Some(Document {
+                id,
+                text,
+                metadata,
+                embedding,
+            }));
+        }
+
+        if reader.u8()? == 1 {
+            let index = HnswIndex::read(&mut reader)?;
+            index.check(store.slots.len(), |slot| {
+                store.slots.get(slot as usize).is_some_and(Option::is_some)
+            })?;
+            store.index = Some(index);
+        }
+
+        if reader.u8()? == 1 {
+            let config = reader.f32s(2)?;
+            store = store.bm25(Bm25Config {
+                k1: config[0],
+                b: config[1],
+            });
+        }
+
+        if !reader.is_empty() {
+            return Err(invalid("trailing bytes"));
+        }
+
+        Ok(store)
+    }
+}
+
+/// Slots are `u32` to keep the HNSW graph small.
+fn slot_u32(slot: usize) -> u32 {
+    u32::try_from(slot).expect("VectorStore holds at most u32::MAX documents")
+}
+
+fn check_len(expected: usize, embedding: &[f32]) -> crate::Result<()> {
+    if expected == embedding.len() {
+        return Ok(());
+    }
+
+    Err(OllamaError::Other(format!(
+        "Embedding has {} dimensions, the store has {expected}",
+        embedding.len(),
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn store() -> VectorStore {
+        let mut store = VectorStore::new(Ollama::default(), "model")
+            .hnsw(HnswConfig::default().seed(7))
+            .bm25(Bm25Config::default());
+        for (text, embedding) in [
+            ("red apple", [1.0, 0.0, 0.0]),
+            ("green pear", [0.0, 1.0, 0.0]),
+            ("blue sky", [0.0, 0.0, 1.0]),
+        ] {
+            store
+                .insert_embedded(text, json!({ "text": text }), &embedding)
+                .unwrap();
+        }
+        store
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut store = store();
+        let removed = store.documents().next().unwrap().id;
+        store.remove(removed);
+
+        let loaded = VectorStore::from_bytes(Ollama::default(), &store.to_bytes()).unwrap();
+        assert_eq!(loaded.model(), "model");
+        assert_eq!(loaded.len(), 2);
+        assert!(loaded.get(removed).is_none());
+        assert_eq!(loaded.hnsw_config(), store.hnsw_config());
+        assert_eq!(loaded.bm25_config(), store.bm25_config());
+
+        let hits = loaded.search_by_embedding(&[0.0, 0.0, 1.0], 1, None);
+        assert_eq!(hits[0].document.text, "blue sky");
+        let hits = loaded.search_text("pear", 1, None).unwrap();
+        assert_eq!(hits[0].document.text, "green pear");
+
+        // The free slot is reused and ids are not
+        let mut loaded = loaded;
+        let id = loaded.insert_embedded("grey cloud", json!({}), &[0.0, 1.0, 1.0]);
+        assert_eq!(id.unwrap(), store.next_id);
+        assert_eq!(loaded.slots.len(), 3);
+    }
+
+    #[test]
+    fn duplicate_ids_are_rejected() {
+        let mut store = store();
+        let id = store.slots[0].as_ref().unwrap().id;
+        store.slots[1].as_mut().unwrap().id = id;
+
+        let error = VectorStore::from_bytes(Ollama::default(), &store.to_bytes()).unwrap_err();
+        assert!(error.to_string().contains("duplicate document id"));
+    }
+
+    #[test]
+    fn reused_next_id_is_rejected() {
+        let mut store = store();
+        store.next_id -= 1;
+
+        let error = VectorStore::from_bytes(Ollama::default(), &store.to_bytes()).unwrap_err();
+        assert!(error.to_string().contains("is not below the next id"));
+    }
+
+    #[test]
+    fn other_versions_are_rejected() {
+        let mut bytes = store().to_bytes();
+        bytes[MAGIC.len()] = VERSION + 1;
+
+        let error = VectorStore::from_bytes(Ollama::default(), &bytes).unwrap_err();
+        assert!(error.to_string().contains("unsupported version"));
+    }
+}